@@ -1,4 +1,6 @@
 pub mod builder_visitor;
+pub mod optimizer;
+pub mod serialize;
 pub mod tree;
 pub mod visualize;
 
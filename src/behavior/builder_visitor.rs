@@ -5,7 +5,7 @@ use crate::parser::types as parser_types;
 use parser_types::{
     BinOp, DataType, Expr, Fn, Script, Statement, UnOp, Value, Whamm, WhammVisitor,
 };
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use crate::behavior::tree::DecoratorType::{HasAltCall, PredIs};
 use crate::behavior::tree::ParamActionType;
@@ -156,8 +156,10 @@ impl<'b> BehaviorTreeBuilder<'_, 'b, '_> {
         if !globals.is_empty() {
             self.tree.sequence(self.err);
 
-            // visit globals
-            for (_name, ProvidedGlobal { global, .. }) in globals.iter() {
+            // visit globals, in the order of their names
+            let mut globals: Vec<(&String, &ProvidedGlobal)> = globals.iter().collect();
+            globals.sort_by_key(|(name, _)| *name);
+            for (_name, ProvidedGlobal { global, .. }) in globals {
                 if global.is_comp_provided {
                     if let Expr::VarId { name, .. } = &global.var_name {
                         self.tree
@@ -176,10 +178,12 @@ impl<'b> BehaviorTreeBuilder<'_, 'b, '_> {
 
     fn visit_bytecode_package(&mut self, package: &'b dyn Package) {
         if package.has_events() {
-            // Build events->globals HashMap
-            let mut events = HashMap::new();
+            // Build events->globals map
+            let mut events = BTreeMap::new();
             for event in package.events() {
-                let globals: Vec<String> = event.get_provided_globals().keys().cloned().collect();
+                let mut globals: Vec<String> =
+                    event.get_provided_globals().keys().cloned().collect();
+                globals.sort();
                 events.insert(event.name(), globals);
             }
 
@@ -191,9 +195,9 @@ impl<'b> BehaviorTreeBuilder<'_, 'b, '_> {
                 },
                 self.err,
             );
-            if let Some(event) = package.events().next() {
-                // just grab the first one and emit behavior (the decorator above is what
-                // makes this apply to all events)
+            if let Some(event) = package.events().min_by_key(|event| event.name()) {
+                // just grab the first one (by name, so the tree is the same for every run) and
+                // emit behavior (the decorator above is what makes this apply to all events)
                 self.visit_event(event);
             }
             self.tree.exit_action_with_child(self.err);
@@ -385,9 +389,10 @@ impl<'b> WhammVisitor<'b, ()> for BehaviorTreeBuilder<'_, 'b, '_> {
         self.ast.global_stmts = script.global_stmts.to_owned();
         self.tree.emit_global_stmts(self.err);
 
-        script
-            .providers
-            .iter()
+        let mut providers: Vec<_> = script.providers.iter().collect();
+        providers.sort_by_key(|(name, _)| *name);
+        providers
+            .into_iter()
             .for_each(|(_name, provider)| self.visit_provider(provider));
 
         self.tree.exit_scope(self.err);
@@ -408,8 +413,10 @@ impl<'b> WhammVisitor<'b, ()> for BehaviorTreeBuilder<'_, 'b, '_> {
         // visit globals
         self.visit_provided_globals(&provider.get_provided_globals());
 
-        provider
-            .packages()
+        let mut packages: Vec<&dyn Package> = provider.packages().collect();
+        packages.sort_by_key(|package| package.name());
+        packages
+            .into_iter()
             .for_each(|package| self.visit_package(package));
 
         self.tree.exit_scope(self.err);
@@ -488,11 +495,14 @@ impl<'b> WhammVisitor<'b, ()> for BehaviorTreeBuilder<'_, 'b, '_> {
         self.context_name += &format!(":{}", probe.mode_name());
         self.add_probe_to_ast(probe.as_ref());
 
+        let mut global_names: Vec<String> =
+            probe.get_mode_provided_globals().keys().cloned().collect();
+        global_names.sort();
         self.tree.action_with_child(
             ActionWithChildType::EnterProbe {
                 context: self.context_name.clone(),
                 probe_mode: probe.mode_name(),
-                global_names: probe.get_mode_provided_globals().keys().cloned().collect(),
+                global_names,
             },
             self.err,
         );
@@ -0,0 +1,413 @@
+use crate::behavior::builder_visitor::SimpleAST;
+use crate::behavior::tree::{ActionType, ActionWithChildType, BehaviorTree, DecoratorType, Node};
+use crate::behavior::tree::{ArgActionType, ParamActionType};
+use crate::generator::const_prop::fold_with;
+use crate::generator::emitters::StmtVars;
+use crate::parser::rules::Probe;
+use crate::parser::types::{Script, Value, Whamm};
+use std::collections::{HashMap, HashSet};
+
+/// Whether `probe` can never emit anything: its predicate is always `false`, or its body is empty
+/// (unless it replaces the instruction, an empty `alt` body removes it)
+fn is_dead(probe: &dyn Probe, consts: &HashMap<String, Value>) -> bool {
+    let pred_is_false = probe.predicate().as_ref().is_some_and(|pred| {
+        matches!(
+            fold_with(pred, consts, &HashSet::new()),
+            Some(Value::Boolean { val: false, .. })
+        )
+    });
+    let body_is_empty = probe.body().as_ref().map_or(true, Vec::is_empty);
+    pred_is_false || (probe.mode_name() != "alt" && body_is_empty)
+}
+
+/// Whether `probe` uses the arguments of the instruction (`argN`)
+fn uses_args(probe: &dyn Probe) -> bool {
+    let mut vars = StmtVars::default();
+    if let Some(pred) = probe.predicate() {
+        vars.add_expr(pred);
+    }
+    if let Some(body) = probe.body() {
+        vars.add_stmts(body);
+    }
+    !vars.args().is_empty()
+}
+
+/// The address of a probe, to tell the probes of the scripts apart in `SimpleAST`
+fn probe_addr(probe: &dyn Probe) -> *const () {
+    probe as *const dyn Probe as *const ()
+}
+
+/// The probes of `script` that can never emit anything, by their address. A global only holds
+/// the same constant in the script it's declared in, so each script is folded with its own.
+fn dead_probes(script: &Script, dead: &mut HashSet<*const ()>) {
+    let consts: HashMap<String, Value> = script
+        .globals
+        .iter()
+        .filter(|(_, global)| !global.is_comp_provided)
+        .filter_map(|(name, global)| Some((name.clone(), global.value.clone()?)))
+        .collect();
+    let probes = script
+        .providers
+        .values()
+        .flat_map(|provider| provider.packages())
+        .flat_map(|package| package.events())
+        .flat_map(|event| event.probes().values())
+        .flatten();
+    for probe in probes {
+        if is_dead(probe.as_ref(), &consts) {
+            dead.insert(probe_addr(probe.as_ref()));
+        }
+    }
+}
+
+/// Drops the probes that can never emit anything (see `dead_probes`) from `ast`, the modes of the
+/// events are kept (possibly without any probe left) since the behavior tree is shared by all
+/// the events
+fn prune_probes(ast: &mut SimpleAST, dead: &HashSet<*const ()>) {
+    let modes = ast
+        .probes
+        .values_mut()
+        .flat_map(|packages| packages.values_mut())
+        .flat_map(|events| events.values_mut())
+        .flat_map(|modes| modes.iter_mut());
+    for (mode, probes) in modes {
+        if mode == "alt" {
+            // only the first `alt` probe is emitted, the others can't take its place
+            if probes
+                .first()
+                .is_some_and(|probe| dead.contains(&probe_addr(**probe)))
+            {
+                probes.clear();
+            }
+        } else {
+            probes.retain(|probe| !dead.contains(&probe_addr(**probe)));
+        }
+    }
+}
+
+fn set_parent(node: &mut Node, new_parent: usize) {
+    match node {
+        Node::Root { .. } => {}
+        Node::Sequence { parent, .. }
+        | Node::Decorator { parent, .. }
+        | Node::Fallback { parent, .. }
+        | Node::ArgAction { parent, .. }
+        | Node::ActionWithChild { parent, .. }
+        | Node::ActionWithParams { parent, .. }
+        | Node::Action { parent, .. } => *parent = new_parent,
+    }
+}
+
+/// The node at `idx` and the nodes under it, in the order they are visited
+fn descendants(tree: &BehaviorTree, idx: usize) -> Vec<usize> {
+    let mut found = vec![];
+    let mut stack = vec![idx];
+    while let Some(idx) = stack.pop() {
+        found.push(idx);
+        stack.extend(tree.nodes[idx].children().into_iter().rev());
+    }
+    found
+}
+
+/// Puts the node at `new` in the place of the child `old` of the node at `parent`
+fn replace_child(tree: &mut BehaviorTree, parent: usize, old: usize, new: usize) {
+    let replace = |idx: &mut usize| {
+        if *idx == old {
+            *idx = new;
+        }
+    };
+    match &mut tree.nodes[parent] {
+        Node::Root { child, .. }
+        | Node::Decorator { child, .. }
+        | Node::ActionWithChild { child, .. } => replace(child),
+        Node::Sequence { children, .. } | Node::Fallback { children, .. } => {
+            children.iter_mut().for_each(replace)
+        }
+        Node::ActionWithParams { children, ty, .. } => {
+            children.iter_mut().for_each(replace);
+            match ty {
+                ParamActionType::EmitIf { cond, conseq } => {
+                    [cond, conseq].into_iter().for_each(replace)
+                }
+                ParamActionType::EmitIfElse { cond, conseq, alt } => {
+                    [cond, conseq, alt].into_iter().for_each(replace)
+                }
+            }
+        }
+        Node::ArgAction { .. } | Node::Action { .. } => {}
+    }
+    set_parent(&mut tree.nodes[new], parent);
+}
+
+/// Takes the node at `idx` out of the tree, along with its parent if it can't do without it
+fn detach(tree: &mut BehaviorTree, idx: usize) {
+    let Some(parent) = tree.nodes[idx].parent() else {
+        return;
+    };
+    match &mut tree.nodes[parent] {
+        Node::Sequence { children, .. } | Node::Fallback { children, .. } => {
+            children.retain(|child| *child != idx)
+        }
+        Node::Root { .. } => {
+            // an empty sequence does nothing
+            let id = tree.nodes.len();
+            tree.nodes.push(Node::Sequence {
+                id,
+                parent,
+                children: vec![],
+            });
+            replace_child(tree, parent, idx, id);
+        }
+        _ => detach(tree, parent),
+    }
+}
+
+/// Skips the events of the package at `idx` without any probe left, and the modes without any
+/// probe left in the other events. What is left of each mode only keeps the behavior of that
+/// mode, and doesn't save the arguments of the instruction if none of its probes use them
+/// (`argN`), unless it replaces the instruction (`alt`).
+fn prune_package(tree: &mut BehaviorTree, ast: &SimpleAST, idx: usize) {
+    let Node::ActionWithChild {
+        ty: ActionWithChildType::EnterPackage {
+            context, events, ..
+        },
+        ..
+    } = &mut tree.nodes[idx]
+    else {
+        return;
+    };
+    // `whamm:script<N>:<provider>:<package>`
+    let mut names = context.split(':').skip(2);
+    let Some(event_probes) = names
+        .next()
+        .and_then(|provider| ast.probes.get(provider))
+        .and_then(|packages| packages.get(names.next()?))
+    else {
+        return;
+    };
+    events.retain(|event, _| {
+        event_probes
+            .get(event)
+            .is_some_and(|modes| modes.values().any(|probes| !probes.is_empty()))
+    });
+    if events.is_empty() {
+        detach(tree, idx);
+        return;
+    }
+    let live_events: Vec<String> = events.keys().cloned().collect();
+
+    let probe_nodes: Vec<(usize, String)> = descendants(tree, idx)
+        .into_iter()
+        .filter_map(|idx| match &tree.nodes[idx] {
+            Node::ActionWithChild {
+                ty: ActionWithChildType::EnterProbe { probe_mode, .. },
+                ..
+            } => Some((idx, probe_mode.clone())),
+            _ => None,
+        })
+        .collect();
+    for (probe_idx, mode) in probe_nodes {
+        let probes: Vec<&dyn Probe> = live_events
+            .iter()
+            .filter_map(|event| event_probes.get(event)?.get(&mode))
+            .flatten()
+            .map(|probe| **probe)
+            .collect();
+        if probes.is_empty() {
+            detach(tree, probe_idx);
+            continue;
+        }
+        let keeps_args = mode == "alt" || probes.iter().any(|probe| uses_args(*probe));
+        for node_idx in descendants(tree, probe_idx) {
+            match &tree.nodes[node_idx] {
+                Node::Decorator {
+                    ty: DecoratorType::IsProbeMode { probe_mode },
+                    parent,
+                    child,
+                    ..
+                } => {
+                    // the mode doesn't change under the probe
+                    if *probe_mode == mode {
+                        let (parent, child) = (*parent, *child);
+                        replace_child(tree, parent, node_idx, child);
+                    } else {
+                        detach(tree, node_idx);
+                    }
+                }
+                Node::ArgAction {
+                    ty: ArgActionType::SaveParams | ArgActionType::EmitParams,
+                    ..
+                } if !keeps_args => detach(tree, node_idx),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Merges the sequences (fallbacks) nested in sequences (fallbacks) under the node at `idx`,
+/// drops what can't change their outcome and replaces those of a single node by the node
+fn merge(tree: &mut BehaviorTree, idx: usize) {
+    for child in tree.nodes[idx].children() {
+        merge(tree, child);
+    }
+    let (is_sequence, children) = match &tree.nodes[idx] {
+        Node::Sequence { children, .. } => (true, children.clone()),
+        Node::Fallback { children, .. } => (false, children.clone()),
+        _ => return,
+    };
+    let mut flattened = vec![];
+    for child in children {
+        match &tree.nodes[child] {
+            Node::Sequence {
+                children: nested, ..
+            } if is_sequence => flattened.extend(nested.iter().copied()),
+            Node::Fallback {
+                children: nested, ..
+            } if !is_sequence => flattened.extend(nested.iter().copied()),
+            _ => flattened.push(child),
+        }
+    }
+    let mut merged = vec![];
+    for child in flattened {
+        if let Node::Action {
+            ty: ActionType::ForceSuccess,
+            ..
+        } = &tree.nodes[child]
+        {
+            // a fallback never gets past it
+            if !is_sequence {
+                merged.push(child);
+                break;
+            }
+        } else {
+            merged.push(child);
+        }
+    }
+    for child in merged.iter() {
+        set_parent(&mut tree.nodes[*child], idx);
+    }
+    if let [only] = merged[..] {
+        if let Some(parent) = tree.nodes[idx].parent() {
+            replace_child(tree, parent, idx, only);
+            return;
+        }
+    }
+    if let Node::Sequence { children, .. } | Node::Fallback { children, .. } = &mut tree.nodes[idx]
+    {
+        *children = merged;
+    }
+}
+
+/// Drops the nodes taken out of the tree, the others are numbered in the order they are visited
+fn compact(tree: &mut BehaviorTree) {
+    let kept = descendants(tree, 0);
+    let ids: HashMap<usize, usize> = kept
+        .iter()
+        .enumerate()
+        .map(|(new, old)| (*old, new))
+        .collect();
+    let mut nodes: Vec<Option<Node>> = std::mem::take(&mut tree.nodes)
+        .into_iter()
+        .map(Some)
+        .collect();
+    for old in kept {
+        let Some(mut node) = nodes[old].take() else {
+            continue;
+        };
+        match &mut node {
+            Node::Root { id, child } => {
+                *id = ids[&*id];
+                *child = ids.get(child).copied().unwrap_or(*id);
+            }
+            Node::Decorator {
+                id, parent, child, ..
+            }
+            | Node::ActionWithChild {
+                id, parent, child, ..
+            } => {
+                *id = ids[&*id];
+                *parent = ids[&*parent];
+                *child = ids[&*child];
+            }
+            Node::Sequence {
+                id,
+                parent,
+                children,
+            }
+            | Node::Fallback {
+                id,
+                parent,
+                children,
+            } => {
+                *id = ids[&*id];
+                *parent = ids[&*parent];
+                children.iter_mut().for_each(|child| *child = ids[&*child]);
+            }
+            Node::ActionWithParams {
+                id,
+                parent,
+                ty,
+                children,
+            } => {
+                *id = ids[&*id];
+                *parent = ids[&*parent];
+                children.iter_mut().for_each(|child| *child = ids[&*child]);
+                match ty {
+                    ParamActionType::EmitIf { cond, conseq } => {
+                        [cond, conseq].into_iter().for_each(|idx| *idx = ids[&*idx])
+                    }
+                    ParamActionType::EmitIfElse { cond, conseq, alt } => [cond, conseq, alt]
+                        .into_iter()
+                        .for_each(|idx| *idx = ids[&*idx]),
+                }
+            }
+            Node::ArgAction { id, parent, .. } | Node::Action { id, parent, .. } => {
+                *id = ids[&*id];
+                *parent = ids[&*parent];
+            }
+        }
+        tree.nodes.push(node);
+    }
+    tree.reset();
+}
+
+/// Optimizes the behavior tree built for `whamm`: the probes that can never emit anything are
+/// dropped from `ast` (see `is_dead`, with the globals propagated by `propagate_consts`), the
+/// events and modes without any probe left are taken out of the tree, the modes don't save the
+/// arguments of the instruction unless they use them, and the control nodes are merged (see
+/// `merge`). Returns the number of nodes removed.
+///
+/// Identical sequences are not merged: a node has a single parent, so the tree can't share a
+/// subtree between two places, and the behaviors of the modes (the only sequences built the same
+/// way) are already reduced to the one of their mode.
+pub fn optimize_behavior_tree(
+    tree: &mut BehaviorTree,
+    ast: &mut SimpleAST,
+    whamm: &Whamm,
+) -> usize {
+    let num_nodes = descendants(tree, 0).len();
+    let mut dead = HashSet::new();
+    for script in whamm.scripts.iter() {
+        dead_probes(script, &mut dead);
+    }
+    prune_probes(ast, &dead);
+
+    let packages: Vec<usize> = descendants(tree, 0)
+        .into_iter()
+        .filter(|idx| {
+            matches!(
+                tree.nodes[*idx],
+                Node::ActionWithChild {
+                    ty: ActionWithChildType::EnterPackage { .. },
+                    ..
+                }
+            )
+        })
+        .collect();
+    for idx in packages {
+        prune_package(tree, ast, idx);
+    }
+    merge(tree, 0);
+    compact(tree);
+    num_nodes.saturating_sub(tree.nodes.len())
+}
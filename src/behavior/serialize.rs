@@ -0,0 +1,169 @@
+use crate::behavior::builder_visitor::SimpleAST;
+use crate::behavior::tree::{BehaviorTree, Node};
+use crate::parser::rules::Probe;
+use crate::parser::types::Whamm;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// `provider` -> `package` -> `event` -> `mode` -> the probes, by their position among the
+/// probes of that mode in the scripts (see `script_probes`)
+type ProbeRefs = BTreeMap<String, BTreeMap<String, BTreeMap<String, BTreeMap<String, Vec<usize>>>>>;
+
+/// The version of the format written by `behavior_to_json`, bumped whenever the nodes change
+/// in a way older trees can't be read as
+pub const BEHAVIOR_FORMAT_VERSION: u32 = 1;
+
+/// A behavior tree and the probes it emits
+#[derive(Serialize, Deserialize)]
+struct Behavior<T> {
+    version: u32,
+    tree: T,
+    probes: ProbeRefs,
+}
+
+/// The probes of `mode` of an event, in the order `build_behavior_tree` collects them
+fn script_probes<'a>(
+    whamm: &'a Whamm,
+    provider: &str,
+    package: &str,
+    event: &str,
+    mode: &str,
+) -> Vec<&'a dyn Probe> {
+    whamm
+        .scripts
+        .iter()
+        .filter_map(|script| script.providers.get(provider))
+        .flat_map(|provider| provider.packages())
+        .filter(|script_package| script_package.name() == package)
+        .flat_map(|script_package| script_package.events())
+        .filter(|script_event| script_event.name() == event)
+        .filter_map(|script_event| script_event.probes().get(mode))
+        .flatten()
+        .map(|probe| probe.as_ref())
+        .collect()
+}
+
+/// Serializes `tree` to JSON, along with the probes of `ast` (built from `whamm`) by their
+/// position in the scripts. The nodes and the maps are always in the same order, so that the
+/// JSON of the same script only changes with its behavior.
+pub fn behavior_to_json(tree: &BehaviorTree, ast: &SimpleAST, whamm: &Whamm) -> String {
+    let mut probes = ProbeRefs::new();
+    for (provider, packages) in ast.probes.iter() {
+        for (package, events) in packages.iter() {
+            for (event, modes) in events.iter() {
+                for (mode, mode_probes) in modes.iter() {
+                    let all = script_probes(whamm, provider, package, event, mode);
+                    let idxs = mode_probes
+                        .iter()
+                        .filter_map(|probe| {
+                            all.iter().position(|other| {
+                                std::ptr::addr_eq(
+                                    **probe as *const dyn Probe,
+                                    *other as *const dyn Probe,
+                                )
+                            })
+                        })
+                        .collect();
+                    probes
+                        .entry(provider.clone())
+                        .or_default()
+                        .entry(package.clone())
+                        .or_default()
+                        .entry(event.clone())
+                        .or_default()
+                        .insert(mode.clone(), idxs);
+                }
+            }
+        }
+    }
+    // only fails for maps with keys that aren't strings
+    serde_json::to_string_pretty(&Behavior {
+        version: BEHAVIOR_FORMAT_VERSION,
+        tree,
+        probes,
+    })
+    .unwrap()
+}
+
+/// Makes sure the nodes of `tree` refer to each other by their position
+fn check_nodes(tree: &BehaviorTree) -> Result<(), String> {
+    if !matches!(tree.get_root(), Some(Node::Root { .. })) {
+        return Err("The behavior tree doesn't start with its root".to_string());
+    }
+    for (idx, node) in tree.nodes.iter().enumerate() {
+        if node.id() != idx {
+            return Err(format!(
+                "The node at {idx} of the behavior tree has the id {}",
+                node.id()
+            ));
+        }
+        let mut refs = node.children().into_iter().chain(node.parent());
+        if let Some(other) = refs.find(|other| *other >= tree.nodes.len()) {
+            return Err(format!(
+                "The node {idx} of the behavior tree refers to the node {other}, which doesn't exist"
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Loads a behavior tree serialized by `behavior_to_json` back, along with the `SimpleAST` of its
+/// probes. `whamm` must be the AST of the same script.
+pub fn behavior_from_json<'a>(
+    json: &str,
+    whamm: &'a Whamm,
+) -> Result<(BehaviorTree, SimpleAST<'a>), String> {
+    // check the version first, the rest may not be readable otherwise
+    #[derive(Deserialize)]
+    struct Version {
+        version: u32,
+    }
+    let Version { version } =
+        serde_json::from_str(json).map_err(|e| format!("Could not read the behavior tree: {e}"))?;
+    if version != BEHAVIOR_FORMAT_VERSION {
+        return Err(format!(
+            "The behavior tree is in version {version} of the format, \
+            this version of whamm only reads version {BEHAVIOR_FORMAT_VERSION}"
+        ));
+    }
+
+    let Behavior::<BehaviorTree> {
+        mut tree, probes, ..
+    } = serde_json::from_str(json).map_err(|e| format!("Could not read the behavior tree: {e}"))?;
+    check_nodes(&tree)?;
+    tree.reset();
+
+    let mut ast = SimpleAST::new();
+    // as when building the tree, the global statements of the last script
+    if let Some(script) = whamm.scripts.last() {
+        ast.global_stmts = script.global_stmts.to_owned();
+    }
+    for (provider, packages) in probes.into_iter() {
+        for (package, events) in packages.into_iter() {
+            for (event, modes) in events.into_iter() {
+                for (mode, idxs) in modes.into_iter() {
+                    let all = script_probes(whamm, &provider, &package, &event, &mode);
+                    let mut mode_probes = vec![];
+                    for idx in idxs {
+                        let Some(probe) = all.get(idx) else {
+                            return Err(format!(
+                                "The behavior tree refers to the probe {idx} of \
+                                `{provider}:{package}:{event}:{mode}`, which the script doesn't have"
+                            ));
+                        };
+                        mode_probes.push(Box::new(*probe));
+                    }
+                    ast.probes
+                        .entry(provider.clone())
+                        .or_default()
+                        .entry(package.clone())
+                        .or_default()
+                        .entry(event.clone())
+                        .or_default()
+                        .insert(mode, mode_probes);
+                }
+            }
+        }
+    }
+    Ok((tree, ast))
+}
@@ -1 +1,172 @@
-// TODO
+use crate::behavior::builder_visitor::{build_behavior_tree, SimpleAST};
+use crate::behavior::optimizer::optimize_behavior_tree;
+use crate::behavior::serialize::{behavior_from_json, behavior_to_json, BEHAVIOR_FORMAT_VERSION};
+use crate::behavior::tree::{ActionWithChildType, ArgActionType, DecoratorType, Node};
+use crate::common::error::ErrorGen;
+use crate::generator::const_prop::propagate_consts;
+use crate::parser::tests;
+use crate::parser::types::Whamm;
+use crate::verifier::verifier;
+
+fn get_whamm(script: &str, err: &mut ErrorGen) -> Whamm {
+    let mut whamm = tests::get_ast(script, err).unwrap();
+    let mut table = verifier::build_symbol_table(&mut whamm, err);
+    propagate_consts(&mut whamm, &mut table);
+    whamm
+}
+
+/// The probe modes left in the optimized tree of `script`, and whether it still saves the
+/// arguments of the instructions
+fn optimized_modes(script: &str) -> (Vec<String>, bool) {
+    optimized_modes_of_scripts(&[script])
+}
+
+/// Like `optimized_modes`, for the tree built from all of `scripts`
+fn optimized_modes_of_scripts(scripts: &[&str]) -> (Vec<String>, bool) {
+    let mut err = ErrorGen::new("".to_string(), "".to_string(), 0);
+    let mut whamm = tests::get_ast(scripts[0], &mut err).unwrap();
+    for script in scripts[1..].iter() {
+        let mut other = tests::get_ast(script, &mut err).unwrap();
+        whamm.add_script(other.scripts.remove(0));
+    }
+    let mut table = verifier::build_symbol_table(&mut whamm, &mut err);
+    propagate_consts(&mut whamm, &mut table);
+
+    let mut ast = SimpleAST::new();
+    let mut tree = build_behavior_tree(&whamm, &mut ast, &mut err);
+    let num_nodes = tree.nodes.len();
+    let removed = optimize_behavior_tree(&mut tree, &mut ast, &whamm);
+    assert_eq!(num_nodes - removed, tree.nodes.len());
+    assert!(!err.has_errors);
+
+    let mut modes = vec![];
+    let mut saves_args = false;
+    for node in tree.nodes.iter() {
+        match node {
+            Node::ActionWithChild {
+                ty: ActionWithChildType::EnterProbe { probe_mode, .. },
+                ..
+            } => modes.push(probe_mode.clone()),
+            Node::ArgAction {
+                ty: ArgActionType::SaveParams,
+                ..
+            } => saves_args = true,
+            // only the behavior of the probe's own mode is left
+            Node::Decorator {
+                ty: DecoratorType::IsProbeMode { .. },
+                ..
+            } => panic!("the probe mode is known under the probe"),
+            _ => {}
+        }
+    }
+    (modes, saves_args)
+}
+
+#[test]
+pub fn optimize_behavior_tree_prunes_dead_probes() {
+    // `DEBUG` is always 0 and the `after` body is empty
+    let (modes, _) = optimized_modes(
+        r#"
+i32 DEBUG = 0;
+wasm:bytecode:call:before / DEBUG == 1 / {
+    DEBUG = 0;
+}
+wasm:bytecode:call:after {}
+    "#,
+    );
+    assert!(modes.is_empty());
+
+    let (modes, saves_args) = optimized_modes(
+        r#"
+i32 count;
+wasm:bytecode:call:before {
+    count = count + 1;
+}
+wasm:bytecode:call:after {}
+    "#,
+    );
+    assert_eq!(vec!["before"], modes);
+    assert!(!saves_args);
+}
+
+#[test]
+pub fn optimize_behavior_tree_folds_globals_per_script() {
+    // `DEBUG` only always holds 0 in the first script
+    let (modes, _) = optimized_modes_of_scripts(&[
+        r#"
+i32 DEBUG = 0;
+wasm:bytecode:call:before / DEBUG == 1 / {
+    DEBUG = 0;
+}
+        "#,
+        r#"
+i32 DEBUG;
+wasm:bytecode:call:after / DEBUG == 1 / {
+    DEBUG = 2;
+}
+        "#,
+    ]);
+    assert_eq!(vec!["after"], modes);
+}
+
+#[test]
+pub fn optimize_behavior_tree_keeps_used_args() {
+    let (modes, saves_args) = optimized_modes(
+        r#"
+i32 count;
+wasm:bytecode:call:before / arg0 == 1 / {
+    count = count + 1;
+}
+    "#,
+    );
+    assert_eq!(vec!["before"], modes);
+    assert!(saves_args);
+
+    // the original instruction needs them
+    let (modes, saves_args) = optimized_modes(
+        r#"
+wasm:bytecode:call:alt {}
+    "#,
+    );
+    assert_eq!(vec!["alt"], modes);
+    assert!(saves_args);
+}
+
+#[test]
+pub fn behavior_tree_json_round_trip() {
+    let mut err = ErrorGen::new("".to_string(), "".to_string(), 0);
+    let whamm = get_whamm(
+        r#"
+i32 count;
+wasm:bytecode:call:before {}
+wasm:bytecode:call:after {
+    count = count + arg0;
+}
+    "#,
+        &mut err,
+    );
+    let mut ast = SimpleAST::new();
+    let mut tree = build_behavior_tree(&whamm, &mut ast, &mut err);
+    optimize_behavior_tree(&mut tree, &mut ast, &whamm);
+    let json = behavior_to_json(&tree, &ast, &whamm);
+
+    // the `before` probe was dropped
+    let (loaded_tree, loaded_ast) = behavior_from_json(&json, &whamm).unwrap();
+    let modes = &loaded_ast.probes["wasm"]["bytecode"]["call"];
+    assert!(modes["before"].is_empty());
+    assert_eq!(1, modes["after"].len());
+    assert_eq!(json, behavior_to_json(&loaded_tree, &loaded_ast, &whamm));
+
+    // only the current version of the format is read
+    let other_version = json.replacen(
+        &format!("\"version\": {BEHAVIOR_FORMAT_VERSION},"),
+        &format!("\"version\": {},", BEHAVIOR_FORMAT_VERSION + 1),
+        1,
+    );
+    assert_ne!(json, other_version);
+    assert!(behavior_from_json(&other_version, &whamm).is_err());
+
+    // the nodes must refer to each other by their position
+    let json = json.replacen("\"id\": 1,", "\"id\": 2,", 1);
+    assert!(behavior_from_json(&json, &whamm).is_err());
+}
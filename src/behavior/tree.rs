@@ -1,12 +1,14 @@
 use crate::common::error::ErrorGen;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 const UNEXPECTED_ERR_MSG: &str =
     "BehaviorTree: Looks like you've found a bug...please report this behavior!";
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct BehaviorTree {
     pub nodes: Vec<Node>,
+    #[serde(skip)]
     pub curr: usize, // indexes into this::nodes
 }
 impl Default for BehaviorTree {
@@ -446,7 +448,8 @@ impl BehaviorTree {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
 pub enum Node {
     Root {
         id: usize,
@@ -494,14 +497,56 @@ pub enum Node {
     },
 }
 
-#[derive(Debug)]
+impl Node {
+    pub fn id(&self) -> usize {
+        match self {
+            Node::Root { id, .. }
+            | Node::Sequence { id, .. }
+            | Node::Decorator { id, .. }
+            | Node::Fallback { id, .. }
+            | Node::ArgAction { id, .. }
+            | Node::ActionWithChild { id, .. }
+            | Node::ActionWithParams { id, .. }
+            | Node::Action { id, .. } => *id,
+        }
+    }
+
+    pub fn parent(&self) -> Option<usize> {
+        match self {
+            Node::Root { .. } => None,
+            Node::Sequence { parent, .. }
+            | Node::Decorator { parent, .. }
+            | Node::Fallback { parent, .. }
+            | Node::ArgAction { parent, .. }
+            | Node::ActionWithChild { parent, .. }
+            | Node::ActionWithParams { parent, .. }
+            | Node::Action { parent, .. } => Some(*parent),
+        }
+    }
+
+    pub fn children(&self) -> Vec<usize> {
+        match self {
+            // the root of an empty tree is its own child
+            Node::Root { id, child } if id != child => vec![*child],
+            Node::Root { .. } | Node::ArgAction { .. } | Node::Action { .. } => vec![],
+            Node::Decorator { child, .. } | Node::ActionWithChild { child, .. } => vec![*child],
+            Node::Sequence { children, .. }
+            | Node::Fallback { children, .. }
+            | Node::ActionWithParams { children, .. } => children.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
 pub enum DecoratorType {
     IsProbeMode { probe_mode: String },
     HasAltCall,
     PredIs { val: bool },
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
 pub enum ActionType {
     EnterScope { context: String, scope_name: String },
     ExitScope,
@@ -516,19 +561,21 @@ pub enum ActionType {
     ForceSuccess,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
 pub enum ArgActionType {
     SaveParams,
     EmitParams,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
 pub enum ActionWithChildType {
     EnterPackage {
         context: String,
         package_name: String,
         /// The events and the corresponding compiler-defined globals
-        events: HashMap<String, Vec<String>>,
+        events: BTreeMap<String, Vec<String>>,
     },
     EnterProbe {
         context: String,
@@ -537,7 +584,8 @@ pub enum ActionWithChildType {
     },
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
 pub enum ParamActionType {
     EmitIf {
         cond: usize,
@@ -35,7 +35,40 @@ pub enum Cmd {
     },
 
     /// To instrument a Wasm application.
-    Instr(InstrArgs),
+    Instr(Box<InstrArgs>),
+
+    /// To find the line of the script that an offset of an instrumented Wasm module came from
+    /// (e.g. where it trapped).
+    Symbolize {
+        /// The path to the instrumented Wasm module.
+        #[arg(value_parser)]
+        wasm: String,
+
+        /// The index of the function in the instrumented module.
+        #[arg(value_parser)]
+        func: u32,
+
+        /// The byte offset in the instrumented module, in decimal or hex (`0x`).
+        #[arg(value_parser = parse_offset)]
+        offset: usize,
+    },
+
+    /// To show, per function, what the instrumentation changed in an app: the injected
+    /// instructions interleaved with the app's, and the injected functions, globals and locals.
+    Diff {
+        /// The path to the application's Wasm module before it was instrumented.
+        #[arg(short, long, value_parser)]
+        app: String,
+
+        /// The path to the instrumented Wasm module.
+        #[arg(short, long, value_parser, required_unless_present = "script")]
+        instrumented: Option<String>,
+
+        /// The path to a Script to instrument the app with first (instead of passing the
+        /// instrumented Wasm module).
+        #[arg(short, long, value_parser, conflicts_with = "instrumented")]
+        script: Option<String>,
+    },
 
     /// To visualize the relationship between various structures in the module and its instructions
     VisWasm {
@@ -62,6 +95,16 @@ pub enum Cmd {
         /// The path to output the visualization to.
         #[clap(short, long, value_parser, default_value = "output/vis.svg")]
         output_path: String,
+
+        /// Whether to visualize the behavior tree once optimized (see `instr --optimize-tree`)
+        #[clap(long, action)]
+        optimize_tree: bool,
+
+        /// The form to output the behavior tree in: a graph (`svg`) or `json`, along with the
+        /// probes it emits. The JSON is written next to the output path, with a `.json`
+        /// extension, and can be passed back to `instr --behavior`.
+        #[clap(long, value_parser = ["svg", "json"], default_value = "svg")]
+        format: String,
     },
 }
 
@@ -72,7 +115,8 @@ pub enum Cmd {
 
 #[derive(Debug, Args)]
 pub struct InstrArgs {
-    /// The path to the application's Wasm module we want to instrument.
+    /// The path to the application's Wasm module we want to instrument
+    /// (in text form if the path ends with `.wat`).
     #[arg(short, long, value_parser)]
     pub app: String,
     /// The path to the Script containing the instrumentation Probe definitions.
@@ -81,16 +125,137 @@ pub struct InstrArgs {
     /// The path that the instrumented version of the Wasm app should be output to.
     #[arg(short, long, value_parser, default_value = "./output/output.wasm")]
     pub output_path: String,
+    /// The form to output the instrumented Wasm app in: `wasm` (binary), `wat` (text) or `both`.
+    /// The text is written next to the output path, with a `.wat` extension.
+    #[arg(long, value_parser = ["wasm", "wat", "both"], default_value = "wasm", conflicts_with = "virgil")]
+    pub emit: String,
+
+    /// Only instrument the functions of the app matching one of these patterns: a name glob
+    /// (e.g. `handle_*`), an index or range of indices (e.g. `3` or `3..10`) or an export name glob
+    /// (e.g. `export:main`). Adds to the `@only_fn` directives of the script.
+    #[arg(long, value_name = "PATTERN")]
+    pub only_fn: Vec<String>,
+    /// Don't instrument the functions of the app matching one of these patterns (see
+    /// `--only-fn`). Adds to the `@skip_fn` directives of the script.
+    #[arg(long, value_name = "PATTERN")]
+    pub skip_fn: Vec<String>,
 
     /// Whether to emit Virgil code as the instrumentation code
     #[arg(short, long, action, default_value = "false")]
     pub virgil: bool,
 
+    /// Whether to emit a separate Wasm monitor module, plus a manifest of where to attach its
+    /// probe callbacks, for engines with direct instrumentation support (the app is left untouched)
+    #[arg(long, action, default_value = "false", conflicts_with = "virgil")]
+    pub monitor_module: bool,
+
+    /// Whether to write the output of the instrumentation to stderr through WASI `fd_write`
+    /// (the import is added if missing). The app must export `_start` or import `proc_exit`.
+    #[arg(long, action, default_value = "false", conflicts_with_all = ["virgil", "monitor_module"])]
+    pub wasi: bool,
+
+    /// Where to write the output of `print`: `host` calls the `whamm::print(ptr, len)` import,
+    /// `buffer` appends to a buffer in memory (exported as `whamm_output` and `whamm_output_len`).
+    /// Defaults to `host`.
+    #[arg(long, value_parser = ["host", "buffer"], conflicts_with_all = ["virgil", "monitor_module", "wasi"])]
+    pub print_backend: Option<String>,
+
+    /// Whether to keep the instrumentation's data (e.g. strings) in a dedicated memory, exported
+    /// as `whamm_memory`. Requires an engine that supports multi-memory if the app has a memory.
+    #[arg(long, action, default_value = "false", conflicts_with_all = ["virgil", "monitor_module"])]
+    pub multi_memory: bool,
+
+    /// The index of the app's memory to keep the instrumentation's data in (defaults to the
+    /// first memory, a memory is added if the app has none)
+    #[arg(long, conflicts_with_all = ["virgil", "monitor_module", "multi_memory"])]
+    pub instr_memory: Option<u32>,
+
+    /// Whether to drop the app's DWARF debug info instead of updating it for the injected code.
+    #[arg(
+        long,
+        action,
+        default_value = "false",
+        conflicts_with = "preserve_debug"
+    )]
+    pub strip_debug: bool,
+
+    /// Whether to update the app's DWARF debug info for the injected code (the default) without
+    /// warning about it.
+    #[arg(long, action, default_value = "false")]
+    pub preserve_debug: bool,
+
+    /// Whether to only list the sites each probe matches, with its predicate folded to `true`,
+    /// `false` or `dynamic`, instead of writing the instrumented app. Printed as a `table` (the
+    /// default) or as `json`.
+    #[arg(long, value_parser = ["table", "json"], num_args = 0..=1, default_missing_value = "table")]
+    pub dry_run: Option<String>,
+
+    /// Whether to print a summary of the instrumentation once the instrumented app is written:
+    /// the sites matched by each probe, what was injected and how much the code grew. Printed as
+    /// `text` (the default) or as `json`.
+    #[arg(
+        long,
+        value_parser = ["text", "json"],
+        num_args = 0..=1,
+        default_missing_value = "text",
+        conflicts_with_all = ["dry_run", "virgil", "monitor_module"]
+    )]
+    pub stats: Option<String>,
+
+    /// How many functions of the app to process in parallel when looking for the sites of the
+    /// probes and validating the instrumented app, `0` for as many as there are CPUs. The probes
+    /// are still emitted at the sites one after the other. The output is the same whatever the
+    /// number of jobs.
+    #[arg(short, long, default_value = "1")]
+    pub jobs: usize,
+
+    /// Whether to outline the probes' bodies larger than this size (roughly their number of
+    /// instructions) into functions called from each site instead of inlining them at every site.
+    /// The sites of a probe share its function, the values that depend on the site (e.g. `argN`)
+    /// are passed to it.
+    #[arg(long, value_name = "SIZE", conflicts_with_all = ["virgil", "monitor_module"])]
+    pub inline_threshold: Option<usize>,
+
+    /// Whether to batch the increments of counters per basic block of the app: the probes whose
+    /// bodies only add constants to global variables the script doesn't otherwise use, with
+    /// predicates that are always true, add up their increments into a single one at the start of
+    /// each basic block instead of one at each site.
+    #[arg(long, action, conflicts_with_all = ["virgil", "monitor_module"])]
+    pub coalesce_counters: bool,
+
+    /// Whether to optimize the behavior tree before emitting: the probes that can never emit
+    /// anything (their predicate is always false, or their body is empty) are dropped, along with
+    /// the events and modes left without probes, and the arguments of the instructions are only
+    /// saved for the probes that use them (`argN`). The dropped probes match no sites.
+    #[arg(long, action)]
+    pub optimize_tree: bool,
+
+    /// The path to a behavior tree written by `vis-script --format json` to emit instead of the
+    /// one built from the script, which must be the script it was written for.
+    #[arg(long, value_name = "PATH")]
+    pub behavior: Option<String>,
+
+    /// Whether to instrument an app that whamm! already instrumented, on top of its
+    /// instrumentation, instead of refusing to. What was injected before is never instrumented.
+    /// The app must still have the `whamm` and `whamm.sourcemap` custom sections written by the
+    /// previous instrumentation.
+    #[arg(long, action)]
+    pub layer: bool,
+
     /// Whether to run the verifier on the specified script
     #[arg(long, short, action, default_value = "true")]
     pub run_verifier: bool,
 }
 
+/// Parses a byte offset in decimal or hex (`0x`)
+fn parse_offset(offset: &str) -> Result<usize, String> {
+    let res = match offset.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => offset.parse::<usize>(),
+    };
+    res.map_err(|e| format!("invalid offset `{offset}`: {e}"))
+}
+
 // pub fn print_completion<G: Generator>(gen: G, app: &mut App) {
 //     generate(gen, app, app.get_name().to_string(), &mut io::stdout());
 // }
@@ -1,2 +1,4 @@
 pub mod error;
+pub mod func_filter;
+pub mod parallel;
 pub mod terminal;
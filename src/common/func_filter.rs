@@ -0,0 +1,89 @@
+use glob::Pattern;
+use std::ops::RangeInclusive;
+
+/// Picks out functions of the app, written as:
+/// - `N` or `N..M`: the functions with index `N` (through `M`, inclusive) in the app
+/// - `export:GLOB`: the functions exported under a name matching `GLOB`
+/// - `GLOB`: the functions named (in the name section) matching `GLOB`
+#[derive(Clone, Debug)]
+pub enum FuncPattern {
+    Idxs(RangeInclusive<u32>),
+    Export(Pattern),
+    Name(Pattern),
+}
+impl FuncPattern {
+    pub fn new(pattern: &str) -> Result<Self, String> {
+        let glob = |glob: &str| {
+            Pattern::new(glob)
+                .map_err(|e| format!("Invalid function pattern `{pattern}`: {}", e.msg))
+        };
+        let idx = |idx: &str| {
+            if idx.is_empty() || !idx.chars().all(|c| c.is_ascii_digit()) {
+                return None;
+            }
+            idx.parse::<u32>().ok()
+        };
+        if let Some(export) = pattern.strip_prefix("export:") {
+            return Ok(FuncPattern::Export(glob(export)?));
+        }
+        if let Some(start) = idx(pattern) {
+            return Ok(FuncPattern::Idxs(start..=start));
+        }
+        if let Some((start, end)) = pattern.split_once("..") {
+            if let (Some(start), Some(end)) = (idx(start), idx(end)) {
+                return Ok(FuncPattern::Idxs(start..=end));
+            }
+        }
+        Ok(FuncPattern::Name(glob(pattern)?))
+    }
+
+    fn matches(&self, idx: u32, name: Option<&str>, exports: &[&str]) -> bool {
+        match self {
+            FuncPattern::Idxs(idxs) => idxs.contains(&idx),
+            FuncPattern::Export(glob) => exports.iter().any(|export| glob.matches(export)),
+            FuncPattern::Name(glob) => name.is_some_and(|name| glob.matches(name)),
+        }
+    }
+}
+
+/// Restricts which functions of the app get instrumented: those matching any of the `only`
+/// patterns (all of them if there are none), except those matching any of the `skip` patterns.
+#[derive(Clone, Debug, Default)]
+pub struct FuncFilter {
+    pub only: Vec<FuncPattern>,
+    pub skip: Vec<FuncPattern>,
+}
+impl FuncFilter {
+    pub fn new(only: &[String], skip: &[String]) -> Result<Self, String> {
+        let patterns = |patterns: &[String]| -> Result<Vec<FuncPattern>, String> {
+            patterns
+                .iter()
+                .map(|pattern| FuncPattern::new(pattern))
+                .collect()
+        };
+        Ok(Self {
+            only: patterns(only)?,
+            skip: patterns(skip)?,
+        })
+    }
+
+    /// Adds the patterns of `other` to this filter
+    pub fn extend(&mut self, other: FuncFilter) {
+        self.only.extend(other.only);
+        self.skip.extend(other.skip);
+    }
+
+    /// Whether the function with index `idx` in the app, named `name` and exported under `exports`
+    /// is instrumented
+    pub fn allows(&self, idx: u32, name: Option<&str>, exports: &[&str]) -> bool {
+        (self.only.is_empty()
+            || self
+                .only
+                .iter()
+                .any(|pattern| pattern.matches(idx, name, exports)))
+            && !self
+                .skip
+                .iter()
+                .any(|pattern| pattern.matches(idx, name, exports))
+    }
+}
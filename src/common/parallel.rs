@@ -0,0 +1,44 @@
+use std::num::NonZeroUsize;
+use std::thread;
+
+/// The number of threads to use for `jobs` as passed to `--jobs`: as many as there are CPUs for
+/// `0`, `jobs` otherwise
+pub fn num_threads(jobs: usize) -> usize {
+    if jobs == 0 {
+        thread::available_parallelism()
+            .map(NonZeroUsize::get)
+            .unwrap_or(1)
+    } else {
+        jobs
+    }
+}
+
+/// Splits `items` into (at most) `threads` contiguous chunks and maps each chunk with `f` on its
+/// own thread. The results are in the order of the chunks, so they don't depend on `threads`
+/// when concatenated.
+pub fn map_chunks<T: Send, R: Send>(
+    mut items: Vec<T>,
+    threads: usize,
+    f: impl Fn(Vec<T>) -> R + Sync,
+) -> Vec<R> {
+    if threads <= 1 || items.len() <= 1 {
+        return vec![f(items)];
+    }
+    let chunk_size = items.len().div_ceil(threads);
+    let mut chunks = vec![];
+    while !items.is_empty() {
+        let rest = items.split_off(chunk_size.min(items.len()));
+        chunks.push(std::mem::replace(&mut items, rest));
+    }
+    let f = &f;
+    thread::scope(|scope| {
+        let handles: Vec<_> = chunks
+            .into_iter()
+            .map(|chunk| scope.spawn(move || f(chunk)))
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect()
+    })
+}
@@ -1,7 +1,16 @@
+pub mod cfg;
+pub mod const_prop;
+pub mod counters;
+pub mod debug_info;
+pub mod diff;
 pub mod emitters;
 pub mod init_generator;
 pub mod instr_generator;
+pub mod name_section;
+pub mod source_map;
+pub mod stats;
 pub mod types;
+pub mod validation;
 
 #[cfg(test)]
 pub mod tests;
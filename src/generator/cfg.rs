@@ -0,0 +1,179 @@
+use std::collections::{BTreeMap, HashMap};
+use walrus::ir::{Instr, InstrLocId, InstrSeqId};
+use walrus::LocalFunction;
+
+/// How control leaves a basic block
+#[derive(Clone, Debug, Default, PartialEq)]
+enum Exit {
+    /// Falls through to whatever follows the end of its block
+    #[default]
+    End,
+    /// Enters the nested blocks of a `block`, `loop` or `if`
+    Enter(Vec<InstrSeqId>),
+    Br(InstrSeqId),
+    BrIf(InstrSeqId),
+    BrTable(Vec<InstrSeqId>),
+    /// `return` or `unreachable`
+    Leave,
+}
+
+/// A run of instructions of one of the blocks of a function that always run one after the other,
+/// from the first one to the last one (unless one of them traps). It ends with a branch or with an
+/// instruction opening nested blocks (`block`, `loop` or `if`), what follows the nested blocks
+/// starts another basic block.
+#[derive(Debug)]
+pub struct BasicBlock {
+    /// The block of the function the instructions are in
+    pub seq: InstrSeqId,
+    /// The locations of the instructions in the app binary, in order
+    pub instrs: Vec<InstrLocId>,
+    /// The basic blocks that can run next
+    pub succs: Vec<usize>,
+    exit: Exit,
+}
+
+/// Where a nested block of the function is in the graph
+struct NestedSeq {
+    /// Its first basic block
+    first: usize,
+    /// The basic block following its end
+    after: usize,
+    /// Whether a branch to it goes back to its start
+    is_loop: bool,
+}
+
+/// The control flow graph of a function, over its basic blocks
+#[derive(Debug, Default)]
+pub struct Cfg {
+    /// In the order of their instructions, the first one is the entry of the function
+    pub blocks: Vec<BasicBlock>,
+    /// The basic block of each instruction, by its location in the app binary
+    block_of: BTreeMap<InstrLocId, usize>,
+}
+impl Cfg {
+    pub fn new(func: &LocalFunction) -> Self {
+        let mut cfg = Self::default();
+        let mut nested = HashMap::new();
+        cfg.add_seq(func, func.entry_block(), &mut nested);
+
+        // branching to the function's block returns from it
+        let target = |seq: &InstrSeqId| {
+            nested.get(seq).map(|nested: &NestedSeq| {
+                if nested.is_loop {
+                    nested.first
+                } else {
+                    nested.after
+                }
+            })
+        };
+        for idx in 0..cfg.blocks.len() {
+            let block = &cfg.blocks[idx];
+            let mut succs: Vec<usize> = match &block.exit {
+                Exit::End => nested
+                    .get(&block.seq)
+                    .map(|nested| nested.after)
+                    .into_iter()
+                    .collect(),
+                Exit::Enter(seqs) => seqs
+                    .iter()
+                    .filter_map(|seq| nested.get(seq).map(|nested| nested.first))
+                    .collect(),
+                Exit::Br(seq) => target(seq).into_iter().collect(),
+                // the branch isn't taken: on to the next instruction
+                Exit::BrIf(seq) => target(seq).into_iter().chain([idx + 1]).collect(),
+                Exit::BrTable(seqs) => seqs.iter().filter_map(target).collect(),
+                Exit::Leave => vec![],
+            };
+            succs.sort();
+            succs.dedup();
+            cfg.blocks[idx].succs = succs;
+        }
+        cfg
+    }
+
+    /// Add the basic blocks of the instructions of `seq` and of its nested blocks, returns the
+    /// first one
+    fn add_seq(
+        &mut self,
+        func: &LocalFunction,
+        seq: InstrSeqId,
+        nested: &mut HashMap<InstrSeqId, NestedSeq>,
+    ) -> usize {
+        let first = self.add_block(seq);
+        let mut curr = first;
+        for (instr, loc) in func.block(seq).iter() {
+            self.blocks[curr].instrs.push(*loc);
+            self.block_of.insert(*loc, curr);
+
+            let (exit, is_loop) = match instr {
+                Instr::Block(block) => (Exit::Enter(vec![block.seq]), false),
+                Instr::Loop(loop_) => (Exit::Enter(vec![loop_.seq]), true),
+                Instr::IfElse(if_else) => (
+                    Exit::Enter(vec![if_else.consequent, if_else.alternative]),
+                    false,
+                ),
+                Instr::Br(br) => (Exit::Br(br.block), false),
+                Instr::BrIf(br_if) => (Exit::BrIf(br_if.block), false),
+                Instr::BrTable(br_table) => (
+                    Exit::BrTable(
+                        br_table
+                            .blocks
+                            .iter()
+                            .chain([&br_table.default])
+                            .copied()
+                            .collect(),
+                    ),
+                    false,
+                ),
+                Instr::Return(..) | Instr::Unreachable(..) => (Exit::Leave, false),
+                _ => continue,
+            };
+            let firsts: Vec<(InstrSeqId, usize)> = match &exit {
+                Exit::Enter(seqs) => seqs
+                    .iter()
+                    .map(|seq| (*seq, self.add_seq(func, *seq, nested)))
+                    .collect(),
+                _ => vec![],
+            };
+            self.blocks[curr].exit = exit;
+
+            // what follows the instruction starts another basic block
+            curr = self.add_block(seq);
+            for (seq, first) in firsts {
+                nested.insert(
+                    seq,
+                    NestedSeq {
+                        first,
+                        after: curr,
+                        is_loop,
+                    },
+                );
+            }
+        }
+        first
+    }
+
+    fn add_block(&mut self, seq: InstrSeqId) -> usize {
+        self.blocks.push(BasicBlock {
+            seq,
+            instrs: vec![],
+            succs: vec![],
+            exit: Exit::End,
+        });
+        self.blocks.len() - 1
+    }
+
+    /// The basic block of the instruction at `loc` in the app binary
+    pub fn block_of(&self, loc: &InstrLocId) -> Option<usize> {
+        self.block_of.get(loc).copied()
+    }
+
+    /// Whether the instruction at `loc` in the app binary ends its basic block, i.e. whatever runs
+    /// after it is in another basic block
+    pub fn ends_block(&self, loc: &InstrLocId) -> bool {
+        self.block_of(loc).is_some_and(|idx| {
+            let block = &self.blocks[idx];
+            block.exit != Exit::End && block.instrs.last() == Some(loc)
+        })
+    }
+}
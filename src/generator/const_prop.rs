@@ -0,0 +1,265 @@
+use crate::generator::types::ExprFolder;
+use crate::parser::types::{DataType, Expr, Script, Statement, Value, Whamm};
+use crate::verifier::types::{Record, ScopeType, SymbolTable};
+use std::collections::{HashMap, HashSet};
+
+/// An assignment to a global of the script, and the variables shadowing the globals where it is
+struct GlobalAssign<'a> {
+    expr: &'a Expr,
+    shadowed: &'a HashSet<String>,
+}
+
+/// The names of the variables declared by some statements
+fn declared(stmts: &[Statement], names: &mut HashSet<String>) {
+    for stmt in stmts.iter() {
+        match stmt {
+            Statement::Decl {
+                var_id: Expr::VarId { name, .. },
+                ..
+            } => {
+                names.insert(name.clone());
+            }
+            Statement::If { conseq, alt, .. } => {
+                declared(&conseq.stmts, names);
+                declared(&alt.stmts, names);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn add_assigns<'a>(
+    stmts: &'a [Statement],
+    shadowed: &'a HashSet<String>,
+    assigns: &mut HashMap<String, Vec<GlobalAssign<'a>>>,
+) {
+    for stmt in stmts.iter() {
+        match stmt {
+            Statement::Assign {
+                var_id: Expr::VarId { name, .. },
+                expr,
+                ..
+            } => {
+                if shadowed.contains(name) {
+                    continue;
+                }
+                if let Some(global_assigns) = assigns.get_mut(name) {
+                    global_assigns.push(GlobalAssign { expr, shadowed });
+                }
+            }
+            Statement::If { conseq, alt, .. } => {
+                add_assigns(&conseq.stmts, shadowed, assigns);
+                add_assigns(&alt.stmts, shadowed, assigns);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// What `expr` folds to with the constants known so far, unless a variable shadows them
+pub(crate) fn fold_with(
+    expr: &Expr,
+    consts: &HashMap<String, Value>,
+    shadowed: &HashSet<String>,
+) -> Option<Value> {
+    fn substitute(expr: &mut Expr, consts: &HashMap<String, Value>, shadowed: &HashSet<String>) {
+        match expr {
+            Expr::VarId { name, loc, .. } => {
+                if let (false, Some(val)) = (shadowed.contains(name), consts.get(name)) {
+                    *expr = Expr::Primitive {
+                        val: val.clone(),
+                        loc: loc.clone(),
+                    };
+                }
+            }
+            Expr::UnOp { expr, .. } => substitute(expr, consts, shadowed),
+            Expr::Ternary {
+                cond, conseq, alt, ..
+            } => {
+                substitute(cond, consts, shadowed);
+                substitute(conseq, consts, shadowed);
+                substitute(alt, consts, shadowed);
+            }
+            Expr::BinOp { lhs, rhs, .. } => {
+                substitute(lhs, consts, shadowed);
+                substitute(rhs, consts, shadowed);
+            }
+            Expr::Call { args, .. } => args
+                .iter_mut()
+                .flatten()
+                .for_each(|arg| substitute(arg, consts, shadowed)),
+            Expr::Primitive { .. } => {}
+        }
+    }
+    let mut expr = expr.clone();
+    substitute(&mut expr, consts, shadowed);
+    // the other variables are unknown
+    match ExprFolder::fold_expr(&expr, &SymbolTable::new()) {
+        Expr::Primitive {
+            val: val @ (Value::Integer { .. } | Value::Boolean { .. }),
+            ..
+        } => Some(val),
+        _ => None,
+    }
+}
+
+/// The value of a global of type `ty` that is never assigned
+fn default_value(ty: &DataType) -> Option<Value> {
+    match ty {
+        // the same as the integer literals
+        DataType::I32 | DataType::U32 => Some(Value::Integer {
+            ty: DataType::I32,
+            val: 0,
+        }),
+        DataType::Boolean => Some(Value::Boolean {
+            ty: DataType::Boolean,
+            val: false,
+        }),
+        _ => None,
+    }
+}
+
+/// The globals of the script that always hold the same value: those only ever assigned that
+/// constant (possibly computed from other such globals), or never assigned at all.
+fn script_consts(script: &Script) -> HashMap<String, Value> {
+    let mut defaults = HashMap::new();
+    let mut assigns: HashMap<String, Vec<GlobalAssign>> = HashMap::new();
+    for (name, global) in script.globals.iter() {
+        if global.is_comp_provided {
+            continue;
+        }
+        if let Some(default) = default_value(&global.ty) {
+            defaults.insert(name.clone(), default);
+            assigns.insert(name.clone(), vec![]);
+        }
+    }
+
+    // where the globals are shadowed by the parameters or the variables of a function or a body
+    let no_shadowed = HashSet::new();
+    let fn_shadowed: Vec<HashSet<String>> = script
+        .fns
+        .iter()
+        .map(|f| {
+            let mut names: HashSet<String> = f
+                .params
+                .iter()
+                .filter_map(|(param, _)| match param {
+                    Expr::VarId { name, .. } => Some(name.clone()),
+                    _ => None,
+                })
+                .collect();
+            declared(&f.body.stmts, &mut names);
+            names
+        })
+        .collect();
+    let bodies: Vec<&Vec<Statement>> = script
+        .providers
+        .values()
+        .flat_map(|provider| provider.packages())
+        .flat_map(|package| package.events())
+        .flat_map(|event| event.probes().values())
+        .flatten()
+        .filter_map(|probe| probe.body().as_ref())
+        .collect();
+    let body_shadowed: Vec<HashSet<String>> = bodies
+        .iter()
+        .map(|body| {
+            let mut names = HashSet::new();
+            declared(body, &mut names);
+            names
+        })
+        .collect();
+
+    add_assigns(&script.global_stmts, &no_shadowed, &mut assigns);
+    for (f, shadowed) in script.fns.iter().zip(fn_shadowed.iter()) {
+        add_assigns(&f.body.stmts, shadowed, &mut assigns);
+    }
+    for (body, shadowed) in bodies.iter().zip(body_shadowed.iter()) {
+        add_assigns(body, shadowed, &mut assigns);
+    }
+
+    // the globals the global statements assign to before any probe runs, the others start out
+    // with their default value
+    let initialized: HashSet<&String> = script
+        .global_stmts
+        .iter()
+        .filter_map(|stmt| match stmt {
+            Statement::Assign {
+                var_id: Expr::VarId { name, .. },
+                ..
+            } => Some(name),
+            _ => None,
+        })
+        .collect();
+
+    // a global becomes known once all the values assigned to it fold to the same constant
+    let mut consts: HashMap<String, Value> = HashMap::new();
+    loop {
+        let mut is_changed = false;
+        for (name, global_assigns) in assigns.iter() {
+            if consts.contains_key(name) {
+                continue;
+            }
+            let Some(mut vals) = global_assigns
+                .iter()
+                .map(|assign| fold_with(assign.expr, &consts, assign.shadowed))
+                .collect::<Option<Vec<Value>>>()
+            else {
+                continue;
+            };
+            if !initialized.contains(name) {
+                vals.push(defaults[name].clone());
+            }
+            if vals.iter().any(|val| *val != vals[0]) {
+                continue;
+            }
+            consts.insert(name.clone(), vals.swap_remove(0));
+            is_changed = true;
+        }
+        if !is_changed {
+            return consts;
+        }
+    }
+}
+
+/// Propagates the globals of the scripts that always hold the same value (see `script_consts`):
+/// their records get the value, so they fold away in the predicates and bodies of the probes
+/// (see `ExprFolder::fold_var_id`), and their globals are initialized with it instead of being
+/// assigned by the global statements. Returns the names of the propagated globals.
+pub fn propagate_consts(whamm: &mut Whamm, table: &mut SymbolTable) -> Vec<String> {
+    let mut propagated = vec![];
+    for script in whamm.scripts.iter_mut() {
+        let consts = script_consts(script);
+        let Some(scope) = table
+            .scopes
+            .iter()
+            .find(|scope| scope.ty == ScopeType::Script && scope.name == script.name)
+        else {
+            continue;
+        };
+        let rec_ids: Vec<(usize, Value)> = consts
+            .iter()
+            .filter_map(|(name, val)| scope.lookup(name).map(|id| (*id, val.clone())))
+            .collect();
+        for (rec_id, val) in rec_ids {
+            if let Some(Record::Var { value, .. }) = table.get_record_mut(&rec_id) {
+                *value = Some(val);
+            }
+        }
+
+        script.global_stmts.retain(|stmt| {
+            !matches!(stmt, Statement::Assign {
+                var_id: Expr::VarId { name, .. },
+                ..
+            } if consts.contains_key(name))
+        });
+        for (name, val) in consts.into_iter() {
+            if let Some(global) = script.globals.get_mut(&name) {
+                global.value = Some(val);
+            }
+            propagated.push(name);
+        }
+    }
+    propagated.sort();
+    propagated
+}
@@ -0,0 +1,106 @@
+use crate::behavior::builder_visitor::SimpleAST;
+use crate::generator::emitters::StmtVars;
+use crate::parser::types::{BinOp, Expr, Location, Statement, Value, Whamm};
+use std::collections::HashSet;
+
+/// An increment of a variable by a constant, e.g. `count = count + 1;`
+#[derive(Clone, Debug, PartialEq)]
+pub struct CounterIncr {
+    pub name: String,
+    pub incr: i32,
+    pub loc: Option<Location>,
+}
+
+fn counter_incr(stmt: &Statement) -> Option<CounterIncr> {
+    let Statement::Assign {
+        var_id: Expr::VarId { name, .. },
+        expr:
+            Expr::BinOp {
+                lhs,
+                op: BinOp::Add,
+                rhs,
+                ..
+            },
+        loc,
+    } = stmt
+    else {
+        return None;
+    };
+    let incr = match (lhs.as_ref(), rhs.as_ref()) {
+        (
+            Expr::VarId { name: var, .. },
+            Expr::Primitive {
+                val: Value::Integer { val, .. },
+                ..
+            },
+        )
+        | (
+            Expr::Primitive {
+                val: Value::Integer { val, .. },
+                ..
+            },
+            Expr::VarId { name: var, .. },
+        ) if var == name => *val,
+        _ => return None,
+    };
+    Some(CounterIncr {
+        name: name.clone(),
+        incr,
+        loc: loc.clone(),
+    })
+}
+
+/// The increments making up a probe's body, if it is nothing else
+pub fn counter_incrs(body: &[Statement]) -> Option<Vec<CounterIncr>> {
+    if body.is_empty() {
+        return None;
+    }
+    body.iter().map(counter_incr).collect()
+}
+
+/// The variables of the scripts in `whamm` that the probes (in `ast`) only ever increment by
+/// constants, and that nothing else reads or writes: the order the sites add to them in doesn't
+/// matter.
+pub fn counters(ast: &SimpleAST, whamm: &Whamm) -> HashSet<String> {
+    let mut incremented = HashSet::new();
+    // the functions of the scripts can be called from any probe, and would see the counts of
+    // the sites batched with the caller's
+    let mut others = StmtVars::default();
+    for script in whamm.scripts.iter() {
+        for f in script.fns.iter() {
+            others.add_stmts(&f.body.stmts);
+        }
+        // the global statements declare and initialize the counters, only their reads count
+        let mut global_vars = StmtVars::default();
+        global_vars.add_stmts(&script.global_stmts);
+        others.used.extend(global_vars.used);
+    }
+    let probes = ast
+        .probes
+        .values()
+        .flat_map(|packages| packages.values())
+        .flat_map(|events| events.values())
+        .flat_map(|modes| modes.values())
+        .flatten();
+    for probe in probes {
+        if let Some(pred) = probe.predicate() {
+            others.add_expr(pred);
+        }
+        for stmt in probe.body().iter().flatten() {
+            match counter_incr(stmt) {
+                Some(incr) => {
+                    incremented.insert(incr.name);
+                }
+                None => others.add_stmts(std::slice::from_ref(stmt)),
+            }
+        }
+    }
+    incremented
+        .into_iter()
+        .filter(|name| {
+            !others.used.contains(name)
+                && !others.assigned.contains(name)
+                && !others.declared.contains(name)
+        })
+        .collect()
+}
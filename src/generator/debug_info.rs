@@ -0,0 +1,115 @@
+use crate::generator::name_section::{custom_sections, read_sections};
+use gimli::write::{Address, EndianVec, Sections};
+use gimli::{EndianSlice, LittleEndian, SectionId};
+use std::borrow::Cow;
+use wasm_encoder::RawSection;
+use wasmparser::BinaryReader;
+
+const CODE_SECTION_ID: u8 = 10;
+const DEBUG_SECTION_PREFIX: &str = ".debug_";
+/// The address `walrus` gives code that was removed, it is left as-is
+const DEAD_CODE: u64 = 0xFFFF_FFFF;
+/// The size of the function count of the code section that `walrus` assumes when it updates the
+/// code addresses of the DWARF debug info
+const ASSUMED_COUNT_SIZE: usize = 2;
+
+/// Whether the module has DWARF debug info, i.e. `.debug_*` custom sections
+pub fn has_debug_info(app_bytes: &[u8]) -> bool {
+    custom_sections(app_bytes).any(|(name, _)| name.starts_with(DEBUG_SECTION_PREFIX))
+}
+
+/// Fix the code addresses of the DWARF debug info of a module emitted by `walrus`.
+///
+/// DWARF code addresses are offsets from the start of the code section's contents. When `walrus`
+/// updates them for the emitted code, it assumes the function count at the start of the code
+/// section is encoded in 2 bytes, so the addresses are off when it isn't (e.g. for modules with
+/// fewer than 128 functions). The module is returned as-is if it can't be read.
+pub fn fix_code_addresses(wasm: Vec<u8>) -> Vec<u8> {
+    let Some(sections) = read_sections(&wasm) else {
+        return wasm;
+    };
+    let Some(count_size) = sections
+        .iter()
+        .find(|section| section.id == CODE_SECTION_ID)
+        .and_then(|code| {
+            let mut reader = BinaryReader::new(code.contents);
+            reader.read_var_u32().ok()?;
+            Some(reader.current_position())
+        })
+    else {
+        return wasm;
+    };
+    let debug_sections: Vec<(&str, &[u8])> = sections
+        .iter()
+        .filter_map(|section| section.custom)
+        .filter(|(name, _)| name.starts_with(DEBUG_SECTION_PREFIX))
+        .collect();
+    if count_size == ASSUMED_COUNT_SIZE || debug_sections.is_empty() {
+        return wasm;
+    }
+
+    let Some(fixed) = rewrite_addresses(&debug_sections, |address| {
+        (address + count_size as u64).saturating_sub(ASSUMED_COUNT_SIZE as u64)
+    }) else {
+        return wasm;
+    };
+    // the fixed sections go where the first debug section was
+    let mut module = wasm_encoder::Module::new();
+    let mut fixed = Some(fixed);
+    for section in sections.iter() {
+        let is_debug = section
+            .custom
+            .is_some_and(|(name, _)| name.starts_with(DEBUG_SECTION_PREFIX));
+        if !is_debug {
+            module.section(&RawSection {
+                id: section.id,
+                data: section.contents,
+            });
+        } else if let Some(fixed) = fixed.take() {
+            for (name, data) in fixed {
+                module.section(&wasm_encoder::CustomSection {
+                    name: Cow::Borrowed(name),
+                    data: Cow::Owned(data),
+                });
+            }
+        }
+    }
+    module.finish()
+}
+
+/// Rewrite the DWARF debug info with the code addresses converted
+fn rewrite_addresses(
+    debug_sections: &[(&str, &[u8])],
+    convert: impl Fn(u64) -> u64,
+) -> Option<Vec<(&'static str, Vec<u8>)>> {
+    let dwarf = gimli::read::Dwarf::load(|id: SectionId| -> Result<_, gimli::Error> {
+        let data = debug_sections
+            .iter()
+            .find(|(name, _)| *name == id.name())
+            .map(|(_, data)| *data)
+            .unwrap_or_default();
+        Ok(EndianSlice::new(data, LittleEndian))
+    })
+    .ok()?;
+    let mut dwarf = gimli::write::Dwarf::from(&dwarf, &|address| {
+        Some(Address::Constant(if address == 0 || address == DEAD_CODE {
+            address
+        } else {
+            convert(address)
+        }))
+    })
+    .ok()?;
+
+    let mut sections = Sections::new(EndianVec::new(LittleEndian));
+    dwarf.write(&mut sections).ok()?;
+    let mut fixed = vec![];
+    sections
+        .for_each(|id, data| -> Result<(), ()> {
+            if !data.slice().is_empty() {
+                fixed.push((id.name(), data.slice().to_vec()));
+            }
+            Ok(())
+        })
+        .ok()?;
+    Some(fixed)
+}
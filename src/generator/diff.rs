@@ -0,0 +1,362 @@
+use crate::common::terminal::{green, grey, red, white, yellow};
+use crate::generator::emitters::{INJECTED_NAME_PREFIX, WHAMM_SECTION};
+use crate::generator::name_section::{custom_section, Names};
+use crate::generator::source_map::{SourceMap, SourceRange};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use termcolor::Buffer;
+use wasmparser::{FunctionBody, Import, ImportSectionEntryType, Operator, Parser, Payload};
+
+/// How an instruction of a function differs between the app and the instrumented app
+#[derive(Debug, PartialEq)]
+pub enum Change {
+    /// An instruction of the app
+    Same,
+    /// An instruction injected by whamm!
+    Injected,
+    /// An instruction of the app that was removed (e.g. by an `alt` probe)
+    Removed,
+}
+
+/// An instruction of a function of the app or the instrumented app
+#[derive(Debug)]
+pub struct InstrDiff {
+    pub change: Change,
+    /// The offset of the instruction in the instrumented app, none if it was removed
+    pub offset: Option<usize>,
+    pub text: String,
+    /// The injected function, global or local the instruction refers to, if any
+    pub injected_ref: Option<String>,
+    /// The script the instruction was injected for
+    pub script_path: Option<String>,
+    /// Where the instruction was injected from, as `line:column` of the script
+    pub script_loc: Option<(usize, usize)>,
+}
+
+/// A function of the instrumented app that was changed or injected by whamm!
+#[derive(Debug)]
+pub struct FuncDiff {
+    /// The index of the function in the instrumented app
+    pub func_idx: u32,
+    pub name: Option<String>,
+    /// The index of the function in the app, none if it was injected
+    pub app_func_idx: Option<u32>,
+    /// The instructions of a changed function, empty for an injected function
+    pub instrs: Vec<InstrDiff>,
+}
+
+/// The difference between an app and the app instrumented by whamm!, per function
+#[derive(Debug)]
+pub struct ModuleDiff {
+    /// The functions injected by whamm!
+    pub injected_funcs: Vec<FuncDiff>,
+    /// The injected globals: (index, name)
+    pub injected_globals: Vec<(u32, Option<String>)>,
+    /// The functions of the app that were changed
+    pub changed_funcs: Vec<FuncDiff>,
+}
+
+/// The functions of a module: the number of imported functions, and the bodies of the others
+pub(crate) fn read_funcs(wasm: &[u8]) -> Result<(u32, Vec<FunctionBody>), String> {
+    let mut imported = 0;
+    let mut bodies = vec![];
+    for payload in Parser::new(0).parse_all(wasm) {
+        match payload.map_err(|e| e.to_string())? {
+            Payload::ImportSection(imports) => {
+                for import in imports {
+                    if let Import {
+                        ty: ImportSectionEntryType::Function(_),
+                        ..
+                    } = import.map_err(|e| e.to_string())?
+                    {
+                        imported += 1;
+                    }
+                }
+            }
+            Payload::CodeSectionEntry(body) => bodies.push(body),
+            _ => {}
+        }
+    }
+    Ok((imported, bodies))
+}
+
+/// The instructions of a function: (offset, instruction)
+fn read_instrs<'a>(body: &FunctionBody<'a>) -> Result<Vec<(usize, Operator<'a>)>, String> {
+    let reader = body.get_operators_reader().map_err(|e| e.to_string())?;
+    reader
+        .into_iter_with_offsets()
+        .map(|res| {
+            res.map(|(op, offset)| (offset, op))
+                .map_err(|e| e.to_string())
+        })
+        .collect()
+}
+
+/// The kind of an instruction, without its immediates (e.g. `Call` for `call 3`)
+fn mnemonic(op: &Operator) -> String {
+    let text = format!("{op:?}");
+    match text.split_once(' ') {
+        Some((mnemonic, _)) => mnemonic.to_string(),
+        None => text,
+    }
+}
+
+/// The `whamm` metadata section of an instrumented app (see `WasmRewritingEmitter::metadata`)
+struct Metadata {
+    injected_funcs: HashSet<u32>,
+    injected_globals: HashSet<u32>,
+    /// The index in the app of each function of the app, by index in the instrumented app
+    app_funcs: HashMap<u32, u32>,
+}
+impl Metadata {
+    fn read(wasm: &[u8]) -> Result<Self, String> {
+        let Some(section) = custom_section(wasm, WHAMM_SECTION) else {
+            return Err(format!(
+                "The module has no `{WHAMM_SECTION}` custom section, was it instrumented by whamm!?"
+            ));
+        };
+        let metadata: Value = serde_json::from_slice(section)
+            .map_err(|e| format!("Could not read the `{WHAMM_SECTION}` custom section: {e}"))?;
+        let idxs = |val: &Value| -> Vec<u32> {
+            val.as_array()
+                .map(|idxs| {
+                    idxs.iter()
+                        .filter_map(|idx| idx.as_u64().map(|idx| idx as u32))
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        let app_funcs = metadata["app_funcs"]
+            .as_array()
+            .map(|pairs| {
+                pairs
+                    .iter()
+                    .filter_map(|pair| match idxs(pair)[..] {
+                        [idx, app_idx] => Some((idx, app_idx)),
+                        _ => None,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        Ok(Self {
+            injected_funcs: idxs(&metadata["injected"]["funcs"]).into_iter().collect(),
+            injected_globals: idxs(&metadata["injected"]["globals"]).into_iter().collect(),
+            app_funcs,
+        })
+    }
+}
+
+/// The difference between an app and the app instrumented by whamm!.
+///
+/// The instrumented app tells which functions and globals were injected (see the `whamm`
+/// section) and which instructions were injected (see the `whamm.sourcemap` section), the rest
+/// are matched with the instructions of the app.
+pub fn diff(app: &[u8], instrumented: &[u8]) -> Result<ModuleDiff, String> {
+    let metadata = Metadata::read(instrumented)?;
+    let ranges = SourceMap::ranges_binary(instrumented)?;
+    let names = Names::read(instrumented);
+    let (app_imported, app_bodies) = read_funcs(app)?;
+    let (imported, bodies) = read_funcs(instrumented)?;
+
+    let injected_ref = |func_idx: u32, op: &Operator| -> Option<String> {
+        let name_of = |names: &HashMap<u32, String>, idx: u32| {
+            names.get(&idx).cloned().unwrap_or(idx.to_string())
+        };
+        match op {
+            Operator::Call { function_index } | Operator::RefFunc { function_index }
+                if metadata.injected_funcs.contains(function_index) =>
+            {
+                Some(name_of(&names.funcs, *function_index))
+            }
+            Operator::GlobalGet { global_index } | Operator::GlobalSet { global_index }
+                if metadata.injected_globals.contains(global_index) =>
+            {
+                Some(name_of(&names.globals, *global_index))
+            }
+            Operator::LocalGet { local_index }
+            | Operator::LocalSet { local_index }
+            | Operator::LocalTee { local_index } => names
+                .locals
+                .get(&func_idx)
+                .and_then(|locals| locals.get(local_index))
+                .filter(|name| name.starts_with(INJECTED_NAME_PREFIX))
+                .cloned(),
+            _ => None,
+        }
+    };
+
+    let mut injected_funcs = vec![];
+    let mut changed_funcs = vec![];
+    let mut func_idxs: Vec<u32> = (0..imported + bodies.len() as u32).collect();
+    // in the order of the app
+    func_idxs.sort_by_key(|idx| metadata.app_funcs.get(idx).copied().unwrap_or(u32::MAX));
+    for func_idx in func_idxs {
+        let name = names.funcs.get(&func_idx).cloned();
+        if metadata.injected_funcs.contains(&func_idx) {
+            injected_funcs.push(FuncDiff {
+                func_idx,
+                name,
+                app_func_idx: None,
+                instrs: vec![],
+            });
+            continue;
+        }
+        let (Some(app_func_idx), Some(body)) = (
+            metadata.app_funcs.get(&func_idx),
+            func_idx
+                .checked_sub(imported)
+                .map(|idx| &bodies[idx as usize]),
+        ) else {
+            // an imported function of the app
+            continue;
+        };
+        let Some(app_body) = app_func_idx
+            .checked_sub(app_imported)
+            .and_then(|idx| app_bodies.get(idx as usize))
+        else {
+            return Err(format!(
+                "The instrumented module doesn't match the app, it has no function {app_func_idx}"
+            ));
+        };
+
+        let func_ranges: Vec<&SourceRange> = ranges
+            .iter()
+            .filter(|range| range.func_idx == func_idx)
+            .collect();
+        let range_of = |offset: usize| {
+            func_ranges
+                .iter()
+                .find(|range| range.start <= offset && offset < range.end)
+        };
+        let mut app_instrs = read_instrs(app_body)?.into_iter().peekable();
+        let mut instrs = vec![];
+        let mut changed = false;
+        for (offset, op) in read_instrs(body)? {
+            if let Some(range) = range_of(offset) {
+                changed = true;
+                instrs.push(InstrDiff {
+                    change: Change::Injected,
+                    offset: Some(offset),
+                    text: format!("{op:?}"),
+                    injected_ref: injected_ref(func_idx, &op),
+                    script_path: range.script.clone(),
+                    script_loc: Some(range.span.start),
+                });
+                continue;
+            }
+            // the instructions of the app that are missing were removed
+            while let Some((_, app_op)) =
+                app_instrs.next_if(|(_, app_op)| mnemonic(app_op) != mnemonic(&op))
+            {
+                changed = true;
+                instrs.push(InstrDiff {
+                    change: Change::Removed,
+                    offset: None,
+                    text: format!("{app_op:?}"),
+                    injected_ref: None,
+                    script_path: None,
+                    script_loc: None,
+                });
+            }
+            app_instrs.next();
+            let injected_ref = injected_ref(func_idx, &op);
+            changed |= injected_ref.is_some();
+            instrs.push(InstrDiff {
+                change: Change::Same,
+                offset: Some(offset),
+                text: format!("{op:?}"),
+                injected_ref,
+                script_path: None,
+                script_loc: None,
+            });
+        }
+        for (_, app_op) in app_instrs {
+            changed = true;
+            instrs.push(InstrDiff {
+                change: Change::Removed,
+                offset: None,
+                text: format!("{app_op:?}"),
+                injected_ref: None,
+                script_path: None,
+                script_loc: None,
+            });
+        }
+        if changed {
+            changed_funcs.push(FuncDiff {
+                func_idx,
+                name,
+                app_func_idx: Some(*app_func_idx),
+                instrs,
+            });
+        }
+    }
+
+    let mut injected_globals: Vec<(u32, Option<String>)> = metadata
+        .injected_globals
+        .iter()
+        .map(|idx| (*idx, names.globals.get(idx).cloned()))
+        .collect();
+    injected_globals.sort();
+    Ok(ModuleDiff {
+        injected_funcs,
+        injected_globals,
+        changed_funcs,
+    })
+}
+
+impl ModuleDiff {
+    pub fn print(&self, buffer: &mut Buffer) {
+        let with_name = |idx: u32, name: &Option<String>| match name {
+            Some(name) => format!("{idx} `{name}`"),
+            None => idx.to_string(),
+        };
+        white(true, "Injected functions:\n".to_string(), buffer);
+        for func in self.injected_funcs.iter() {
+            green(
+                false,
+                format!("  + {}\n", with_name(func.func_idx, &func.name)),
+                buffer,
+            );
+        }
+        white(true, "Injected globals:\n".to_string(), buffer);
+        for (idx, name) in self.injected_globals.iter() {
+            green(false, format!("  + {}\n", with_name(*idx, name)), buffer);
+        }
+
+        for func in self.changed_funcs.iter() {
+            white(
+                true,
+                format!("\nFunction {}", with_name(func.func_idx, &func.name)),
+                buffer,
+            );
+            if let Some(app_func_idx) = func.app_func_idx {
+                grey(
+                    false,
+                    format!(" (function {app_func_idx} of the app)"),
+                    buffer,
+                );
+            }
+            white(false, "\n".to_string(), buffer);
+            for instr in func.instrs.iter() {
+                let offset = match instr.offset {
+                    Some(offset) => format!("{offset:#08x}"),
+                    None => " ".repeat(8),
+                };
+                let line = format!("{offset}  {}", instr.text);
+                match instr.change {
+                    Change::Same => white(false, format!("    {line}"), buffer),
+                    Change::Injected => green(false, format!("  + {line}"), buffer),
+                    Change::Removed => red(false, format!("  - {line}"), buffer),
+                }
+                if let Some(injected_ref) = &instr.injected_ref {
+                    yellow(true, format!("  ;; {injected_ref}"), buffer);
+                }
+                if let Some((line, col)) = instr.script_loc {
+                    let script_path = instr.script_path.as_deref().unwrap_or("<script>");
+                    grey(false, format!("  ;; {script_path}:{line}:{col}"), buffer);
+                }
+                white(false, "\n".to_string(), buffer);
+            }
+        }
+    }
+}
@@ -0,0 +1,468 @@
+use crate::common::error::{ErrorGen, WhammError};
+use crate::common::func_filter::FuncFilter;
+use crate::generator::counters::CounterIncr;
+use crate::generator::emitters::wasm_rewriting::{
+    define_compiler_var, AppItems, EmittingInstrTracker, InstrIter, ProbeLoc, WasmRewritingEmitter,
+};
+use crate::generator::emitters::{AppLocations, Emitter};
+use crate::parser::rules::wasm::BytecodeEventKind;
+use crate::parser::types::{DataType, Expr, Fn, Statement, Value};
+use crate::verifier::types::{Record, SymbolTable, VarAddr};
+use log::{info, warn};
+use std::collections::HashSet;
+use std::path::PathBuf;
+use walrus::ir::{Instr, InstrLocId};
+use walrus::FunctionBuilder;
+
+const MONITOR_UNEXPECTED_ERR_MSG: &str =
+    "MonitorModuleEmitter: Looks like you've found a bug...please report this behavior!";
+
+/// A location in the app to attach a probe callback to
+struct MonitorSite {
+    func_idx: u32,
+    /// The byte offset of the instruction from the start of the original app binary (see
+    /// `Emitter::curr_site`)
+    offset: usize,
+    mode: String,
+    export_name: String,
+}
+
+/// Emits the instrumentation as a separate Wasm monitor module for engines with direct
+/// instrumentation support (e.g. Wizard). The app is left byte-for-byte untouched.
+///
+/// Each matched location gets its own callback function exported by the monitor.
+/// The callback takes the arguments of the instruction of interest as parameters.
+/// The manifest lists where the engine should attach each callback: the function's index
+/// and the byte offset of the instruction from the start of the app binary, as everywhere else
+/// whamm! refers to a site.
+pub struct MonitorModuleEmitter {
+    pub app_wasm: walrus::Module,
+
+    // whamm! AST traversal bookkeeping
+    locations: AppLocations,
+    instr_iter: InstrIter,
+    /// Emits the probe logic into the callbacks of the monitor module
+    monitor: WasmRewritingEmitter,
+    /// Whether the callback for the current location fires after the instruction of interest
+    is_after: bool,
+
+    sites: Vec<MonitorSite>,
+}
+impl MonitorModuleEmitter {
+    pub fn new(app_wasm: walrus::Module, app_bytes: &[u8], table: SymbolTable) -> Self {
+        let locations = AppLocations::new(&app_wasm, app_bytes);
+
+        // the monitor keeps its data (e.g. string literals) in its own memory
+        let mut monitor_wasm = walrus::Module::default();
+        let mem_id = monitor_wasm.memories.add_local(false, 1, None);
+        monitor_wasm.exports.add("memory", mem_id);
+        let monitor = WasmRewritingEmitter::with_memory(monitor_wasm, table, mem_id, true);
+
+        Self {
+            app_wasm,
+            locations,
+            instr_iter: InstrIter::new(),
+            monitor,
+            is_after: false,
+            sites: vec![],
+        }
+    }
+
+    /// The manifest of where to attach the probe callbacks, as JSON. The sites are described
+    /// with the same fields as in the `whamm` section.
+    pub fn manifest(&self) -> String {
+        let probes: Vec<serde_json::Value> = self
+            .sites
+            .iter()
+            .map(|site| {
+                serde_json::json!({
+                    "func_index": site.func_idx,
+                    "offset": site.offset,
+                    "mode": site.mode,
+                    "callback": site.export_name,
+                })
+            })
+            .collect();
+        let manifest = serde_json::json!({
+            "version": env!("CARGO_PKG_VERSION"),
+            "probes": probes,
+        });
+        serde_json::to_string_pretty(&manifest).unwrap()
+    }
+
+    pub fn monitor_wasm(&mut self) -> &mut walrus::Module {
+        &mut self.monitor.app_wasm
+    }
+
+    /// Make sure there is a callback to emit the probe logic for the current location into.
+    fn ensure_callback(&mut self) -> bool {
+        if self.monitor.emitting_instr.is_some() {
+            return true;
+        }
+        let Some(curr_loc) = self.instr_iter.curr() else {
+            return false;
+        };
+
+        // the callback's params are the arguments of the instruction of interest
+        let monitor_wasm = &mut self.monitor.app_wasm;
+        let mut args = vec![];
+        let mut arg_recs = vec![];
+        for (num, param_ty) in curr_loc.instr_params.iter().enumerate() {
+            let arg_local_id = monitor_wasm.locals.add(*param_ty);
+            args.push(arg_local_id);
+
+            // place in symbol table with var addr for future reference
+            let arg_name = format!("arg{}", num);
+            let id = self.monitor.table.put(
+                arg_name.clone(),
+                Record::Var {
+                    ty: DataType::I32, // we only support integers right now.
+                    name: arg_name.clone(),
+                    value: None,
+                    is_comp_provided: false,
+                    addr: Some(VarAddr::Local { addr: arg_local_id }),
+                    loc: None,
+                },
+            );
+            arg_recs.push((arg_name, id));
+        }
+        let callback = FunctionBuilder::new(&mut monitor_wasm.types, &curr_loc.instr_params, &[]);
+        let callback_id = callback.finish(args, &mut monitor_wasm.funcs);
+        let entry = monitor_wasm
+            .funcs
+            .get(callback_id)
+            .kind
+            .unwrap_local()
+            .entry_block();
+
+        // point the monitor's emitter at the (empty) body of the callback
+        self.monitor.instr_iter = InstrIter {
+            instr_locs: vec![ProbeLoc {
+                wasm_func_id: callback_id,
+                instr_seq_id: entry,
+                index: 0,
+                event: curr_loc.event,
+                // there is no instruction of interest in the callback, it is never emitted
+                instr: Instr::Unreachable(walrus::ir::Unreachable {}),
+                instr_loc: InstrLocId::default(),
+                next_loc: InstrLocId::default(),
+                func_info: None,
+                instr_params: curr_loc.instr_params.clone(),
+                instr_created_args: arg_recs,
+                instr_alt_call: None,
+            }],
+            curr_loc: 0,
+            func_filter: FuncFilter::default(),
+            jobs: 1,
+        };
+        self.monitor.emitting_instr = Some(EmittingInstrTracker {
+            orig_instr_idx: 0usize,
+            curr_seq_id: entry,
+            curr_idx: 0usize,
+            main_seq_id: entry,
+            main_idx: 0usize,
+            outer_seq_id: None,
+            outer_idx: None,
+            then_seq_id: None,
+            then_idx: None,
+            else_seq_id: None,
+            else_idx: None,
+        });
+        true
+    }
+
+    /// Finish the callback for the current location and save it off to the manifest
+    fn finish_callback(&mut self) {
+        let is_after = self.is_after;
+        self.is_after = false;
+        if self.monitor.emitting_instr.take().is_none() {
+            return;
+        }
+        let Some(callback_id) = self
+            .monitor
+            .instr_iter
+            .curr()
+            .map(|callback| callback.wasm_func_id)
+        else {
+            return;
+        };
+        let monitor_wasm = &mut self.monitor.app_wasm;
+        let callback = monitor_wasm.funcs.get(callback_id).kind.unwrap_local();
+        if callback.block(callback.entry_block()).is_empty() {
+            // nothing to do when the probe fires
+            monitor_wasm.funcs.delete(callback_id);
+            return;
+        }
+
+        let Some(curr_loc) = self.instr_iter.curr() else {
+            return;
+        };
+        let (func_idx, offset) = match self.locations.func_idx(&curr_loc.wasm_func_id) {
+            Some(func_idx) if !curr_loc.instr_loc.is_default() => {
+                (func_idx, curr_loc.instr_loc.data() as usize)
+            }
+            _ => {
+                warn!(
+                    "Could not find the original location of `{}`, skipping probe.",
+                    curr_loc.event.name()
+                );
+                monitor_wasm.funcs.delete(callback_id);
+                return;
+            }
+        };
+
+        let export_name = format!("probe_{}", self.sites.len());
+        monitor_wasm.exports.add(&export_name, callback_id);
+        self.sites.push(MonitorSite {
+            func_idx,
+            offset,
+            mode: if is_after { "after" } else { "before" }.to_string(),
+            export_name,
+        });
+    }
+
+    fn in_branch(&self) -> bool {
+        match &self.monitor.emitting_instr {
+            Some(tracker) => tracker.outer_seq_id.is_some(),
+            None => false,
+        }
+    }
+}
+
+impl Emitter for MonitorModuleEmitter {
+    fn enter_scope(&mut self) -> Result<(), Box<WhammError>> {
+        self.monitor.enter_scope()
+    }
+    fn enter_named_scope(&mut self, scope_name: &str) -> bool {
+        self.monitor.enter_named_scope(scope_name)
+    }
+    fn exit_scope(&mut self) -> Result<(), Box<WhammError>> {
+        self.monitor.exit_scope()
+    }
+    fn reset_children(&mut self) {
+        self.monitor.reset_children();
+    }
+
+    fn add_func_filter(&mut self, filter: FuncFilter) {
+        self.instr_iter.func_filter.extend(filter);
+    }
+
+    fn set_jobs(&mut self, jobs: usize) {
+        self.instr_iter.jobs = jobs;
+    }
+
+    fn set_inline_threshold(&mut self, _threshold: Option<usize>) {
+        // the probes' bodies are already functions of the monitor
+    }
+
+    fn coalesce_counters(&mut self, _counters: HashSet<String>) {
+        // the engine runs the probes, nothing is injected into the app's basic blocks
+    }
+
+    fn init_instr_iter(
+        &mut self,
+        instrs_of_interest: &HashSet<BytecodeEventKind>,
+    ) -> Result<(), Box<WhammError>> {
+        // nothing was injected into the app yet
+        self.instr_iter.init(
+            &self.app_wasm,
+            instrs_of_interest,
+            &AppItems::new(&self.app_wasm),
+        );
+        Ok(())
+    }
+
+    fn has_next_instr(&self) -> bool {
+        self.instr_iter.has_next()
+    }
+
+    fn init_first_instr(&mut self) -> bool {
+        self.monitor.emitting_instr = None;
+        self.instr_iter.curr().is_some()
+    }
+
+    fn next_instr(&mut self) -> bool {
+        self.finish_callback();
+        if self.instr_iter.has_next() {
+            return self.instr_iter.next().is_some();
+        }
+        false
+    }
+
+    fn curr_instr_type(&mut self) -> &'static str {
+        if let Some(instr) = self.instr_iter.curr() {
+            return instr.event.name();
+        }
+        unreachable!()
+    }
+
+    fn curr_site(&self) -> Option<(u32, usize)> {
+        let curr_loc = self.instr_iter.curr()?;
+        let func_idx = self.locations.func_idx(&curr_loc.wasm_func_id)?;
+        Some((func_idx, curr_loc.instr_loc.data() as usize))
+    }
+
+    fn incr_loc_pointer(&mut self) {
+        self.is_after = true;
+    }
+
+    fn has_params(&mut self) -> Result<bool, Box<WhammError>> {
+        if let Some(curr_instr) = self.instr_iter.curr() {
+            return Ok(!curr_instr.instr_params.is_empty());
+        }
+        Err(Box::new(ErrorGen::get_unexpected_error(
+            true,
+            Some(format!(
+                "{MONITOR_UNEXPECTED_ERR_MSG} \
+        Something went wrong when trying to access the current instruction."
+            )),
+            None,
+        )))
+    }
+
+    fn save_params(&mut self) -> bool {
+        // The params are passed to the callback by the engine
+        true
+    }
+
+    fn emit_params(&mut self) -> Result<bool, Box<WhammError>> {
+        // The params are never removed from the operand stack
+        Ok(true)
+    }
+
+    fn define_compiler_var(
+        &mut self,
+        context: &str,
+        var_name: &str,
+    ) -> Result<bool, Box<WhammError>> {
+        define_compiler_var(
+            &mut self.monitor.table,
+            self.instr_iter.curr(),
+            context,
+            var_name,
+        )
+    }
+
+    fn fold_expr(&mut self, expr: &mut Expr) -> bool {
+        self.monitor.fold_expr(expr)
+    }
+
+    fn emit_expr(&mut self, expr: &mut Expr) -> Result<bool, Box<WhammError>> {
+        self.ensure_callback();
+        self.monitor.emit_expr(expr)
+    }
+
+    fn emit_fn(&mut self, context: &str, f: &Fn) -> Result<bool, Box<WhammError>> {
+        self.monitor.emit_fn(context, f)
+    }
+
+    fn emit_formal_param(&mut self, param: &(Expr, DataType)) -> Result<bool, Box<WhammError>> {
+        self.monitor.emit_formal_param(param)
+    }
+
+    fn emit_global(
+        &mut self,
+        name: String,
+        ty: DataType,
+        val: &Option<Value>,
+    ) -> Result<bool, Box<WhammError>> {
+        self.monitor.emit_global(name, ty, val)
+    }
+
+    fn remove_orig(&mut self) -> bool {
+        warn!("Engine probes cannot replace an instruction, `alt` probes are not supported when emitting a monitor module.");
+        false
+    }
+
+    fn emit_orig(&mut self) -> bool {
+        // The original instruction is never removed
+        true
+    }
+
+    fn emit_if(&mut self) -> bool {
+        self.ensure_callback() && self.monitor.emit_if()
+    }
+
+    fn emit_if_else(&mut self) -> bool {
+        self.ensure_callback() && self.monitor.emit_if_else()
+    }
+
+    fn emit_condition(&mut self) -> bool {
+        self.monitor.emit_condition()
+    }
+
+    fn emit_consequent(&mut self) -> bool {
+        self.monitor.emit_consequent()
+    }
+
+    fn emit_alternate(&mut self) -> bool {
+        self.monitor.emit_alternate()
+    }
+
+    fn finish_branch(&mut self) -> bool {
+        let is_success = self.monitor.finish_branch();
+        // the probe's logic is complete
+        self.finish_callback();
+        is_success
+    }
+
+    fn emit_global_stmts(&mut self, stmts: &mut Vec<Statement>) -> Result<bool, Box<WhammError>> {
+        // Global statements are run when the monitor is instantiated
+        let monitor_wasm = &mut self.monitor.app_wasm;
+        if monitor_wasm.start.is_none() {
+            let start = FunctionBuilder::new(&mut monitor_wasm.types, &[], &[]);
+            monitor_wasm.start = Some(start.finish(vec![], &mut monitor_wasm.funcs));
+        }
+        let is_success = self.monitor.emit_global_stmts(stmts)?;
+        self.monitor.emitting_instr = None;
+        Ok(is_success)
+    }
+
+    fn emit_body(&mut self, body: &mut Vec<Statement>) -> Result<bool, Box<WhammError>> {
+        self.ensure_callback();
+        let is_success = self.monitor.emit_body(body)?;
+        if !self.in_branch() {
+            // not in a branch, the probe's logic is complete
+            self.finish_callback();
+        }
+        Ok(is_success)
+    }
+
+    fn emit_counter_incrs(&mut self, _incrs: &[CounterIncr]) -> Result<bool, Box<WhammError>> {
+        Ok(false)
+    }
+
+    fn has_alt_call(&mut self) -> bool {
+        info!("Alternate calls are not supported when emitting a monitor module.");
+        false
+    }
+
+    fn emit_alt_call(&mut self) -> Result<bool, Box<WhammError>> {
+        Ok(false)
+    }
+
+    fn emit_stmt(&mut self, stmt: &mut Statement) -> Result<bool, Box<WhammError>> {
+        self.ensure_callback();
+        self.monitor.emit_stmt(stmt)
+    }
+
+    fn dump_to_file(&mut self, output_wasm_path: String) -> Result<bool, Box<WhammError>> {
+        self.finish_callback();
+        self.monitor.dump_to_file(output_wasm_path.clone())?;
+
+        let manifest_path = PathBuf::from(&output_wasm_path).with_extension("json");
+        match std::fs::write(&manifest_path, self.manifest()) {
+            Ok(..) => Ok(true),
+            Err(err) => Err(Box::new(ErrorGen::get_unexpected_error(
+                true,
+                Some(format!(
+                    "{MONITOR_UNEXPECTED_ERR_MSG} \
+                Failed to dump probe manifest to {} from error: {}",
+                    manifest_path.display(),
+                    err
+                )),
+                None,
+            ))),
+        }
+    }
+}
@@ -0,0 +1,869 @@
+use crate::common::error::{ErrorGen, WhammError};
+use crate::common::func_filter::FuncFilter;
+use crate::generator::counters::CounterIncr;
+use crate::generator::emitters::wasm_rewriting::{
+    define_compiler_var, is_print_call, print_arg_ty, print_parts, AppItems, InstrIter, PrintPart,
+    StmtVars,
+};
+use crate::generator::emitters::{AppLocations, Emitter};
+use crate::generator::types::ExprFolder;
+use crate::parser::rules::wasm::BytecodeEventKind;
+use crate::parser::types::{BinOp, DataType, Expr, Fn, Statement, UnOp, Value};
+use crate::verifier::types::{Record, SymbolTable};
+use log::{info, warn};
+use std::collections::HashSet;
+use walrus::ValType;
+
+const VIRGIL_UNEXPECTED_ERR_MSG: &str =
+    "VirgilEmitter: Looks like you've found a bug...please report this behavior!";
+
+fn data_type_to_virgil(ty: &DataType) -> Result<String, Box<WhammError>> {
+    let ty = match ty {
+        DataType::I32 => "int".to_string(),
+        DataType::U32 => "u32".to_string(),
+        DataType::Boolean => "bool".to_string(),
+        DataType::Str => "string".to_string(),
+        DataType::Tuple { ty_info } => {
+            let mut tys = vec![];
+            for ty in ty_info.iter() {
+                tys.push(data_type_to_virgil(ty)?);
+            }
+            format!("({})", tys.join(", "))
+        }
+        DataType::Null | DataType::Map { .. } | DataType::AssumeGood => {
+            return Err(Box::new(ErrorGen::get_unexpected_error(
+                false,
+                Some(format!(
+                    "VirgilEmitter: Data type is not supported when emitting Virgil: {:?}",
+                    ty
+                )),
+                None,
+            )));
+        }
+    };
+    Ok(ty)
+}
+
+fn binop_to_virgil(op: &BinOp) -> &'static str {
+    match op {
+        BinOp::And => "&&",
+        BinOp::Or => "||",
+        BinOp::EQ => "==",
+        BinOp::NE => "!=",
+        BinOp::GE => ">=",
+        BinOp::GT => ">",
+        BinOp::LE => "<=",
+        BinOp::LT => "<",
+        BinOp::Add => "+",
+        BinOp::Subtract => "-",
+        BinOp::Multiply => "*",
+        BinOp::Divide => "/",
+        BinOp::Modulo => "%",
+    }
+}
+
+fn value_to_virgil(val: &Value) -> Result<String, Box<WhammError>> {
+    let val = match val {
+        Value::Integer { val, .. } => val.to_string(),
+        Value::Str { val, .. } => format!("\"{}\"", val.escape_default()),
+        Value::Tuple { vals, .. } => {
+            let mut vals_str = vec![];
+            for val in vals.iter() {
+                vals_str.push(expr_to_virgil(val)?);
+            }
+            format!("({})", vals_str.join(", "))
+        }
+        Value::Boolean { val, .. } => val.to_string(),
+    };
+    Ok(val)
+}
+
+fn expr_to_virgil(expr: &Expr) -> Result<String, Box<WhammError>> {
+    let expr = match expr {
+        Expr::UnOp { op, expr, .. } => match op {
+            UnOp::Not => format!("!({})", expr_to_virgil(expr)?),
+        },
+        Expr::Ternary {
+            cond, conseq, alt, ..
+        } => format!(
+            "if({}, {}, {})",
+            expr_to_virgil(cond)?,
+            expr_to_virgil(conseq)?,
+            expr_to_virgil(alt)?
+        ),
+        Expr::BinOp { lhs, op, rhs, .. } => format!(
+            "({} {} {})",
+            expr_to_virgil(lhs)?,
+            binop_to_virgil(op),
+            expr_to_virgil(rhs)?
+        ),
+        Expr::Call {
+            fn_target, args, ..
+        } => {
+            let fn_name = match &**fn_target {
+                Expr::VarId { name, .. } => name.clone(),
+                _ => {
+                    return Err(Box::new(ErrorGen::get_unexpected_error(
+                        true,
+                        Some(format!(
+                            "{VIRGIL_UNEXPECTED_ERR_MSG} \
+                            Expected VarId as the target of a call."
+                        )),
+                        None,
+                    )));
+                }
+            };
+            // provided functions can inspect the app's state through the probe's location
+            let mut args_str = vec!["loc".to_string()];
+            if let Some(args) = args {
+                for arg in args.iter() {
+                    args_str.push(expr_to_virgil(arg)?);
+                }
+            }
+            format!("{}({})", fn_name, args_str.join(", "))
+        }
+        Expr::VarId { name, .. } => name.clone(),
+        Expr::Primitive { val, .. } => value_to_virgil(val)?,
+    };
+    Ok(expr)
+}
+
+fn indent(lines: Vec<String>) -> Vec<String> {
+    lines.into_iter().map(|line| format!("\t{line}")).collect()
+}
+
+/// Where the VirgilEmitter is currently emitting code into
+#[derive(PartialEq)]
+enum VirgilTarget {
+    Main,
+    Cond,
+    Then,
+    Else,
+}
+
+struct VirgilBranch {
+    cond: String,
+    then_lines: Vec<String>,
+    else_lines: Option<Vec<String>>,
+}
+
+/// The body of the `fire` method of the Probe being emitted at the current location
+struct VirgilProbeBody {
+    lines: Vec<String>,
+    branch: Option<VirgilBranch>,
+    target: VirgilTarget,
+    /// Whether the probe should fire after the instruction of interest
+    is_after: bool,
+    /// The variables used by the predicate and body, to bind the arguments they use
+    vars: StmtVars,
+}
+impl VirgilProbeBody {
+    fn new() -> Self {
+        Self {
+            lines: vec![],
+            branch: None,
+            target: VirgilTarget::Main,
+            is_after: false,
+            vars: StmtVars::default(),
+        }
+    }
+}
+
+/// A location in the app to insert a Probe at
+struct VirgilSite {
+    func_idx: u32,
+    pc: u32,
+    probe_class: usize,
+}
+
+/// Emits the instrumentation as the Virgil source of a Wizard engine monitor.
+/// Rather than rewriting the app, the monitor inserts a Probe at each location matched
+/// by the script's probes when the app's module is parsed by the engine.
+pub struct VirgilEmitter {
+    pub app_wasm: walrus::Module,
+    pub table: SymbolTable,
+
+    // whamm! AST traversal bookkeeping
+    locations: AppLocations,
+    instr_iter: InstrIter,
+    probe_body: Option<VirgilProbeBody>,
+
+    // the generated monitor
+    globals: Vec<String>,
+    global_names: Vec<String>,
+    fns: Vec<String>,
+    init_stmts: Vec<String>,
+    /// The body of the `fire` method of each generated Probe class
+    probe_classes: Vec<Vec<String>>,
+    sites: Vec<VirgilSite>,
+
+    fn_providing_contexts: Vec<String>,
+}
+impl VirgilEmitter {
+    pub fn new(app_wasm: walrus::Module, app_bytes: &[u8], table: SymbolTable) -> Self {
+        let locations = AppLocations::new(&app_wasm, app_bytes);
+        Self {
+            app_wasm,
+            table,
+            locations,
+            instr_iter: InstrIter::new(),
+            probe_body: None,
+            globals: vec![],
+            global_names: vec![],
+            fns: vec![],
+            init_stmts: vec![],
+            probe_classes: vec![],
+            sites: vec![],
+            fn_providing_contexts: vec!["whamm".to_string()],
+        }
+    }
+
+    /// Generate the Virgil source of the monitor
+    pub fn to_virgil(&self) -> String {
+        let mut out = vec![
+            "// Generated by whamm!, do not edit.".to_string(),
+            "// Run the app in Wizard with `--monitors=whamm` to apply the instrumentation."
+                .to_string(),
+            "def monitor_ = MonitorRegistry.add(".to_string(),
+            "\t\"whamm\", \"Instrumentation generated by whamm!\",".to_string(),
+            "\tWhammMonitor.new());".to_string(),
+            "".to_string(),
+        ];
+        if !self.globals.is_empty() {
+            out.extend(self.globals.clone());
+            out.push("".to_string());
+        }
+        for f in self.fns.iter() {
+            out.push(f.clone());
+            out.push("".to_string());
+        }
+
+        out.push("class WhammMonitor extends Monitor {".to_string());
+        out.push("\tdef onParse(module: Module) {".to_string());
+        out.extend(indent(indent(self.init_stmts.clone())));
+        for site in self.sites.iter() {
+            out.push(format!(
+                "\t\tInstrumentation.insertLocalProbe(module, {}, {}, WhammProbe{}.new());",
+                site.func_idx, site.pc, site.probe_class
+            ));
+        }
+        out.push("\t}".to_string());
+        out.push("}".to_string());
+
+        for (i, body) in self.probe_classes.iter().enumerate() {
+            out.push("".to_string());
+            out.push(format!("class WhammProbe{i} extends Probe {{"));
+            out.push("\tdef fire(loc: DynamicLoc) -> Resumption {".to_string());
+            out.extend(indent(indent(body.clone())));
+            out.push("\t\treturn Resumption.Continue;".to_string());
+            out.push("\t}".to_string());
+            out.push("}".to_string());
+        }
+        out.push("".to_string());
+        out.join("\n")
+    }
+
+    fn push_lines(&mut self, lines: Vec<String>) -> bool {
+        let body = self.probe_body.get_or_insert_with(VirgilProbeBody::new);
+        match (&body.target, &mut body.branch) {
+            (VirgilTarget::Then, Some(branch)) => branch.then_lines.extend(lines),
+            (
+                VirgilTarget::Else,
+                Some(VirgilBranch {
+                    else_lines: Some(else_lines),
+                    ..
+                }),
+            ) => else_lines.extend(lines),
+            (VirgilTarget::Main, _) => body.lines.extend(lines),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Finish the Probe for the current location and save it off to be inserted into the app
+    fn finish_probe(&mut self) {
+        let Some(body) = self.probe_body.take() else {
+            return;
+        };
+        if body.lines.is_empty() {
+            return;
+        }
+        let Some(curr_loc) = self.instr_iter.curr() else {
+            return;
+        };
+        let (func_idx, pc) = match (
+            self.locations.func_idx(&curr_loc.wasm_func_id),
+            self.locations.pc(
+                &curr_loc.wasm_func_id,
+                if body.is_after {
+                    &curr_loc.next_loc
+                } else {
+                    &curr_loc.instr_loc
+                },
+            ),
+        ) {
+            (Some(func_idx), Some(pc)) => (func_idx, pc),
+            _ => {
+                warn!(
+                    "Could not find the original location of `{}`, skipping probe.",
+                    curr_loc.event.name()
+                );
+                return;
+            }
+        };
+
+        // bind the arguments of the instruction, they are still on the operand stack
+        // (checked by `check_args`)
+        let mut lines = vec![];
+        let num_params = curr_loc.instr_params.len();
+        let args = body.vars.args();
+        if !args.is_empty() {
+            lines.push("var accessor = loc.frame.getFrameAccessor();".to_string());
+            for num in args {
+                lines.push(format!(
+                    "var arg{num} = Values.unbox_i(accessor.getOperand({}));",
+                    num_params - 1 - num
+                ));
+            }
+        }
+        lines.extend(body.lines);
+
+        // reuse Probe classes with identical bodies
+        let probe_class = match self.probe_classes.iter().position(|other| *other == lines) {
+            Some(idx) => idx,
+            None => {
+                self.probe_classes.push(lines);
+                self.probe_classes.len() - 1
+            }
+        };
+        self.sites.push(VirgilSite {
+            func_idx,
+            pc,
+            probe_class,
+        });
+    }
+
+    /// Make sure the arguments used by the probe at the current location can be read off of the
+    /// operand stack when it fires
+    fn check_args(&self) -> Result<(), Box<WhammError>> {
+        let (Some(body), Some(curr_loc)) = (&self.probe_body, self.instr_iter.curr()) else {
+            return Ok(());
+        };
+        let args = body.vars.args();
+        let Some(num) = args.first() else {
+            return Ok(());
+        };
+        let event = curr_loc.event.name();
+        if body.is_after {
+            return Err(Box::new(ErrorGen::get_unexpected_error(
+                false,
+                Some(format!(
+                    "VirgilEmitter: The arguments of `{event}` have been consumed by the time an \
+                    `after` probe fires, `arg{num}` is not available when emitting Virgil."
+                )),
+                None,
+            )));
+        }
+        for num in args {
+            match curr_loc.instr_params.get(num) {
+                Some(ValType::I32) => {}
+                Some(ty) => {
+                    return Err(Box::new(ErrorGen::get_unexpected_error(
+                        false,
+                        Some(format!(
+                            "VirgilEmitter: `arg{num}` of `{event}` is of type {ty}, only i32 \
+                            arguments are supported when emitting Virgil."
+                        )),
+                        None,
+                    )));
+                }
+                None => {
+                    return Err(Box::new(ErrorGen::get_unexpected_error(
+                        false,
+                        Some(format!(
+                            "VirgilEmitter: `{event}` has no argument `arg{num}`."
+                        )),
+                        None,
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn stmt_to_virgil(&mut self, stmt: &mut Statement) -> Result<Vec<String>, Box<WhammError>> {
+        let lines = match stmt {
+            Statement::Decl { ty, var_id, .. } => {
+                vec![format!(
+                    "var {}: {};",
+                    expr_to_virgil(var_id)?,
+                    data_type_to_virgil(ty)?
+                )]
+            }
+            Statement::Assign { var_id, expr, .. } => {
+                let folded_expr = ExprFolder::fold_expr(expr, &self.table);
+                if let Expr::VarId { name, .. } = var_id {
+                    if let Some(rec_id) = self.table.lookup(name).copied() {
+                        if let Some(Record::Var {
+                            value,
+                            is_comp_provided: true,
+                            ..
+                        }) = self.table.get_record_mut(&rec_id)
+                        {
+                            // compiler-provided variables only exist at compile time
+                            if let Expr::Primitive { val, .. } = &folded_expr {
+                                *value = Some(val.clone());
+                            }
+                            return Ok(vec![]);
+                        }
+                    }
+                }
+                vec![format!(
+                    "{} = {};",
+                    expr_to_virgil(var_id)?,
+                    expr_to_virgil(&folded_expr)?
+                )]
+            }
+            Statement::Expr { expr, .. } => {
+                let folded_expr = ExprFolder::fold_expr(expr, &self.table);
+                if let Expr::Call { args, .. } = &folded_expr {
+                    if is_print_call(&folded_expr) {
+                        return self.print_to_virgil(args.as_deref());
+                    }
+                }
+                vec![format!("{};", expr_to_virgil(&folded_expr)?)]
+            }
+            Statement::If {
+                cond, conseq, alt, ..
+            } => {
+                let folded_cond = ExprFolder::fold_expr(cond, &self.table);
+                let mut lines = vec![format!("if ({}) {{", expr_to_virgil(&folded_cond)?)];
+                for stmt in conseq.stmts.iter_mut() {
+                    let stmt_lines = self.stmt_to_virgil(stmt)?;
+                    lines.extend(indent(stmt_lines));
+                }
+                if alt.stmts.is_empty() {
+                    lines.push("}".to_string());
+                } else {
+                    lines.push("} else {".to_string());
+                    for stmt in alt.stmts.iter_mut() {
+                        let stmt_lines = self.stmt_to_virgil(stmt)?;
+                        lines.extend(indent(stmt_lines));
+                    }
+                    lines.push("}".to_string());
+                }
+                lines
+            }
+            Statement::Return { loc, .. } => {
+                return Err(Box::new(ErrorGen::get_type_check_error_from_loc(
+                    false,
+                    "Return statements are not supported in probe bodies".to_string(),
+                    loc,
+                )));
+            }
+        };
+        Ok(lines)
+    }
+
+    fn print_to_virgil(&self, args: Option<&[Box<Expr>]>) -> Result<Vec<String>, Box<WhammError>> {
+        let mut lines = vec![];
+        for part in print_parts(args, &self.table)? {
+            lines.push(match part {
+                PrintPart::Text(text) => format!("System.puts(\"{}\");", text.escape_default()),
+                PrintPart::Arg(arg) => {
+                    let arg_str = expr_to_virgil(&arg)?;
+                    match print_arg_ty(&arg, &self.table) {
+                        DataType::Str => format!("System.puts({});", arg_str),
+                        DataType::Boolean => {
+                            format!("System.puts(if({}, \"true\", \"false\"));", arg_str)
+                        }
+                        DataType::U32 => format!("System.puti(int.view({}));", arg_str),
+                        _ => format!("System.puti({});", arg_str),
+                    }
+                }
+            });
+        }
+        Ok(lines)
+    }
+
+    fn emit_virgil_strcmp_fn(&mut self) {
+        self.fns.push(
+            [
+                "// Compares a string in the app's memory, (address, length), with `expected`.",
+                "def strcmp(loc: DynamicLoc, mem_str: (int, int), expected: string) -> bool {",
+                "\tvar memory = loc.func.instance.memories[0];",
+                "\tif (mem_str.1 != expected.length) return false;",
+                "\tfor (i < expected.length) {",
+                "\t\tvar b = memory.read1(0, u32.view(mem_str.0 + i));",
+                "\t\tif (!b.ok() || b.result != expected[i]) return false;",
+                "\t}",
+                "\treturn true;",
+                "}",
+            ]
+            .join("\n"),
+        );
+    }
+}
+
+impl Emitter for VirgilEmitter {
+    fn enter_scope(&mut self) -> Result<(), Box<WhammError>> {
+        self.table.enter_scope()
+    }
+    fn enter_named_scope(&mut self, scope_name: &str) -> bool {
+        self.table.enter_named_scope(scope_name)
+    }
+    fn exit_scope(&mut self) -> Result<(), Box<WhammError>> {
+        self.table.exit_scope()
+    }
+    fn reset_children(&mut self) {
+        self.table.reset_children();
+    }
+
+    fn add_func_filter(&mut self, filter: FuncFilter) {
+        self.instr_iter.func_filter.extend(filter);
+    }
+
+    fn set_jobs(&mut self, jobs: usize) {
+        self.instr_iter.jobs = jobs;
+    }
+
+    fn set_inline_threshold(&mut self, _threshold: Option<usize>) {
+        // the probes' bodies are already functions of the monitor
+    }
+
+    fn coalesce_counters(&mut self, _counters: HashSet<String>) {
+        // the engine runs the probes, nothing is injected into the app's basic blocks
+    }
+
+    fn init_instr_iter(
+        &mut self,
+        instrs_of_interest: &HashSet<BytecodeEventKind>,
+    ) -> Result<(), Box<WhammError>> {
+        // nothing was injected into the app yet
+        self.instr_iter.init(
+            &self.app_wasm,
+            instrs_of_interest,
+            &AppItems::new(&self.app_wasm),
+        );
+        Ok(())
+    }
+
+    fn has_next_instr(&self) -> bool {
+        self.instr_iter.has_next()
+    }
+
+    fn init_first_instr(&mut self) -> bool {
+        self.probe_body = None;
+        self.instr_iter.curr().is_some()
+    }
+
+    fn next_instr(&mut self) -> bool {
+        self.finish_probe();
+        if self.instr_iter.has_next() {
+            return self.instr_iter.next().is_some();
+        }
+        false
+    }
+
+    fn curr_instr_type(&mut self) -> &'static str {
+        if let Some(instr) = self.instr_iter.curr() {
+            return instr.event.name();
+        }
+        unreachable!()
+    }
+
+    fn curr_site(&self) -> Option<(u32, usize)> {
+        let curr_loc = self.instr_iter.curr()?;
+        let func_idx = self.locations.func_idx(&curr_loc.wasm_func_id)?;
+        Some((func_idx, curr_loc.instr_loc.data() as usize))
+    }
+
+    fn incr_loc_pointer(&mut self) {
+        self.probe_body
+            .get_or_insert_with(VirgilProbeBody::new)
+            .is_after = true;
+    }
+
+    fn has_params(&mut self) -> Result<bool, Box<WhammError>> {
+        if let Some(curr_instr) = self.instr_iter.curr() {
+            return Ok(!curr_instr.instr_params.is_empty());
+        }
+        Err(Box::new(ErrorGen::get_unexpected_error(
+            true,
+            Some(format!(
+                "{VIRGIL_UNEXPECTED_ERR_MSG} \
+        Something went wrong when trying to access the current instruction."
+            )),
+            None,
+        )))
+    }
+
+    fn save_params(&mut self) -> bool {
+        // The params are read off of the operand stack when the probe fires
+        true
+    }
+
+    fn emit_params(&mut self) -> Result<bool, Box<WhammError>> {
+        // The params are never removed from the operand stack
+        Ok(true)
+    }
+
+    fn define_compiler_var(
+        &mut self,
+        context: &str,
+        var_name: &str,
+    ) -> Result<bool, Box<WhammError>> {
+        define_compiler_var(&mut self.table, self.instr_iter.curr(), context, var_name)
+    }
+
+    fn fold_expr(&mut self, expr: &mut Expr) -> bool {
+        *expr = ExprFolder::fold_expr(expr, &self.table);
+        true
+    }
+
+    fn emit_expr(&mut self, expr: &mut Expr) -> Result<bool, Box<WhammError>> {
+        let expr_str = expr_to_virgil(expr)?;
+        let body = self.probe_body.get_or_insert_with(VirgilProbeBody::new);
+        body.vars.add_expr(expr);
+        if let (VirgilTarget::Cond, Some(branch)) = (&body.target, &mut body.branch) {
+            branch.cond = expr_str;
+            return Ok(true);
+        }
+        Ok(self.push_lines(vec![format!("{expr_str};")]))
+    }
+
+    fn emit_fn(&mut self, context: &str, f: &Fn) -> Result<bool, Box<WhammError>> {
+        // figure out if this is a provided fn.
+        if f.is_comp_provided {
+            return if self.fn_providing_contexts.contains(&context.to_string())
+                && f.name.name == "strcmp"
+            {
+                self.emit_virgil_strcmp_fn();
+                Ok(true)
+            } else if self.fn_providing_contexts.contains(&context.to_string())
+                && f.name.name == "print"
+            {
+                // calls to `print` are translated to `System` calls in place
+                Ok(true)
+            } else {
+                Err(Box::new(ErrorGen::get_unexpected_error(
+                    true,
+                    Some(format!(
+                        "{VIRGIL_UNEXPECTED_ERR_MSG} \
+                Provided fn, but could not find a context to provide the definition, context: {}",
+                        context
+                    )),
+                    None,
+                )))
+            };
+        }
+
+        // only when we're supporting user-defined fns in script...
+        Err(Box::new(ErrorGen::get_unexpected_error(
+            false,
+            Some(format!(
+                "VirgilEmitter: User-defined functions are not supported when emitting Virgil: {}",
+                f.name.name
+            )),
+            None,
+        )))
+    }
+
+    fn emit_formal_param(&mut self, _param: &(Expr, DataType)) -> Result<bool, Box<WhammError>> {
+        Err(Box::new(ErrorGen::get_unexpected_error(
+            false,
+            Some(
+                "VirgilEmitter: Function parameters are not supported when emitting Virgil"
+                    .to_string(),
+            ),
+            None,
+        )))
+    }
+
+    fn emit_global(
+        &mut self,
+        name: String,
+        ty: DataType,
+        val: &Option<Value>,
+    ) -> Result<bool, Box<WhammError>> {
+        let ty = data_type_to_virgil(&ty)?;
+        let global = match val {
+            Some(val) => format!("var {name}: {ty} = {};", value_to_virgil(val)?),
+            None => format!("var {name}: {ty};"),
+        };
+        self.globals.push(global);
+        self.global_names.push(name);
+        Ok(true)
+    }
+
+    fn remove_orig(&mut self) -> bool {
+        warn!("Wizard probes cannot replace an instruction, `alt` probes are not supported when emitting Virgil.");
+        false
+    }
+
+    fn emit_orig(&mut self) -> bool {
+        // The original instruction is never removed
+        true
+    }
+
+    fn emit_if(&mut self) -> bool {
+        self.probe_body
+            .get_or_insert_with(VirgilProbeBody::new)
+            .branch = Some(VirgilBranch {
+            cond: "".to_string(),
+            then_lines: vec![],
+            else_lines: None,
+        });
+        true
+    }
+
+    fn emit_if_else(&mut self) -> bool {
+        self.probe_body
+            .get_or_insert_with(VirgilProbeBody::new)
+            .branch = Some(VirgilBranch {
+            cond: "".to_string(),
+            then_lines: vec![],
+            else_lines: Some(vec![]),
+        });
+        true
+    }
+
+    /// Will configure the emitter to emit subsequent expression as the condition of an if or if/else stmt
+    fn emit_condition(&mut self) -> bool {
+        if let Some(body) = &mut self.probe_body {
+            if body.branch.is_some() {
+                body.target = VirgilTarget::Cond;
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Will configure the emitter to emit subsequent statements into the consequent body of an if or if/else stmt
+    fn emit_consequent(&mut self) -> bool {
+        if let Some(body) = &mut self.probe_body {
+            if body.branch.is_some() {
+                body.target = VirgilTarget::Then;
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Will configure the emitter to emit subsequent statements into the alternate body of an if/else stmt
+    fn emit_alternate(&mut self) -> bool {
+        if let Some(body) = &mut self.probe_body {
+            if let Some(VirgilBranch {
+                else_lines: Some(_),
+                ..
+            }) = &body.branch
+            {
+                body.target = VirgilTarget::Else;
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Will configure the emitter to emit subsequent statements in the outer block of some branching logic
+    fn finish_branch(&mut self) -> bool {
+        if let Some(body) = &mut self.probe_body {
+            body.target = VirgilTarget::Main;
+            if let Some(branch) = body.branch.take() {
+                if branch.then_lines.is_empty()
+                    && branch.else_lines.as_ref().map_or(true, |l| l.is_empty())
+                {
+                    // nothing to do when the probe fires
+                    return true;
+                }
+                body.lines.push(format!("if ({}) {{", branch.cond));
+                body.lines.extend(indent(branch.then_lines));
+                if let Some(else_lines) = branch.else_lines {
+                    body.lines.push("} else {".to_string());
+                    body.lines.extend(indent(else_lines));
+                }
+                body.lines.push("}".to_string());
+            }
+        }
+        // the probe's logic is complete
+        self.finish_probe();
+        true
+    }
+
+    fn emit_global_stmts(&mut self, stmts: &mut Vec<Statement>) -> Result<bool, Box<WhammError>> {
+        // Global statements are run when the monitor is set up
+        for stmt in stmts.iter_mut() {
+            if let Statement::Decl {
+                var_id: Expr::VarId { name, .. },
+                ..
+            } = stmt
+            {
+                if self.global_names.contains(name) {
+                    // already emitted as a global
+                    continue;
+                }
+            }
+            let lines = self.stmt_to_virgil(stmt)?;
+            self.init_stmts.extend(lines);
+        }
+        Ok(true)
+    }
+
+    fn emit_body(&mut self, body: &mut Vec<Statement>) -> Result<bool, Box<WhammError>> {
+        self.probe_body
+            .get_or_insert_with(VirgilProbeBody::new)
+            .vars
+            .add_stmts(body);
+        self.check_args()?;
+        let mut is_success = true;
+        for stmt in body.iter_mut() {
+            is_success &= self.emit_stmt(stmt)?;
+        }
+        if let Some(VirgilProbeBody {
+            target: VirgilTarget::Main,
+            ..
+        }) = &self.probe_body
+        {
+            // not in a branch, the probe's logic is complete
+            self.finish_probe();
+        }
+        Ok(is_success)
+    }
+
+    fn emit_counter_incrs(&mut self, _incrs: &[CounterIncr]) -> Result<bool, Box<WhammError>> {
+        Ok(false)
+    }
+
+    fn has_alt_call(&mut self) -> bool {
+        info!("Alternate calls are not supported when emitting Virgil.");
+        false
+    }
+
+    fn emit_alt_call(&mut self) -> Result<bool, Box<WhammError>> {
+        Ok(false)
+    }
+
+    fn emit_stmt(&mut self, stmt: &mut Statement) -> Result<bool, Box<WhammError>> {
+        let lines = self.stmt_to_virgil(stmt)?;
+        if lines.is_empty() {
+            return Ok(true);
+        }
+        Ok(self.push_lines(lines))
+    }
+
+    fn dump_to_file(&mut self, output_path: String) -> Result<bool, Box<WhammError>> {
+        self.finish_probe();
+        match std::fs::write(&output_path, self.to_virgil()) {
+            Ok(..) => Ok(true),
+            Err(err) => Err(Box::new(ErrorGen::get_unexpected_error(
+                true,
+                Some(format!(
+                    "{VIRGIL_UNEXPECTED_ERR_MSG} \
+                Failed to dump Virgil monitor to {} from error: {}",
+                    &output_path, err
+                )),
+                None,
+            ))),
+        }
+    }
+}
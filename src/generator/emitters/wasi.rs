@@ -0,0 +1,388 @@
+use crate::common::error::{ErrorGen, WhammError};
+use crate::common::func_filter::FuncFilter;
+use crate::generator::counters::CounterIncr;
+use crate::generator::emitters::wasm_rewriting::{
+    add_local, emit_data, injected_name, InstrMemory, PrintBackend, WasmRewritingEmitter,
+    WASI_MODULE,
+};
+use crate::generator::emitters::Emitter;
+use crate::parser::rules::wasm::BytecodeEventKind;
+use crate::parser::types::{DataType, Expr, Fn, Statement, Value};
+use crate::verifier::types::{Record, SymbolTable, VarAddr};
+use std::collections::HashSet;
+use walrus::ir::{dfs_pre_order_mut, VisitorMut};
+use walrus::{ExportItem, FunctionBuilder, FunctionId, GlobalId, MemoryId, ValType};
+
+/// Export the memory as `memory` if the app doesn't export one. WASI writes the output from the
+/// memory exported as `memory`, so it's an error if the app exports another memory than `mem_id`.
+fn export_wasi_memory(
+    app_wasm: &mut walrus::Module,
+    mem_id: MemoryId,
+) -> Result<(), Box<WhammError>> {
+    let exported = app_wasm
+        .exports
+        .iter()
+        .find(|export| export.name == "memory")
+        .map(|export| export.item);
+    match exported {
+        Some(ExportItem::Memory(id)) if id == mem_id => Ok(()),
+        Some(_) => Err(Box::new(ErrorGen::get_unexpected_error(
+            true,
+            Some(
+                "WASI writes the output from the memory exported as `memory`, \
+            the instrumentation's data must live in that memory."
+                    .to_string(),
+            ),
+            None,
+        ))),
+        None => {
+            app_wasm.exports.add("memory", mem_id);
+            Ok(())
+        }
+    }
+}
+
+/// Rewrites the app (see WasmRewritingEmitter) and wires the output of the instrumentation to
+/// `wasi_snapshot_preview1::fd_write`, so an instrumented CLI app prints its results to stderr
+/// without a custom host. The import is added to the app if it's missing.
+///
+/// When the app finishes running (returns from `_start` or calls `proc_exit`), the final value
+/// of each script global is reported as `name: value`.
+pub struct WasiEmitter {
+    pub emitter: WasmRewritingEmitter,
+
+    /// The script globals to report when the app finishes running
+    globals: Vec<(String, GlobalId, DataType)>,
+}
+impl WasiEmitter {
+    pub fn new(app_wasm: walrus::Module, table: SymbolTable) -> Result<Self, Box<WhammError>> {
+        let mut emitter = WasmRewritingEmitter::new(app_wasm, table);
+        emitter.print_backend = PrintBackend::Wasi;
+        export_wasi_memory(&mut emitter.app_wasm, emitter.metadata.mem_id)?;
+        Ok(Self {
+            emitter,
+            globals: vec![],
+        })
+    }
+
+    /// WASI writes the output from the memory exported as `memory`, so the instrumentation's
+    /// data must live there.
+    pub fn new_with_memory(
+        app_wasm: walrus::Module,
+        table: SymbolTable,
+        instr_memory: InstrMemory,
+    ) -> Result<Self, Box<WhammError>> {
+        let mut emitter = WasmRewritingEmitter::new_with_memory(app_wasm, table, instr_memory)?;
+        emitter.print_backend = PrintBackend::Wasi;
+        export_wasi_memory(&mut emitter.app_wasm, emitter.metadata.mem_id)?;
+        Ok(Self {
+            emitter,
+            globals: vec![],
+        })
+    }
+
+    /// Inject a function that reports the final value of each script global
+    fn emit_report(&mut self) -> Option<FunctionId> {
+        if self.globals.is_empty() {
+            // nothing to report
+            return None;
+        }
+        // keep the output deterministic
+        let mut globals = self.globals.clone();
+        globals.sort_by(|(a, ..), (b, ..)| a.cmp(b));
+
+        let lines: Vec<(u32, usize, GlobalId, DataType)> = globals
+            .iter()
+            .map(|(name, global, ty)| {
+                let label = format!("{name}: ");
+                let (_, label_addr) = emit_data(
+                    &mut self.emitter.app_wasm.data,
+                    &mut self.emitter.metadata,
+                    label.as_bytes(),
+                    &format!("report_label${name}"),
+                );
+                (label_addr, label.len(), *global, ty.clone())
+            })
+            .collect();
+        let (_, newline) = emit_data(
+            &mut self.emitter.app_wasm.data,
+            &mut self.emitter.metadata,
+            b"\n",
+            "newline",
+        );
+        let print_fns = self.emitter.print_fns();
+
+        let app_wasm = &mut self.emitter.app_wasm;
+        let mut report = FunctionBuilder::new(&mut app_wasm.types, &[], &[]);
+        report.name(injected_name("report"));
+        let mut body = report.func_body();
+        for (label_addr, label_len, global, ty) in lines.iter() {
+            let print_val = match ty {
+                DataType::Boolean => print_fns.print_bool,
+                DataType::U32 => print_fns.print_u32,
+                _ => print_fns.print_i32,
+            };
+            body.i32_const(*label_addr as i32)
+                .i32_const(*label_len as i32)
+                .call(print_fns.print_str)
+                .global_get(*global)
+                .call(print_val)
+                .i32_const(newline as i32)
+                .i32_const(1)
+                .call(print_fns.print_str);
+        }
+        Some(report.finish(vec![], &mut app_wasm.funcs))
+    }
+
+    /// Run the report when the app finishes: after `_start` returns and before any `proc_exit`
+    fn hook_report(&mut self, report: FunctionId) -> bool {
+        let app_wasm = &mut self.emitter.app_wasm;
+        let mut is_hooked = false;
+
+        // wrap `_start`
+        if let Some(export) = app_wasm
+            .exports
+            .iter_mut()
+            .find(|export| export.name == "_start")
+        {
+            if let ExportItem::Function(start) = export.item {
+                let mut wrapper = FunctionBuilder::new(&mut app_wasm.types, &[], &[]);
+                wrapper.name(injected_name("start"));
+                wrapper.func_body().call(start).call(report);
+                export.item = ExportItem::Function(wrapper.finish(vec![], &mut app_wasm.funcs));
+                is_hooked = true;
+            }
+        }
+
+        // `proc_exit` never returns, report before calling it
+        if let Ok(proc_exit) = app_wasm.imports.get_func(WASI_MODULE, "proc_exit") {
+            let code = add_local(&mut app_wasm.locals, ValType::I32, "code");
+            let mut wrapper = FunctionBuilder::new(&mut app_wasm.types, &[ValType::I32], &[]);
+            wrapper.name(injected_name("proc_exit"));
+            wrapper
+                .func_body()
+                .call(report)
+                .local_get(code)
+                .call(proc_exit);
+            let wrapper = wrapper.finish(vec![code], &mut app_wasm.funcs);
+
+            let mut redirect = RedirectCalls {
+                from: proc_exit,
+                to: wrapper,
+            };
+            for (id, func) in app_wasm.funcs.iter_local_mut() {
+                if id != wrapper {
+                    let entry = func.entry_block();
+                    dfs_pre_order_mut(&mut redirect, func, entry);
+                }
+            }
+            is_hooked = true;
+        }
+        is_hooked
+    }
+}
+
+/// Redirects all calls to one function to another
+struct RedirectCalls {
+    from: FunctionId,
+    to: FunctionId,
+}
+impl VisitorMut for RedirectCalls {
+    fn visit_call_mut(&mut self, instr: &mut walrus::ir::Call) {
+        if instr.func == self.from {
+            instr.func = self.to;
+        }
+    }
+}
+
+impl Emitter for WasiEmitter {
+    fn enter_scope(&mut self) -> Result<(), Box<WhammError>> {
+        self.emitter.enter_scope()
+    }
+    fn enter_named_scope(&mut self, scope_name: &str) -> bool {
+        self.emitter.enter_named_scope(scope_name)
+    }
+    fn exit_scope(&mut self) -> Result<(), Box<WhammError>> {
+        self.emitter.exit_scope()
+    }
+    fn reset_children(&mut self) {
+        self.emitter.reset_children();
+    }
+
+    fn add_func_filter(&mut self, filter: FuncFilter) {
+        self.emitter.add_func_filter(filter)
+    }
+
+    fn set_jobs(&mut self, jobs: usize) {
+        self.emitter.set_jobs(jobs)
+    }
+
+    fn set_inline_threshold(&mut self, threshold: Option<usize>) {
+        self.emitter.set_inline_threshold(threshold)
+    }
+
+    fn coalesce_counters(&mut self, counters: HashSet<String>) {
+        self.emitter.coalesce_counters(counters)
+    }
+
+    fn init_instr_iter(
+        &mut self,
+        instrs_of_interest: &HashSet<BytecodeEventKind>,
+    ) -> Result<(), Box<WhammError>> {
+        self.emitter.init_instr_iter(instrs_of_interest)
+    }
+
+    fn has_next_instr(&self) -> bool {
+        self.emitter.has_next_instr()
+    }
+
+    fn init_first_instr(&mut self) -> bool {
+        self.emitter.init_first_instr()
+    }
+
+    fn next_instr(&mut self) -> bool {
+        self.emitter.next_instr()
+    }
+
+    fn curr_instr_type(&mut self) -> &'static str {
+        self.emitter.curr_instr_type()
+    }
+
+    fn curr_site(&self) -> Option<(u32, usize)> {
+        self.emitter.curr_site()
+    }
+
+    fn incr_loc_pointer(&mut self) {
+        self.emitter.incr_loc_pointer()
+    }
+
+    fn has_params(&mut self) -> Result<bool, Box<WhammError>> {
+        self.emitter.has_params()
+    }
+
+    fn save_params(&mut self) -> bool {
+        self.emitter.save_params()
+    }
+
+    fn emit_params(&mut self) -> Result<bool, Box<WhammError>> {
+        self.emitter.emit_params()
+    }
+
+    fn define_compiler_var(
+        &mut self,
+        context: &str,
+        var_name: &str,
+    ) -> Result<bool, Box<WhammError>> {
+        self.emitter.define_compiler_var(context, var_name)
+    }
+
+    fn fold_expr(&mut self, expr: &mut Expr) -> bool {
+        self.emitter.fold_expr(expr)
+    }
+
+    fn emit_expr(&mut self, expr: &mut Expr) -> Result<bool, Box<WhammError>> {
+        self.emitter.emit_expr(expr)
+    }
+
+    fn emit_fn(&mut self, context: &str, f: &Fn) -> Result<bool, Box<WhammError>> {
+        self.emitter.emit_fn(context, f)
+    }
+
+    fn emit_formal_param(&mut self, param: &(Expr, DataType)) -> Result<bool, Box<WhammError>> {
+        self.emitter.emit_formal_param(param)
+    }
+
+    fn emit_global(
+        &mut self,
+        name: String,
+        ty: DataType,
+        val: &Option<Value>,
+    ) -> Result<bool, Box<WhammError>> {
+        let is_success = self.emitter.emit_global(name.clone(), ty.clone(), val)?;
+
+        // remember the global to report its value at the end
+        if let Some(rec_id) = self.emitter.table.lookup(&name) {
+            if let Some(Record::Var {
+                addr: Some(VarAddr::Global { addr }),
+                ..
+            }) = self.emitter.table.get_record(rec_id)
+            {
+                self.globals.push((name, *addr, ty));
+            }
+        }
+        Ok(is_success)
+    }
+
+    fn remove_orig(&mut self) -> bool {
+        self.emitter.remove_orig()
+    }
+
+    fn emit_orig(&mut self) -> bool {
+        self.emitter.emit_orig()
+    }
+
+    fn emit_if(&mut self) -> bool {
+        self.emitter.emit_if()
+    }
+
+    fn emit_if_else(&mut self) -> bool {
+        self.emitter.emit_if_else()
+    }
+
+    fn emit_condition(&mut self) -> bool {
+        self.emitter.emit_condition()
+    }
+
+    fn emit_consequent(&mut self) -> bool {
+        self.emitter.emit_consequent()
+    }
+
+    fn emit_alternate(&mut self) -> bool {
+        self.emitter.emit_alternate()
+    }
+
+    fn finish_branch(&mut self) -> bool {
+        self.emitter.finish_branch()
+    }
+
+    fn emit_global_stmts(&mut self, stmts: &mut Vec<Statement>) -> Result<bool, Box<WhammError>> {
+        self.emitter.emit_global_stmts(stmts)
+    }
+
+    fn emit_body(&mut self, body: &mut Vec<Statement>) -> Result<bool, Box<WhammError>> {
+        self.emitter.emit_body(body)
+    }
+
+    fn emit_counter_incrs(&mut self, incrs: &[CounterIncr]) -> Result<bool, Box<WhammError>> {
+        self.emitter.emit_counter_incrs(incrs)
+    }
+
+    fn has_alt_call(&mut self) -> bool {
+        self.emitter.has_alt_call()
+    }
+
+    fn emit_alt_call(&mut self) -> Result<bool, Box<WhammError>> {
+        self.emitter.emit_alt_call()
+    }
+
+    fn emit_stmt(&mut self, stmt: &mut Statement) -> Result<bool, Box<WhammError>> {
+        self.emitter.emit_stmt(stmt)
+    }
+
+    fn dump_to_file(&mut self, output_wasm_path: String) -> Result<bool, Box<WhammError>> {
+        if let Some(report) = self.emit_report() {
+            if !self.hook_report(report) {
+                return Err(Box::new(ErrorGen::get_unexpected_error(
+                    true,
+                    Some(
+                        "WasiEmitter: The app neither exports `_start` nor imports `proc_exit`, \
+                    unable to report the script's results when it finishes running"
+                            .to_string(),
+                    ),
+                    None,
+                )));
+            }
+        }
+        self.emitter.dump_to_file(output_wasm_path)
+    }
+}
@@ -0,0 +1,4286 @@
+use crate::common::error::{ErrorGen, WhammError};
+use crate::common::func_filter::FuncFilter;
+use crate::common::parallel::{map_chunks, num_threads};
+use crate::generator::cfg::Cfg;
+use crate::generator::counters::CounterIncr;
+use crate::generator::debug_info::fix_code_addresses;
+use crate::generator::emitters::Emitter;
+use crate::generator::name_section::{merge_name_sections, ExtraNames};
+use crate::generator::source_map::{SourceMap, SOURCE_MAP_SECTION};
+use crate::generator::types::ExprFolder;
+use crate::generator::validation::{validate_with_jobs, InvalidModule};
+use crate::parser::rules::wasm::BytecodeEventKind;
+use crate::parser::types::{BinOp, DataType, Expr, Fn, Location, Statement, UnOp, Value};
+use crate::verifier::types::{Record, ScopeType, SymbolTable, VarAddr};
+use crate::verifier::verifier::RUNTIME_ONLY_GLOBALS;
+use log::{debug, info};
+use pest::error::LineColLocation;
+use regex::Regex;
+use sha2::{Digest, Sha256};
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::ops::Range;
+use walrus::ir::{
+    BinaryOp, ExtendedLoad, Instr, InstrLocId, InstrSeqId, LoadKind, MemArg, StoreKind,
+};
+use walrus::{
+    ActiveData, ActiveDataLocation, DataId, DataKind, ExportItem, FunctionBuilder, FunctionId,
+    FunctionKind, GlobalId, GlobalKind, IdsToIndices, ImportKind, ImportedFunction, InitExpr,
+    InstrSeqBuilder, LocalFunction, LocalId, MemoryId, ModuleData, ModuleLocals, RawCustomSection,
+    ValType,
+};
+
+// =================================================================================
+// ================ WasmRewritingEmitter - HELPER FUNCTIONS ========================
+// Necessary to extract common logic between Emitter and InstrumentationVisitor.
+// Can't pass an Emitter instance to InstrumentationVisitor due to Rust not
+// allowing nested references to a common mutable object. So I can't pass the
+// Emitter to the InstrumentationVisitor since I must iterate over Emitter.app_wasm
+// with a construction of InstrumentationVisitor inside that loop.
+// =================================================================================
+// =================================================================================
+
+const UNEXPECTED_ERR_MSG: &str =
+    "WasmRewritingEmitter: Looks like you've found a bug...please report this behavior!";
+
+fn data_type_to_val_type(ty: &DataType) -> (ValType, InitExpr) {
+    match ty {
+        DataType::U32 => (ValType::I32, InitExpr::Value(walrus::ir::Value::I32(0))),
+        DataType::I32 => (ValType::I32, InitExpr::Value(walrus::ir::Value::I32(0))),
+        DataType::Boolean => (ValType::I32, InitExpr::Value(walrus::ir::Value::I32(0))),
+        DataType::Null => unimplemented!(),
+        DataType::Str => unimplemented!(),
+        DataType::Tuple { .. } => unimplemented!(),
+        // the ID used to track this var in the lib
+        DataType::Map { .. } => (ValType::I32, InitExpr::Value(walrus::ir::Value::I32(0))),
+        &DataType::AssumeGood => unimplemented!(),
+    }
+}
+
+/// The prefix of the names of everything injected into the app, in the name section
+pub(crate) const INJECTED_NAME_PREFIX: &str = "whamm$";
+
+/// The name of an item injected into the app
+pub(crate) fn injected_name(name: &str) -> String {
+    format!("{INJECTED_NAME_PREFIX}{name}")
+}
+
+/// Add a local to the app, named after what it holds
+pub(crate) fn add_local(locals: &mut ModuleLocals, ty: ValType, name: &str) -> LocalId {
+    let id = locals.add(ty);
+    locals.get_mut(id).name = Some(injected_name(name));
+    id
+}
+
+/// The scratch locals added to the functions of the app, by type. The code injected at a site is
+/// done with its locals (e.g. the saved arguments of the instruction) once the site's instruction
+/// runs, so the next sites of the function reuse them instead of adding their own.
+#[derive(Default)]
+struct LocalPool {
+    locals: HashMap<(FunctionId, ValType), Vec<LocalId>>,
+    /// How many of the locals the current site uses
+    taken: HashMap<(FunctionId, ValType), usize>,
+}
+impl LocalPool {
+    /// A local of the function that the current site doesn't use yet, added if there is none.
+    /// Also returns whether a previous site used it.
+    fn take(
+        &mut self,
+        locals: &mut ModuleLocals,
+        func_id: FunctionId,
+        ty: ValType,
+        name: &str,
+    ) -> (LocalId, bool) {
+        let pool = self.locals.entry((func_id, ty)).or_default();
+        let taken = self.taken.entry((func_id, ty)).or_default();
+        let is_reused = *taken < pool.len();
+        if !is_reused {
+            pool.push(add_local(locals, ty, name));
+        }
+        *taken += 1;
+        (pool[*taken - 1], is_reused)
+    }
+
+    /// The current site is done with its locals
+    fn next_site(&mut self) {
+        self.taken.clear();
+    }
+}
+
+/// Add the bytes to memory as an active data segment, returns the segment and its address.
+/// The same bytes are only added once, e.g. the text of a `print` matched at many sites.
+pub(crate) fn emit_data(
+    module_data: &mut ModuleData,
+    metadata: &mut InsertionMetadata,
+    bytes: &[u8],
+    name: &str,
+) -> (DataId, u32) {
+    if let Some(emitted) = metadata.emitted_data.get(bytes) {
+        return *emitted;
+    }
+    let addr = metadata.curr_mem_offset;
+    let data_id = module_data.add(
+        DataKind::Active(ActiveData {
+            memory: metadata.mem_id,
+            location: ActiveDataLocation::Absolute(addr),
+        }),
+        Vec::from(bytes),
+    );
+    module_data.get_mut(data_id).name = Some(injected_name(name));
+    // update curr_mem_offset to account for new data
+    metadata.curr_mem_offset += bytes.len() as u32;
+    metadata
+        .emitted_data
+        .insert(Vec::from(bytes), (data_id, addr));
+    (data_id, addr)
+}
+
+/// Find where the instrumentation's data can live in the app's memory without colliding with the
+/// app's data: past the memory's initial size (the app grows the memory to get more room), its
+/// active data segments, `__heap_base` and the data of a previous instrumentation (see `--layer`).
+/// Also returns the number of data segments whose address isn't known statically.
+fn free_mem_offset(app_wasm: &walrus::Module, mem_id: MemoryId) -> (u32, usize) {
+    let const_global = |global: GlobalId| match app_wasm.globals.get(global).kind {
+        GlobalKind::Local(InitExpr::Value(walrus::ir::Value::I32(val))) => Some(val as u32),
+        _ => None,
+    };
+
+    let mut end = app_wasm.memories.get(mem_id).initial as u64 * WASM_PAGE_SIZE as u64;
+    let mut unplaced_data = 0;
+    for data in app_wasm.data.iter() {
+        let DataKind::Active(ActiveData { memory, location }) = &data.kind else {
+            // only copied to memory by the app
+            continue;
+        };
+        if *memory != mem_id {
+            continue;
+        }
+        let addr = match location {
+            ActiveDataLocation::Absolute(addr) => Some(*addr),
+            ActiveDataLocation::Relative(global) => const_global(*global),
+        };
+        match addr {
+            Some(addr) => end = end.max(addr as u64 + data.value.len() as u64),
+            None => unplaced_data += 1,
+        }
+    }
+    let heap_base = app_wasm
+        .exports
+        .iter()
+        .find_map(|export| match export.item {
+            ExportItem::Global(global) if export.name == "__heap_base" => const_global(global),
+            _ => None,
+        });
+    if let Some(heap_base) = heap_base {
+        end = end.max(heap_base as u64);
+    }
+    // the memory a previous instrumentation grows at start, see `reserve_instr_mem`
+    let mem_idx = app_wasm
+        .memories
+        .iter()
+        .position(|memory| memory.id() == mem_id);
+    if let Some(prev) = whamm_section(app_wasm) {
+        if prev["memory"]["index"].as_u64() == mem_idx.map(|idx| idx as u64) {
+            end = end.max(prev["memory"]["end"].as_u64().unwrap_or_default());
+        }
+    }
+
+    // keep the instrumentation's data aligned
+    let base = end.next_multiple_of(8);
+    (u32::try_from(base).unwrap_or(u32::MAX), unplaced_data)
+}
+
+/// Add a memory that only holds the instrumentation's data, it's sized to fit once all data is emitted
+fn add_instr_memory(app_wasm: &mut walrus::Module) -> MemoryId {
+    let mem_id = app_wasm.memories.add_local(false, 1, None);
+    app_wasm.memories.get_mut(mem_id).name = Some(injected_name("memory"));
+    app_wasm.exports.add(INSTR_MEMORY_EXPORT, mem_id);
+    mem_id
+}
+
+/// A piece of the output of a call to `print`
+pub(crate) enum PrintPart {
+    /// Text known at compile time (adjacent constants are folded together)
+    Text(String),
+    /// A value only known at runtime
+    Arg(Expr),
+}
+
+pub(crate) fn is_print_call(expr: &Expr) -> bool {
+    if let Expr::Call { fn_target, .. } = expr {
+        if let Expr::VarId { name, .. } = fn_target.as_ref() {
+            return name == "print";
+        }
+    }
+    false
+}
+
+/// Split the arguments of a call to `print` into the text known at compile time and the values
+/// only known at runtime. The output is followed by a newline.
+pub(crate) fn print_parts(
+    args: Option<&[Box<Expr>]>,
+    table: &SymbolTable,
+) -> Result<Vec<PrintPart>, Box<WhammError>> {
+    let args: Vec<Expr> = match args {
+        Some(args) => args
+            .iter()
+            .map(|arg| ExprFolder::fold_expr(arg, table))
+            .collect(),
+        None => vec![],
+    };
+    let Some((
+        Expr::Primitive {
+            val: Value::Str { val: fmt, .. },
+            ..
+        },
+        fmt_args,
+    )) = args.split_first()
+    else {
+        return Err(Box::new(ErrorGen::get_unexpected_error(
+            false,
+            Some(format!(
+                "{UNEXPECTED_ERR_MSG} \
+            The format string of `print` must be a string literal."
+            )),
+            None,
+        )));
+    };
+    let mut texts = fmt.split("{}");
+    if fmt.matches("{}").count() != fmt_args.len() {
+        return Err(Box::new(ErrorGen::get_unexpected_error(
+            false,
+            Some(format!(
+                "{UNEXPECTED_ERR_MSG} \
+            The format string of `print` does not match the number of arguments."
+            )),
+            None,
+        )));
+    }
+
+    let mut parts = vec![];
+    let mut text = texts.next().unwrap_or_default().to_string();
+    for arg in fmt_args.iter() {
+        match arg {
+            Expr::Primitive {
+                val: Value::Str { val, .. },
+                ..
+            } => text += val,
+            Expr::Primitive {
+                val: Value::Integer { val, .. },
+                ..
+            } => text += &val.to_string(),
+            Expr::Primitive {
+                val: Value::Boolean { val, .. },
+                ..
+            } => text += &val.to_string(),
+            _ => {
+                if !text.is_empty() {
+                    parts.push(PrintPart::Text(text));
+                }
+                text = String::new();
+                parts.push(PrintPart::Arg(arg.clone()));
+            }
+        }
+        text += texts.next().unwrap_or_default();
+    }
+    text.push('\n');
+    parts.push(PrintPart::Text(text));
+    Ok(parts)
+}
+
+/// The type of a value to print
+pub(crate) fn print_arg_ty(expr: &Expr, table: &SymbolTable) -> DataType {
+    match expr {
+        Expr::Primitive { val, .. } => match val {
+            Value::Integer { ty, .. } => ty.clone(),
+            Value::Str { .. } => DataType::Str,
+            Value::Boolean { .. } => DataType::Boolean,
+            Value::Tuple { ty, .. } => ty.clone(),
+        },
+        Expr::UnOp { .. } => DataType::Boolean,
+        Expr::BinOp { op, .. } => match op {
+            BinOp::Add | BinOp::Subtract | BinOp::Multiply | BinOp::Divide | BinOp::Modulo => {
+                DataType::I32
+            }
+            _ => DataType::Boolean,
+        },
+        Expr::Ternary { conseq, .. } => print_arg_ty(conseq, table),
+        Expr::VarId { name, .. } => match table.lookup(name).and_then(|id| table.get_record(id)) {
+            Some(Record::Var { ty, .. }) => ty.clone(),
+            _ => DataType::I32,
+        },
+        Expr::Call { fn_target, .. } => {
+            if let Expr::VarId { name, .. } = fn_target.as_ref() {
+                if let Some(Record::Fn { ret_ty, .. }) =
+                    table.lookup(name).and_then(|id| table.get_record(id))
+                {
+                    return ret_ty.clone();
+                }
+            }
+            DataType::I32
+        }
+    }
+}
+
+fn emit_set(
+    table: &mut SymbolTable,
+    var_id: &mut Expr,
+    instr_builder: &mut InstrSeqBuilder,
+    index: &mut usize,
+) -> Result<bool, Box<WhammError>> {
+    if let Expr::VarId { name, .. } = var_id {
+        let var_rec_id = match table.lookup(name) {
+            Some(rec_id) => *rec_id,
+            _ => {
+                return Err(Box::new(ErrorGen::get_unexpected_error(
+                    true,
+                    Some(format!(
+                        "{UNEXPECTED_ERR_MSG} \
+                                                VarId '{name}' does not exist in this scope!"
+                    )),
+                    None,
+                )));
+            }
+        };
+        match table.get_record_mut(&var_rec_id) {
+            Some(Record::Var { addr, loc, .. }) => {
+                // this will be different based on if this is a global or local var
+                match addr {
+                    Some(VarAddr::Global { addr }) => {
+                        instr_builder.instr_at(*index, walrus::ir::GlobalSet {
+                            global: *addr
+                        });
+                        // update index to point to what follows our insertions
+                        *index += 1;
+                    }
+                    Some(VarAddr::Local { addr }) => {
+                        instr_builder.instr_at(*index, walrus::ir::LocalSet {
+                            local: *addr
+                        });
+                        // update index to point to what follows our insertions
+                        *index += 1;
+                    },
+                    None => {
+                        return Err(Box::new(ErrorGen::get_type_check_error_from_loc(false,
+                           format!("Variable assigned before declared: {}", name), loc)));
+                    }
+                }
+                Ok(true)
+            },
+            Some(ty) => {
+                Err(Box::new(ErrorGen::get_unexpected_error(true, Some(format!("{UNEXPECTED_ERR_MSG} \
+                                                Incorrect variable record, expected Record::Var, found: {:?}", ty)), None)))
+            },
+            None => {
+                Err(Box::new(ErrorGen::get_unexpected_error(true, Some(format!("{UNEXPECTED_ERR_MSG} \
+                                                Variable symbol does not exist!")), None)))
+            }
+        }
+    } else {
+        Err(Box::new(ErrorGen::get_unexpected_error(
+            true,
+            Some(format!(
+                "{UNEXPECTED_ERR_MSG} \
+                                        Expected VarId."
+            )),
+            None,
+        )))
+    }
+}
+
+fn emit_expr(
+    table: &mut SymbolTable,
+    module_data: &mut ModuleData,
+    expr: &mut Expr,
+    instr_builder: &mut InstrSeqBuilder,
+    metadata: &mut InsertionMetadata,
+    index: &mut usize,
+) -> Result<bool, Box<WhammError>> {
+    let mut is_success = true;
+    match expr {
+        Expr::UnOp { op, expr, .. } => {
+            is_success &= emit_expr(table, module_data, expr, instr_builder, metadata, index)?;
+            is_success &= emit_unop(op, instr_builder, index);
+        }
+        Expr::BinOp { lhs, op, rhs, .. } => {
+            is_success &= emit_expr(table, module_data, lhs, instr_builder, metadata, index)?;
+            is_success &= emit_expr(table, module_data, rhs, instr_builder, metadata, index)?;
+            is_success &= emit_binop(op, instr_builder, index);
+        }
+        Expr::Ternary {
+            cond: _cond,
+            conseq: _conseq,
+            alt: _alt,
+            ..
+        } => {
+            return Err(Box::new(ErrorGen::get_unexpected_error(
+                true,
+                Some(format!(
+                    "{UNEXPECTED_ERR_MSG} \
+                            Ternary expressions should be handled before this point!"
+                )),
+                None,
+            )));
+        }
+        Expr::Call {
+            fn_target, args, ..
+        } => {
+            let fn_name = match &**fn_target {
+                Expr::VarId { name, .. } => name.clone(),
+                _ => return Ok(false),
+            };
+            if fn_name == "print" {
+                return emit_print(
+                    table,
+                    module_data,
+                    args.as_deref(),
+                    instr_builder,
+                    metadata,
+                    index,
+                );
+            }
+
+            // emit the arguments
+            if let Some(args) = args {
+                for boxed_arg in args.iter_mut() {
+                    let arg = &mut **boxed_arg; // unbox
+                    is_success &=
+                        emit_expr(table, module_data, arg, instr_builder, metadata, index)?;
+                }
+            }
+
+            let fn_rec_id = table.lookup(&fn_name).copied();
+
+            match fn_rec_id {
+                Some(rec_id) => {
+                    let fn_rec = table.get_record_mut(&rec_id);
+                    match fn_rec {
+                        Some(Record::Fn { addr, .. }) => {
+                            if let Some(f_id) = addr {
+                                instr_builder.instr_at(*index, walrus::ir::Call { func: *f_id });
+                                // update index to point to what follows our insertions
+                                *index += 1;
+                            } else {
+                                return Err(Box::new(ErrorGen::get_unexpected_error(
+                                    true,
+                                    Some(format!(
+                                        "{UNEXPECTED_ERR_MSG} \
+                                fn_target address not in symbol table, not emitted yet..."
+                                    )),
+                                    None,
+                                )));
+                            }
+                        }
+                        _ => {
+                            return Err(Box::new(ErrorGen::get_unexpected_error(
+                                true,
+                                Some(format!(
+                                    "{UNEXPECTED_ERR_MSG} \
+                            fn_target not defined in symbol table!"
+                                )),
+                                None,
+                            )));
+                        }
+                    }
+                }
+                None => {
+                    // Must be defined in the Wasm
+                    unimplemented!()
+                }
+            }
+        }
+        Expr::VarId { name, .. } => {
+            // TODO -- support string vars (unimplemented)
+            let var_rec_id = match table.lookup(name) {
+                Some(rec_id) => *rec_id,
+                _ => {
+                    return Err(Box::new(ErrorGen::get_unexpected_error(
+                        true,
+                        Some(format!(
+                            "{UNEXPECTED_ERR_MSG} \
+                    VarId '{}' does not exist in this scope!",
+                            name
+                        )),
+                        None,
+                    )));
+                }
+            };
+            return match table.get_record_mut(&var_rec_id) {
+                Some(Record::Var { addr, .. }) => {
+                    // this will be different based on if this is a global or local var
+                    match addr {
+                        Some(VarAddr::Global { addr }) => {
+                            instr_builder.instr_at(*index, walrus::ir::GlobalGet { global: *addr });
+                            // update index to point to what follows our insertions
+                            *index += 1;
+                        }
+                        Some(VarAddr::Local { addr }) => {
+                            instr_builder.instr_at(*index, walrus::ir::LocalGet { local: *addr });
+                            // update index to point to what follows our insertions
+                            *index += 1;
+                        }
+                        None => {
+                            return Err(Box::new(ErrorGen::get_unexpected_error(
+                                true,
+                                Some(format!(
+                                    "{UNEXPECTED_ERR_MSG} \
+                            Variable does not exist in scope: {}",
+                                    name
+                                )),
+                                None,
+                            )));
+                        }
+                    }
+                    Ok(true)
+                }
+                Some(ty) => Err(Box::new(ErrorGen::get_unexpected_error(
+                    true,
+                    Some(format!(
+                        "{UNEXPECTED_ERR_MSG} \
+                    Incorrect variable record, expected Record::Var, found: {:?}",
+                        ty
+                    )),
+                    None,
+                ))),
+                None => Err(Box::new(ErrorGen::get_unexpected_error(
+                    true,
+                    Some(format!(
+                        "{UNEXPECTED_ERR_MSG} \
+                    Variable symbol does not exist!"
+                    )),
+                    None,
+                ))),
+            };
+        }
+        Expr::Primitive { val, .. } => {
+            is_success &= emit_value(table, module_data, val, instr_builder, metadata, index)?;
+        }
+    }
+    Ok(is_success)
+}
+
+fn emit_binop(op: &BinOp, instr_builder: &mut InstrSeqBuilder, index: &mut usize) -> bool {
+    match op {
+        BinOp::And => {
+            // we only support i32's at the moment
+            instr_builder.instr_at(
+                *index,
+                walrus::ir::Binop {
+                    op: BinaryOp::I32And,
+                },
+            );
+            // update index to point to what follows our insertions
+            *index += 1;
+            true
+        }
+        BinOp::Or => {
+            // we only support i32's at the moment
+            instr_builder.instr_at(
+                *index,
+                walrus::ir::Binop {
+                    op: BinaryOp::I32Or,
+                },
+            );
+            // update index to point to what follows our insertions
+            *index += 1;
+            true
+        }
+        BinOp::EQ => {
+            // we only support i32's at the moment
+            instr_builder.instr_at(
+                *index,
+                walrus::ir::Binop {
+                    op: BinaryOp::I32Eq,
+                },
+            );
+            // update index to point to what follows our insertions
+            *index += 1;
+            true
+        }
+        BinOp::NE => {
+            // we only support i32's at the moment
+            instr_builder.instr_at(
+                *index,
+                walrus::ir::Binop {
+                    op: BinaryOp::I32Ne,
+                },
+            );
+            // update index to point to what follows our insertions
+            *index += 1;
+            true
+        }
+        BinOp::GE => {
+            // we only support i32's at the moment (assumes signed)
+            instr_builder.instr_at(
+                *index,
+                walrus::ir::Binop {
+                    op: BinaryOp::I32GeS,
+                },
+            );
+            // update index to point to what follows our insertions
+            *index += 1;
+            true
+        }
+        BinOp::GT => {
+            // we only support i32's at the moment (assumes signed)
+            instr_builder.instr_at(
+                *index,
+                walrus::ir::Binop {
+                    op: BinaryOp::I32GtS,
+                },
+            );
+            // update index to point to what follows our insertions
+            *index += 1;
+            true
+        }
+        BinOp::LE => {
+            // we only support i32's at the moment (assumes signed)
+            instr_builder.instr_at(
+                *index,
+                walrus::ir::Binop {
+                    op: BinaryOp::I32LeS,
+                },
+            );
+            // update index to point to what follows our insertions
+            *index += 1;
+            true
+        }
+        BinOp::LT => {
+            // we only support i32's at the moment (assumes signed)
+            instr_builder.instr_at(
+                *index,
+                walrus::ir::Binop {
+                    op: BinaryOp::I32LtS,
+                },
+            );
+            // update index to point to what follows our insertions
+            *index += 1;
+            true
+        }
+        BinOp::Add => {
+            // we only support i32's at the moment (assumes signed)
+            instr_builder.instr_at(
+                *index,
+                walrus::ir::Binop {
+                    op: BinaryOp::I32Add,
+                },
+            );
+            // update index to point to what follows our insertions
+            *index += 1;
+            true
+        }
+        BinOp::Subtract => {
+            // we only support i32's at the moment (assumes signed)
+            instr_builder.instr_at(
+                *index,
+                walrus::ir::Binop {
+                    op: BinaryOp::I32Sub,
+                },
+            );
+            // update index to point to what follows our insertions
+            *index += 1;
+            true
+        }
+        BinOp::Multiply => {
+            // we only support i32's at the moment (assumes signed)
+            instr_builder.instr_at(
+                *index,
+                walrus::ir::Binop {
+                    op: BinaryOp::I32Mul,
+                },
+            );
+            // update index to point to what follows our insertions
+            *index += 1;
+            true
+        }
+        BinOp::Divide => {
+            // we only support i32's at the moment (assumes signed)
+            instr_builder.instr_at(
+                *index,
+                walrus::ir::Binop {
+                    op: BinaryOp::I32DivS,
+                },
+            );
+            // update index to point to what follows our insertions
+            *index += 1;
+            true
+        }
+        BinOp::Modulo => {
+            // we only support i32's at the moment (assumes signed)
+            instr_builder.instr_at(
+                *index,
+                walrus::ir::Binop {
+                    op: BinaryOp::I32RemS,
+                },
+            );
+            // update index to point to what follows our insertions
+            *index += 1;
+            true
+        }
+    }
+}
+
+fn emit_unop(op: &UnOp, instr_builder: &mut InstrSeqBuilder, index: &mut usize) -> bool {
+    match op {
+        UnOp::Not => {
+            instr_builder.instr_at(
+                *index,
+                walrus::ir::Unop {
+                    op: walrus::ir::UnaryOp::I32Eqz, // return 1 if 0, return 0 otherwise
+                },
+            );
+            // update index to point to what follows our insertions
+            *index += 1;
+            true
+        }
+    }
+}
+
+fn emit_print(
+    table: &mut SymbolTable,
+    module_data: &mut ModuleData,
+    args: Option<&[Box<Expr>]>,
+    instr_builder: &mut InstrSeqBuilder,
+    metadata: &mut InsertionMetadata,
+    index: &mut usize,
+) -> Result<bool, Box<WhammError>> {
+    let Some(print_fns) = metadata.print_fns else {
+        return Err(Box::new(ErrorGen::get_unexpected_error(
+            true,
+            Some(format!(
+                "{UNEXPECTED_ERR_MSG} \
+            The functions to print the output have not been injected yet."
+            )),
+            None,
+        )));
+    };
+
+    let mut is_success = true;
+    for part in print_parts(args, table)? {
+        let print_fn = match part {
+            PrintPart::Text(text) => {
+                // the text is placed in memory, print it from there
+                let (_, addr) = emit_data(module_data, metadata, text.as_bytes(), "print_text");
+                instr_builder.instr_at(
+                    *index,
+                    walrus::ir::Const {
+                        value: walrus::ir::Value::I32(addr as i32),
+                    },
+                );
+                instr_builder.instr_at(
+                    *index + 1,
+                    walrus::ir::Const {
+                        value: walrus::ir::Value::I32(text.len() as i32),
+                    },
+                );
+                // update index to point to what follows our insertions
+                *index += 2;
+                print_fns.print_str
+            }
+            PrintPart::Arg(mut arg) => {
+                let ty = print_arg_ty(&arg, table);
+                is_success &=
+                    emit_expr(table, module_data, &mut arg, instr_builder, metadata, index)?;
+                match ty {
+                    DataType::Str => print_fns.print_str,
+                    DataType::Boolean => print_fns.print_bool,
+                    DataType::U32 => print_fns.print_u32,
+                    _ => print_fns.print_i32,
+                }
+            }
+        };
+        instr_builder.instr_at(*index, walrus::ir::Call { func: print_fn });
+        // update index to point to what follows our insertions
+        *index += 1;
+    }
+    Ok(is_success)
+}
+
+fn emit_value(
+    table: &mut SymbolTable,
+    module_data: &mut ModuleData,
+    val: &mut Value,
+    instr_builder: &mut InstrSeqBuilder,
+    metadata: &mut InsertionMetadata,
+    index: &mut usize,
+) -> Result<bool, Box<WhammError>> {
+    let mut is_success = true;
+    match val {
+        Value::Integer { val, .. } => {
+            instr_builder.instr_at(
+                *index,
+                walrus::ir::Const {
+                    value: walrus::ir::Value::I32(*val),
+                },
+            );
+            // update index to point to what follows our insertions
+            *index += 1;
+            is_success &= true;
+        }
+        Value::Str { val, addr, ty: _ty } => {
+            let (data_id, data_addr) = emit_data(module_data, metadata, val.as_bytes(), "str");
+
+            // save the memory addresses/lens, so they can be used as appropriate
+            *addr = Some((data_id, data_addr, val.len()));
+
+            // emit Wasm instructions for the memory address and string length
+            instr_builder.instr_at(
+                *index,
+                walrus::ir::Const {
+                    value: walrus::ir::Value::I32(data_addr as i32),
+                },
+            );
+            // update index to point to what follows our insertions
+            *index += 1;
+            instr_builder.instr_at(
+                *index,
+                walrus::ir::Const {
+                    value: walrus::ir::Value::I32(val.len() as i32),
+                },
+            );
+            // update index to point to what follows our insertions
+            *index += 1;
+            is_success &= true;
+        }
+        Value::Tuple { vals, .. } => {
+            for val in vals.iter_mut() {
+                is_success &= emit_expr(table, module_data, val, instr_builder, metadata, index)?;
+            }
+        }
+        Value::Boolean { val, .. } => {
+            // "In a boolean context, such as a br_if condition, any non-zero value is interpreted as true
+            // and 0 is interpreted as false."
+            // https://github.com/sunfishcode/wasm-reference-manual/blob/master/WebAssembly.md#booleans
+            if *val {
+                // insert true (non-zero)
+                instr_builder.instr_at(
+                    *index,
+                    walrus::ir::Const {
+                        value: walrus::ir::Value::I32(1),
+                    },
+                );
+            } else {
+                // insert false (zero)
+                instr_builder.instr_at(
+                    *index,
+                    walrus::ir::Const {
+                        value: walrus::ir::Value::I32(0),
+                    },
+                );
+            }
+            // update index to point to what follows our insertions
+            *index += 1;
+            is_success &= true;
+        }
+    }
+    Ok(is_success)
+}
+
+fn get_func_info(app_wasm: &walrus::Module, func: &walrus::Function) -> (FuncInfo, Vec<ValType>) {
+    match &func.kind {
+        FunctionKind::Import(ImportedFunction {
+            ty: ty_id,
+            import: import_id,
+        }) => {
+            let import = app_wasm.imports.get(*import_id);
+            let ty = app_wasm.types.get(*ty_id);
+
+            (
+                FuncInfo {
+                    func_kind: "import".to_string(),
+                    module: import.module.clone(),
+                    name: import.name.clone(),
+                },
+                Vec::from(ty.params()),
+            )
+        }
+        FunctionKind::Local(LocalFunction { args, .. }) => {
+            let mut params = vec![];
+            args.iter().for_each(|arg_id| {
+                let arg = app_wasm.locals.get(*arg_id);
+                params.push(arg.ty());
+            });
+
+            (
+                FuncInfo {
+                    func_kind: "local".to_string(),
+                    module: "".to_string(),
+                    name: func.name.clone().unwrap_or("".to_string()),
+                },
+                params,
+            )
+        }
+        FunctionKind::Uninitialized(ty_id) => {
+            let ty = app_wasm.types.get(*ty_id);
+
+            (
+                FuncInfo {
+                    func_kind: "uninitialized".to_string(),
+                    module: "".to_string(),
+                    name: "".to_string(),
+                },
+                Vec::from(ty.params()),
+            )
+        }
+    }
+}
+
+fn override_var_val(table: &mut SymbolTable, rec_id: &usize, val: Option<Value>) {
+    let mut rec = table.get_record_mut(rec_id);
+    if let Some(Record::Var { value, .. }) = &mut rec {
+        *value = val;
+    }
+}
+
+fn define_new_target_fn_name() -> Result<bool, Box<WhammError>> {
+    // TODO -- change this to be an inline call() instead of setting a var
+    Ok(true)
+}
+
+fn define_target_imp_name(
+    table: &mut SymbolTable,
+    curr_instr: Option<&ProbeLoc>,
+) -> Result<bool, Box<WhammError>> {
+    let var_name = "target_imp_name".to_string();
+
+    if let Some(curr_instr) = curr_instr {
+        if let Some(func_info) = &curr_instr.func_info {
+            if func_info.name.contains("call_perform") {
+                // For debugging, set breakpoint here!
+                println!("{}", func_info.name);
+            }
+
+            let rec_id = match table.lookup(&var_name) {
+                Some(rec_id) => *rec_id,
+                _ => {
+                    return Err(Box::new(ErrorGen::get_unexpected_error(
+                        true,
+                        Some(format!(
+                            "{UNEXPECTED_ERR_MSG} \
+                    `{var_name}` symbol does not exist in this scope!"
+                        )),
+                        None,
+                    )));
+                }
+            };
+            override_var_val(
+                table,
+                &rec_id,
+                Some(Value::Str {
+                    ty: DataType::Str,
+                    val: func_info.name.to_string(),
+                    addr: None,
+                }),
+            );
+        }
+    }
+    Ok(true)
+}
+
+fn define_target_fn_type(
+    table: &mut SymbolTable,
+    curr_instr: Option<&ProbeLoc>,
+) -> Result<bool, Box<WhammError>> {
+    let var_name = "target_fn_type".to_string();
+
+    if let Some(curr_instr) = curr_instr {
+        if let Some(func_info) = &curr_instr.func_info {
+            // if func_info.name.contains("call_new") {
+            //     // For debugging, set breakpoint here!
+            //     println!("{}", func_info.name);
+            // }
+            let rec_id = match table.lookup(&var_name) {
+                Some(rec_id) => *rec_id,
+                _ => {
+                    return Err(Box::new(ErrorGen::get_unexpected_error(
+                        true,
+                        Some(format!(
+                            "{UNEXPECTED_ERR_MSG} \
+                    `{var_name}` symbol does not exist in this scope!"
+                        )),
+                        None,
+                    )));
+                }
+            };
+            override_var_val(
+                table,
+                &rec_id,
+                Some(Value::Str {
+                    ty: DataType::Str,
+                    val: func_info.func_kind.to_string(),
+                    addr: None,
+                }),
+            );
+        }
+    }
+    Ok(true)
+}
+
+fn define_target_imp_module(
+    table: &mut SymbolTable,
+    curr_instr: Option<&ProbeLoc>,
+) -> Result<bool, Box<WhammError>> {
+    let var_name = "target_imp_module".to_string();
+    if let Some(curr_instr) = curr_instr {
+        if let Some(func_info) = &curr_instr.func_info {
+            // if func_info.name.contains("call_new") {
+            //     // For debugging, set breakpoint here!
+            //     println!("{}", func_info.name);
+            // }
+            let rec_id = match table.lookup(&var_name) {
+                Some(rec_id) => *rec_id,
+                _ => {
+                    return Err(Box::new(ErrorGen::get_unexpected_error(
+                        true,
+                        Some(format!(
+                            "{UNEXPECTED_ERR_MSG} \
+                    `{var_name}` symbol does not exist in this scope!"
+                        )),
+                        None,
+                    )));
+                }
+            };
+            override_var_val(
+                table,
+                &rec_id,
+                Some(Value::Str {
+                    ty: DataType::Str,
+                    val: func_info.module.to_string(),
+                    addr: None,
+                }),
+            );
+        }
+    }
+    Ok(true)
+}
+
+pub(crate) fn define_compiler_var(
+    table: &mut SymbolTable,
+    curr_instr: Option<&ProbeLoc>,
+    context: &str,
+    var_name: &str,
+) -> Result<bool, Box<WhammError>> {
+    let regex = Regex::new(r"whamm:script([0-9]+):wasm:bytecode").unwrap();
+    return if let Some(_caps) = regex.captures(context) {
+        match var_name {
+            "new_target_fn_name" => define_new_target_fn_name(),
+            "target_imp_name" => define_target_imp_name(table, curr_instr),
+            "target_fn_type" => define_target_fn_type(table, curr_instr),
+            "target_imp_module" => define_target_imp_module(table, curr_instr),
+            // only known at runtime, the verifier rejects the scripts that use these
+            name if RUNTIME_ONLY_GLOBALS.contains(&name) => Ok(false),
+            _ => {
+                return Err(Box::new(ErrorGen::get_unexpected_error(
+                    true,
+                    Some(format!(
+                        "{UNEXPECTED_ERR_MSG} \
+                Current context `{}` does not provide definition for variable `{}`",
+                        context, var_name
+                    )),
+                    None,
+                )));
+            }
+        }
+    } else {
+        return Err(Box::new(ErrorGen::get_unexpected_error(
+            true,
+            Some(format!(
+                "{UNEXPECTED_ERR_MSG} \
+        Could not find a context to provide the definition, context: {}",
+                context
+            )),
+            None,
+        )));
+    };
+}
+
+// ==============================
+// ==== WasmRewritingEmitter ====
+// ==============================
+
+/// The custom section describing what whamm! injected into the app (see `WasmRewritingEmitter::metadata`)
+pub const WHAMM_SECTION: &str = "whamm";
+
+/// The contents of the `whamm` custom section of a module instrumented by whamm!
+fn whamm_section(app_wasm: &walrus::Module) -> Option<serde_json::Value> {
+    let (_, section) = app_wasm
+        .customs
+        .iter()
+        .find(|(_, section)| section.name() == WHAMM_SECTION)?;
+    serde_json::from_slice(&section.data(&IdsToIndices::default())).ok()
+}
+
+/// Whether the module was instrumented by whamm! and still has its `whamm` custom section, which
+/// is needed to instrument it again (see `--layer`)
+pub fn has_whamm_section(app_wasm: &walrus::Module) -> bool {
+    whamm_section(app_wasm).is_some()
+}
+
+/// Whether the module was already instrumented by whamm!.
+/// Falls back to the items whamm! injects if the `whamm` custom section was stripped.
+pub fn is_instrumented(app_wasm: &walrus::Module) -> bool {
+    let has_section = app_wasm
+        .customs
+        .iter()
+        .any(|(_, section)| section.name() == WHAMM_SECTION);
+    let has_export = app_wasm.exports.iter().any(|export| {
+        [INSTR_MEMORY_EXPORT, "whamm_output", "whamm_output_len"].contains(&export.name.as_str())
+    });
+    let has_import = app_wasm
+        .imports
+        .iter()
+        .any(|import| import.module == HOST_MODULE);
+    let has_func = app_wasm.funcs.iter().any(|func| match &func.name {
+        Some(name) => name.starts_with(INJECTED_NAME_PREFIX),
+        None => false,
+    });
+    has_section || has_export || has_import || has_func
+}
+
+/// The error for an instrumented app that is invalid, pointing at the part of the script the
+/// offending code was injected for (see `SourceMap`)
+fn invalid_module_error(wasm: &[u8], invalid: &InvalidModule) -> WhammError {
+    let InvalidModule {
+        message,
+        offset,
+        func_idx,
+    } = invalid;
+    let (location, range) = match func_idx {
+        Some(func_idx) => (
+            format!("function {func_idx}, offset {offset:#x}"),
+            SourceMap::lookup_binary(wasm, *func_idx, *offset)
+                .ok()
+                .flatten(),
+        ),
+        None => (format!("offset {offset:#x}"), None),
+    };
+    match range {
+        Some(range) => ErrorGen::get_unexpected_error(
+            true,
+            Some(format!(
+                "{UNEXPECTED_ERR_MSG} The instrumented app is invalid ({location}): {message}, \
+                in the code injected for this part of the script"
+            )),
+            Some(LineColLocation::Span(range.span.start, range.span.end)),
+        ),
+        None => ErrorGen::get_unexpected_error(
+            true,
+            Some(format!(
+                "{UNEXPECTED_ERR_MSG} The instrumented app is invalid ({location}): {message}"
+            )),
+            None,
+        ),
+    }
+}
+
+/// The hash of a script, as recorded in the `whamm` custom section
+pub fn hash_script(script_text: &str) -> String {
+    let hash = Sha256::digest(script_text.as_bytes());
+    hash.iter().fold("sha256:".to_string(), |mut hex, byte| {
+        let _ = write!(hex, "{byte:02x}");
+        hex
+    })
+}
+
+/// A location in the app where instrumentation was injected
+#[derive(PartialEq)]
+struct InjectedSite {
+    /// The index of the function in the original app
+    func_idx: u32,
+    /// The byte offset of the instruction from the start of the original app binary (see
+    /// `Emitter::curr_site`)
+    offset: u32,
+    /// e.g. `wasm:bytecode:call`
+    probe: String,
+    mode: String,
+}
+
+/// The indices of the injected items, in the order they are emitted in
+fn injected_idxs<T>(
+    items: impl Iterator<Item = T>,
+    is_injected: impl std::ops::Fn(&T) -> bool,
+) -> Vec<u32> {
+    items
+        .enumerate()
+        .filter(|(_, item)| is_injected(item))
+        .map(|(idx, _)| idx as u32)
+        .collect()
+}
+
+/// The functions, globals and data of the app before it was instrumented
+pub(crate) struct AppItems {
+    /// Maps each function to its index in the original app
+    func_idxs: HashMap<FunctionId, u32>,
+    globals: HashSet<GlobalId>,
+    data: HashSet<DataId>,
+    /// The offsets of the code a previous instrumentation injected into the app's functions, as
+    /// listed in its source map section
+    injected_code: HashMap<FunctionId, Vec<Range<usize>>>,
+}
+impl AppItems {
+    /// The items of `app_wasm`, except those a previous instrumentation injected into it (as
+    /// listed in its `whamm` section, see `--layer`)
+    pub(crate) fn new(app_wasm: &walrus::Module) -> Self {
+        let prev = whamm_section(app_wasm);
+        let prev_injected = |kind: &str| -> HashSet<usize> {
+            let Some(idxs) = prev
+                .as_ref()
+                .and_then(|prev| prev["injected"][kind].as_array())
+            else {
+                return HashSet::new();
+            };
+            idxs.iter()
+                .filter_map(|idx| idx.as_u64().map(|idx| idx as usize))
+                .collect()
+        };
+        let (prev_funcs, prev_globals, prev_data) = (
+            prev_injected("funcs"),
+            prev_injected("globals"),
+            prev_injected("data"),
+        );
+        let mut injected_code: HashMap<FunctionId, Vec<Range<usize>>> = HashMap::new();
+        if prev.is_some() {
+            let ranges = SourceMap::ranges(app_wasm).unwrap_or_default();
+            for range in ranges {
+                if let Some(func) = app_wasm.funcs.iter().nth(range.func_idx as usize) {
+                    injected_code
+                        .entry(func.id())
+                        .or_default()
+                        .push(range.start..range.end);
+                }
+            }
+        }
+        // walrus keeps the items of a parsed module in their original order
+        Self {
+            func_idxs: app_wasm
+                .funcs
+                .iter()
+                .enumerate()
+                .filter(|(idx, _)| !prev_funcs.contains(idx))
+                .map(|(idx, func)| (func.id(), idx as u32))
+                .collect(),
+            globals: app_wasm
+                .globals
+                .iter()
+                .enumerate()
+                .filter(|(idx, _)| !prev_globals.contains(idx))
+                .map(|(_, global)| global.id())
+                .collect(),
+            data: app_wasm
+                .data
+                .iter()
+                .enumerate()
+                .filter(|(idx, _)| !prev_data.contains(idx))
+                .map(|(_, data)| data.id())
+                .collect(),
+            injected_code,
+        }
+    }
+
+    /// Whether the function was injected by whamm!, i.e. it isn't part of the original app
+    fn is_injected(&self, func_id: &FunctionId) -> bool {
+        !self.func_idxs.contains_key(func_id)
+    }
+
+    /// The functions in the order walrus emits them: the imported functions first, then the local
+    /// functions from largest to smallest
+    fn emitted_funcs(app_wasm: &walrus::Module) -> Vec<FunctionId> {
+        let mut local_funcs: Vec<(u64, FunctionId)> = app_wasm
+            .funcs
+            .iter()
+            .filter_map(|func| match &func.kind {
+                FunctionKind::Local(local_func) => Some((local_func.size(), func.id())),
+                _ => None,
+            })
+            .collect();
+        local_funcs.sort_by_key(|(size, id)| (Reverse(*size), *id));
+        app_wasm
+            .imports
+            .iter()
+            .filter_map(|import| match import.kind {
+                ImportKind::Function(id) => Some(id),
+                _ => None,
+            })
+            .chain(local_funcs.iter().map(|(_, id)| *id))
+            .collect()
+    }
+
+    /// The index of each function of the app once it is emitted, with its index in the original
+    /// app: (index, original index)
+    fn app_funcs(&self, app_wasm: &walrus::Module) -> Vec<(u32, u32)> {
+        Self::emitted_funcs(app_wasm)
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, id)| Some((idx as u32, *self.func_idxs.get(id)?)))
+            .collect()
+    }
+
+    /// The indices of the injected functions, globals and data once the app is emitted
+    fn injected(&self, app_wasm: &walrus::Module) -> (Vec<u32>, Vec<u32>, Vec<u32>) {
+        let funcs = Self::emitted_funcs(app_wasm);
+
+        // ...and the imported globals first, then the local globals
+        let globals = app_wasm
+            .imports
+            .iter()
+            .filter_map(|import| match import.kind {
+                ImportKind::Global(id) => Some(id),
+                _ => None,
+            })
+            .chain(
+                app_wasm
+                    .globals
+                    .iter()
+                    .filter(|global| matches!(global.kind, GlobalKind::Local(_)))
+                    .map(|global| global.id()),
+            );
+
+        (
+            injected_idxs(funcs.iter(), |id| !self.func_idxs.contains_key(id)),
+            injected_idxs(globals, |id| !self.globals.contains(id)),
+            injected_idxs(app_wasm.data.iter().map(|data| data.id()), |id| {
+                !self.data.contains(id)
+            }),
+        )
+    }
+}
+
+pub(crate) struct InsertionMetadata {
+    // curr_event: String,
+    /// The memory the instrumentation's data (e.g. strings) lives in
+    pub(crate) mem_id: MemoryId,
+    /// The memory the app's data (e.g. the strings passed to `strcmp`) lives in
+    app_mem_id: MemoryId,
+    /// Whether `mem_id` only holds the instrumentation's data
+    is_dedicated_mem: bool,
+    /// Where the instrumentation's data starts, past any of the app's data
+    mem_base: u32,
+    /// The number of the app's data segments in `mem_id` whose address isn't known statically
+    unplaced_data: usize,
+    curr_mem_offset: u32,
+    /// The data added to memory so far and where it is (see `emit_data`)
+    emitted_data: HashMap<Vec<u8>, (DataId, u32)>,
+    /// Injected the first time the script prints something
+    print_fns: Option<PrintFns>,
+}
+
+/// The name the memory added for the instrumentation is exported as
+const INSTR_MEMORY_EXPORT: &str = "whamm_memory";
+const WASM_PAGE_SIZE: u32 = 65_536;
+/// The most pages a 32-bit memory can have
+const MAX_WASM_PAGES: u32 = 65_536;
+
+/// Which memory the instrumentation's data (e.g. strings) lives in
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InstrMemory {
+    /// The app's first memory, a new memory is added if the app has none
+    Default,
+    /// The app's memory at this index
+    App(u32),
+    /// A new memory added for the instrumentation (requires multi-memory if the app has a memory)
+    Dedicated,
+}
+
+const HOST_MODULE: &str = "whamm";
+pub(crate) const WASI_MODULE: &str = "wasi_snapshot_preview1";
+const STDERR_FD: i32 = 2;
+/// The size of the buffer the output is appended to when printing to memory
+const PRINT_BUFFER_SIZE: u32 = 16_384;
+/// Enough room for the digits of any i32
+const PRINT_DIGITS_SIZE: u32 = 16;
+
+/// Where the output of the instrumentation (e.g. `print`) is written to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PrintBackend {
+    /// Call `whamm::print(ptr: i32, len: i32)`, provided by the host
+    HostImport,
+    /// Append to a buffer in memory. Its address and the length of the output are exported as
+    /// the `whamm_output` and `whamm_output_len` globals.
+    Buffer,
+    /// Write to stderr through `wasi_snapshot_preview1::fd_write`
+    Wasi,
+}
+
+/// The functions injected into the app to print the output of the instrumentation
+#[derive(Clone, Copy)]
+pub(crate) struct PrintFns {
+    /// `(ptr: i32, len: i32)`: prints `len` bytes of memory starting at `ptr`
+    pub(crate) print_str: FunctionId,
+    /// `(val: i32)`: prints the decimal representation of `val`
+    pub(crate) print_i32: FunctionId,
+    /// `(val: i32)`: prints the decimal representation of `val` as an unsigned integer
+    pub(crate) print_u32: FunctionId,
+    /// `(val: i32)`: prints `true` or `false`
+    pub(crate) print_bool: FunctionId,
+}
+
+/// The bytecode event that an instruction is an instance of
+fn event_kind(instr: &Instr) -> BytecodeEventKind {
+    match instr {
+        Instr::Block(_) => BytecodeEventKind::Block,
+        Instr::Loop(_) => BytecodeEventKind::Loop,
+        Instr::Call(_) => BytecodeEventKind::Call,
+        Instr::CallIndirect(_) => BytecodeEventKind::CallIndirect,
+        Instr::LocalGet(_) => BytecodeEventKind::LocalGet,
+        Instr::LocalSet(_) => BytecodeEventKind::LocalSet,
+        Instr::LocalTee(_) => BytecodeEventKind::LocalTee,
+        Instr::GlobalGet(_) => BytecodeEventKind::GlobalGet,
+        Instr::GlobalSet(_) => BytecodeEventKind::GlobalSet,
+        Instr::Const(_) => BytecodeEventKind::Const,
+        Instr::Binop(_) => BytecodeEventKind::Binop,
+        Instr::Unop(_) => BytecodeEventKind::Unop,
+        Instr::Select(_) => BytecodeEventKind::Select,
+        Instr::Unreachable(_) => BytecodeEventKind::Unreachable,
+        Instr::Br(_) => BytecodeEventKind::Br,
+        Instr::BrIf(_) => BytecodeEventKind::BrIf,
+        Instr::IfElse(_) => BytecodeEventKind::IfElse,
+        Instr::BrTable(_) => BytecodeEventKind::BrTable,
+        Instr::Drop(_) => BytecodeEventKind::Drop,
+        Instr::Return(_) => BytecodeEventKind::Return,
+        Instr::MemorySize(_) => BytecodeEventKind::MemorySize,
+        Instr::MemoryGrow(_) => BytecodeEventKind::MemoryGrow,
+        Instr::MemoryInit(_) => BytecodeEventKind::MemoryInit,
+        Instr::DataDrop(_) => BytecodeEventKind::DataDrop,
+        Instr::MemoryCopy(_) => BytecodeEventKind::MemoryCopy,
+        Instr::MemoryFill(_) => BytecodeEventKind::MemoryFill,
+        Instr::Load(_) => BytecodeEventKind::Load,
+        Instr::Store(_) => BytecodeEventKind::Store,
+        Instr::AtomicRmw(_) => BytecodeEventKind::AtomicRmw,
+        Instr::Cmpxchg(_) => BytecodeEventKind::Cmpxchg,
+        Instr::AtomicNotify(_) => BytecodeEventKind::AtomicNotify,
+        Instr::AtomicWait(_) => BytecodeEventKind::AtomicWait,
+        Instr::AtomicFence(_) => BytecodeEventKind::AtomicFence,
+        Instr::TableGet(_) => BytecodeEventKind::TableGet,
+        Instr::TableSet(_) => BytecodeEventKind::TableSet,
+        Instr::TableGrow(_) => BytecodeEventKind::TableGrow,
+        Instr::TableSize(_) => BytecodeEventKind::TableSize,
+        Instr::TableFill(_) => BytecodeEventKind::TableFill,
+        Instr::RefNull(_) => BytecodeEventKind::RefNull,
+        Instr::RefIsNull(_) => BytecodeEventKind::RefIsNull,
+        Instr::RefFunc(_) => BytecodeEventKind::RefFunc,
+        Instr::V128Bitselect(_) => BytecodeEventKind::V128Bitselect,
+        Instr::I8x16Swizzle(_) => BytecodeEventKind::I8x16Swizzle,
+        Instr::I8x16Shuffle(_) => BytecodeEventKind::I8x16Shuffle,
+        Instr::LoadSimd(_) => BytecodeEventKind::LoadSimd,
+        Instr::TableInit(_) => BytecodeEventKind::TableInit,
+        Instr::ElemDrop(_) => BytecodeEventKind::ElemDrop,
+        Instr::TableCopy(_) => BytecodeEventKind::TableCopy,
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct InstrIter {
+    pub(crate) instr_locs: Vec<ProbeLoc>,
+    pub(crate) curr_loc: usize,
+    /// Which functions to visit
+    pub(crate) func_filter: FuncFilter,
+    /// How many functions to visit in parallel (see `num_threads`)
+    pub(crate) jobs: usize,
+}
+impl InstrIter {
+    /// Build out a list of all local functions and their blocks/instruction indexes
+    /// to visit while doing instrumentation.
+    pub(crate) fn new() -> Self {
+        Self {
+            instr_locs: vec![],
+            curr_loc: 0,
+            func_filter: FuncFilter::default(),
+            jobs: 1,
+        }
+    }
+    pub(crate) fn init(
+        &mut self,
+        app_wasm: &walrus::Module,
+        instrs_of_interest: &HashSet<BytecodeEventKind>,
+        app_items: &AppItems,
+    ) {
+        // Figure out which functions to visit
+        let mut funcs = vec![];
+        for func in app_wasm.funcs.iter() {
+            let func_id = func.id();
+            if app_items.is_injected(&func_id) {
+                // never instrument the instrumentation
+                continue;
+            }
+            if let Some(idx) = app_items.func_idxs.get(&func_id) {
+                let exports: Vec<&str> = app_wasm
+                    .exports
+                    .iter()
+                    .filter(
+                        |export| matches!(export.item, ExportItem::Function(id) if id == func_id),
+                    )
+                    .map(|export| export.name.as_str())
+                    .collect();
+                if !self
+                    .func_filter
+                    .allows(*idx, func.name.as_deref(), &exports)
+                {
+                    continue;
+                }
+            }
+
+            if let FunctionKind::Local(local_func) = &func.kind {
+                let injected = app_items
+                    .injected_code
+                    .get(&func_id)
+                    .map_or(&[][..], |ranges| &ranges[..]);
+                funcs.push((func_id, local_func, injected));
+            }
+        }
+
+        // the functions are independent, the locations are in the order of the functions
+        // whatever the number of jobs
+        let chunks = map_chunks(funcs, num_threads(self.jobs), |funcs| {
+            let mut instr_locs = vec![];
+            for (func_id, local_func, injected) in funcs {
+                Self::init_instr_locs(
+                    &mut instr_locs,
+                    instrs_of_interest,
+                    app_wasm,
+                    local_func,
+                    &func_id,
+                    injected,
+                    local_func.entry_block(),
+                );
+            }
+            instr_locs
+        });
+        self.instr_locs.extend(chunks.into_iter().flatten());
+        debug!("Finished creating list of instructions to visit");
+    }
+    fn init_instr_locs(
+        instr_locs: &mut Vec<ProbeLoc>,
+        instrs_of_interest: &HashSet<BytecodeEventKind>,
+        app_wasm: &walrus::Module,
+        func: &LocalFunction,
+        func_id: &FunctionId,
+        injected: &[Range<usize>],
+        instr_seq_id: InstrSeqId,
+    ) {
+        let seq = func.block(instr_seq_id);
+        seq.iter()
+            .enumerate()
+            .for_each(|(index, (instr, instr_loc))| {
+                let event = event_kind(instr);
+                // never instrument what a previous instrumentation injected
+                let offset = instr_loc.data() as usize;
+                let is_injected = injected.iter().any(|range| range.contains(&offset));
+                if instrs_of_interest.contains(&event) && !is_injected {
+                    let (func_info, params) = if let Instr::Call(func) = instr {
+                        let func = app_wasm.funcs.get(func.func);
+                        // get information about the function call
+                        let (func_info, params) = get_func_info(app_wasm, func);
+                        (Some(func_info), params)
+                    } else {
+                        (None, vec![])
+                    };
+
+                    // add current instr
+                    instr_locs.push(ProbeLoc {
+                        wasm_func_id: *func_id,
+                        instr_seq_id,
+                        index,
+                        event,
+                        instr: instr.clone(),
+                        instr_loc: *instr_loc,
+                        // the location of whatever follows this instruction in the same block
+                        next_loc: match seq.get(index + 1) {
+                            Some((_, next_loc)) => *next_loc,
+                            None => seq.end,
+                        },
+                        instr_params: params,
+                        instr_created_args: vec![],
+                        instr_alt_call: None,
+                        // instr_symbols: HashMap::new()
+                        func_info,
+                    });
+                }
+
+                // visit nested blocks
+                match instr {
+                    Instr::Block(block) => {
+                        Self::init_instr_locs(
+                            instr_locs,
+                            instrs_of_interest,
+                            app_wasm,
+                            func,
+                            func_id,
+                            injected,
+                            block.seq,
+                        );
+                    }
+                    Instr::Loop(_loop) => {
+                        Self::init_instr_locs(
+                            instr_locs,
+                            instrs_of_interest,
+                            app_wasm,
+                            func,
+                            func_id,
+                            injected,
+                            _loop.seq,
+                        );
+                    }
+                    Instr::IfElse(if_else, ..) => {
+                        Self::init_instr_locs(
+                            instr_locs,
+                            instrs_of_interest,
+                            app_wasm,
+                            func,
+                            func_id,
+                            injected,
+                            if_else.consequent,
+                        );
+                        Self::init_instr_locs(
+                            instr_locs,
+                            instrs_of_interest,
+                            app_wasm,
+                            func,
+                            func_id,
+                            injected,
+                            if_else.alternative,
+                        );
+                    }
+                    _ => {
+                        // do nothing extra for other instructions
+                    }
+                }
+            });
+    }
+    pub(crate) fn has_next(&self) -> bool {
+        self.curr_loc + 1 < self.instr_locs.len()
+    }
+    pub(crate) fn next(&mut self) -> Option<&ProbeLoc> {
+        self.curr_loc += 1;
+        self.curr()
+    }
+    pub(crate) fn curr(&self) -> Option<&ProbeLoc> {
+        self.instr_locs.get(self.curr_loc)
+    }
+    fn curr_mut(&mut self) -> Option<&mut ProbeLoc> {
+        self.instr_locs.get_mut(self.curr_loc)
+    }
+}
+
+// Struct to store info on insertion locations for an instruction sequence.
+// Note that blocks can be indefinitely nested.
+#[derive(Debug)]
+pub(crate) struct ProbeLoc {
+    // wasm_func_name: Option<String>,
+    pub(crate) wasm_func_id: FunctionId,
+    pub(crate) instr_seq_id: InstrSeqId,
+    pub(crate) index: usize,
+
+    pub(crate) event: BytecodeEventKind,
+    pub(crate) instr: Instr,
+    /// The original location (byte offset in the app binary) of the instruction
+    pub(crate) instr_loc: InstrLocId,
+    pub(crate) next_loc: InstrLocId,
+    pub(crate) func_info: Option<FuncInfo>,
+    pub(crate) instr_params: Vec<ValType>,
+    pub(crate) instr_created_args: Vec<(String, usize)>,
+
+    // Save off the compiler-defined constants for this instruction
+    // instr_symbols: HashMap<String, Record>,
+    pub(crate) instr_alt_call: Option<FunctionId>,
+}
+#[derive(Debug)]
+pub(crate) struct FuncInfo {
+    func_kind: String,
+    module: String,
+    name: String,
+}
+
+pub(crate) struct EmittingInstrTracker {
+    // To keep track of the location of the original instruction while we're instrumenting!
+    pub(crate) orig_instr_idx: usize,
+
+    pub(crate) curr_seq_id: InstrSeqId,
+    pub(crate) curr_idx: usize,
+
+    /// The sequence ID of the main block (containing the instruction of-interest)
+    pub(crate) main_seq_id: InstrSeqId,
+    /// The current index into the main block (containing the instruction of-interest)
+    pub(crate) main_idx: usize,
+
+    /// The sequence ID of the outer block of an injected conditional
+    pub(crate) outer_seq_id: Option<InstrSeqId>,
+    /// The current index into the outer block of an injected conditional
+    pub(crate) outer_idx: Option<usize>,
+
+    /// The sequence ID of the consequent block of an injected conditional
+    pub(crate) then_seq_id: Option<InstrSeqId>,
+    /// The current index into the consequent block of an injected conditional
+    pub(crate) then_idx: Option<usize>,
+
+    /// The sequence ID of the alternate block of an injected conditional
+    pub(crate) else_seq_id: Option<InstrSeqId>,
+    /// The current index into the alternate block of an injected conditional
+    pub(crate) else_idx: Option<usize>,
+}
+
+/// Roughly the number of instructions emitting the expression inlines
+fn expr_size(expr: &Expr) -> usize {
+    match expr {
+        Expr::UnOp { expr, .. } => 1 + expr_size(expr),
+        // the block, the if/else and its condition
+        Expr::Ternary {
+            cond, conseq, alt, ..
+        } => 2 + expr_size(cond) + expr_size(conseq) + expr_size(alt),
+        Expr::BinOp { lhs, rhs, .. } => 1 + expr_size(lhs) + expr_size(rhs),
+        Expr::Call { args, .. } => {
+            1 + args
+                .iter()
+                .flatten()
+                .map(|arg| expr_size(arg))
+                .sum::<usize>()
+        }
+        Expr::VarId { .. } => 1,
+        Expr::Primitive { val, .. } => match val {
+            // its address and length
+            Value::Str { .. } => 2,
+            Value::Tuple { vals, .. } => vals.iter().map(expr_size).sum(),
+            Value::Integer { .. } | Value::Boolean { .. } => 1,
+        },
+    }
+}
+
+/// Roughly the number of instructions emitting the statements inlines, what `--inline-threshold`
+/// compares a probe's body to
+fn stmts_size(stmts: &[Statement]) -> usize {
+    stmts
+        .iter()
+        .map(|stmt| match stmt {
+            Statement::Decl { .. } => 0,
+            // and the set
+            Statement::Assign { expr, .. } => 1 + expr_size(expr),
+            Statement::Expr { expr, .. } | Statement::Return { expr, .. } => expr_size(expr),
+            Statement::If {
+                cond, conseq, alt, ..
+            } => 2 + expr_size(cond) + stmts_size(&conseq.stmts) + stmts_size(&alt.stmts),
+        })
+        .sum()
+}
+
+/// The variables used by some statements, in the order they are first used, and those they
+/// declare or assign to
+#[derive(Default)]
+pub(crate) struct StmtVars {
+    pub(crate) used: Vec<String>,
+    pub(crate) declared: HashSet<String>,
+    pub(crate) assigned: HashSet<String>,
+}
+impl StmtVars {
+    pub(crate) fn of(stmts: &[Statement]) -> Self {
+        let mut vars = Self::default();
+        vars.add_stmts(stmts);
+        vars
+    }
+
+    pub(crate) fn add_stmts(&mut self, stmts: &[Statement]) {
+        for stmt in stmts.iter() {
+            match stmt {
+                Statement::Decl { var_id, .. } => {
+                    if let Expr::VarId { name, .. } = var_id {
+                        self.declared.insert(name.clone());
+                    }
+                }
+                Statement::Assign { var_id, expr, .. } => {
+                    if let Expr::VarId { name, .. } = var_id {
+                        self.assigned.insert(name.clone());
+                    }
+                    self.add_expr(expr);
+                }
+                Statement::Expr { expr, .. } | Statement::Return { expr, .. } => {
+                    self.add_expr(expr)
+                }
+                Statement::If {
+                    cond, conseq, alt, ..
+                } => {
+                    self.add_expr(cond);
+                    self.add_stmts(&conseq.stmts);
+                    self.add_stmts(&alt.stmts);
+                }
+            }
+        }
+    }
+
+    /// The numbers of the arguments of the instruction (`argN`) among the variables, in order
+    pub(crate) fn args(&self) -> Vec<usize> {
+        let mut args: Vec<usize> = self
+            .used
+            .iter()
+            .chain(self.declared.iter())
+            .chain(self.assigned.iter())
+            .filter_map(|name| name.strip_prefix("arg")?.parse::<usize>().ok())
+            .collect();
+        args.sort();
+        args.dedup();
+        args
+    }
+
+    pub(crate) fn add_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::UnOp { expr, .. } => self.add_expr(expr),
+            Expr::Ternary {
+                cond, conseq, alt, ..
+            } => {
+                self.add_expr(cond);
+                self.add_expr(conseq);
+                self.add_expr(alt);
+            }
+            Expr::BinOp { lhs, rhs, .. } => {
+                self.add_expr(lhs);
+                self.add_expr(rhs);
+            }
+            // the target is a function, not a variable
+            Expr::Call { args, .. } => args.iter().flatten().for_each(|arg| self.add_expr(arg)),
+            Expr::VarId { name, .. } => {
+                if !self.used.contains(name) {
+                    self.used.push(name.clone());
+                }
+            }
+            Expr::Primitive {
+                val: Value::Tuple { vals, .. },
+                ..
+            } => vals.iter().for_each(|val| self.add_expr(val)),
+            Expr::Primitive { .. } => {}
+        }
+    }
+}
+
+/// What a site passes to the function a probe's body was outlined into
+enum SiteArg {
+    /// A local of the site's function, e.g. a saved argument of the instruction (`argN`)
+    Local(LocalId),
+    /// A value the compiler provides for the site
+    Const(i32),
+}
+
+/// A parameter of the function a probe's body was outlined into, standing in for a variable
+/// whose value depends on the site
+struct OutlinedParam {
+    /// The record of the variable
+    rec_id: usize,
+    name: String,
+    ty: ValType,
+    arg: SiteArg,
+}
+
+/// The increments of a counter batched for a basic block of a function of the app
+struct BatchedIncr {
+    func_id: FunctionId,
+    /// The basic block, in the function's `Cfg`
+    block: usize,
+    global: GlobalId,
+    total: i32,
+    /// Where the first increment is in the script
+    loc: Option<Location>,
+}
+
+pub struct WasmRewritingEmitter {
+    pub app_wasm: walrus::Module,
+    pub table: SymbolTable,
+
+    // whamm! AST traversal bookkeeping
+    pub(crate) metadata: InsertionMetadata,
+    pub(crate) instr_iter: InstrIter,
+    pub(crate) emitting_instr: Option<EmittingInstrTracker>,
+    pub print_backend: PrintBackend,
+    /// The hash of the script, recorded in the `whamm` custom section (see `hash_script`)
+    pub script_hash: Option<String>,
+
+    /// The app before instrumentation, anything else was injected
+    app_items: AppItems,
+    /// The `whamm` section of the app, if it was already instrumented (see `--layer`)
+    prev_metadata: Option<serde_json::Value>,
+    /// Where instrumentation was injected
+    sites: Vec<InjectedSite>,
+    /// Maps the injected instructions back to the script
+    pub source_map: SourceMap,
+
+    /// The size (see `stmts_size`) above which a probe's body is outlined into a function called
+    /// from each site instead of being inlined, always inlined if `None`
+    inline_threshold: Option<usize>,
+    /// The functions the probes' bodies were outlined into, shared by the sites with the same body
+    /// (see `outlined_params`)
+    outlined_bodies: HashMap<String, FunctionId>,
+    /// The function being emitted into instead of the site's, while outlining a body
+    outlining: Option<FunctionId>,
+    /// The locals of the functions, shared by their sites
+    local_pool: LocalPool,
+    /// The number of instructions injected into (or, for `alt`, removed from) each block so far,
+    /// the sites are visited in order so this is what the app's indices of the later sites of a
+    /// block are off by
+    injected_per_block: HashMap<(FunctionId, InstrSeqId), isize>,
+    /// The block of the current site, its length before anything was injected there and the index
+    /// of the site in it
+    site_block: Option<(FunctionId, InstrSeqId, usize, usize)>,
+    /// The script location of the last code mapped at the current site, what the rest of the code
+    /// injected there (e.g. saving the arguments of the instruction) is mapped to. So, the source
+    /// map covers all the injected code, which `--layer` relies on.
+    site_loc: Option<Location>,
+    /// The control flow graphs of the app's functions, only built if counters are coalesced
+    cfgs: HashMap<FunctionId, Cfg>,
+    /// The variables whose increments are batched per basic block (see `coalesce_counters`)
+    counters: HashSet<String>,
+    batched_incrs: Vec<BatchedIncr>,
+    /// The index of the increments of each counter in `batched_incrs`, by basic block
+    batched_idxs: HashMap<(FunctionId, usize, GlobalId), usize>,
+
+    fn_providing_contexts: Vec<String>,
+}
+impl WasmRewritingEmitter {
+    pub fn new(app_wasm: walrus::Module, table: SymbolTable) -> Self {
+        let mut app_wasm = app_wasm;
+        let first_mem = app_wasm.memories.iter().next().map(|memory| memory.id());
+        match first_mem {
+            Some(mem_id) => Self::with_memory(app_wasm, table, mem_id, false),
+            None => {
+                let mem_id = add_instr_memory(&mut app_wasm);
+                Self::with_memory(app_wasm, table, mem_id, true)
+            }
+        }
+    }
+
+    /// Choose which memory the instrumentation's data (e.g. strings) lives in
+    pub fn new_with_memory(
+        app_wasm: walrus::Module,
+        table: SymbolTable,
+        instr_memory: InstrMemory,
+    ) -> Result<Self, Box<WhammError>> {
+        let mut app_wasm = app_wasm;
+        match instr_memory {
+            InstrMemory::Default => Ok(Self::new(app_wasm, table)),
+            InstrMemory::App(idx) => {
+                let app_mem = app_wasm
+                    .memories
+                    .iter()
+                    .nth(idx as usize)
+                    .map(|memory| memory.id());
+                match app_mem {
+                    Some(mem_id) => Ok(Self::with_memory(app_wasm, table, mem_id, false)),
+                    None => Err(Box::new(ErrorGen::get_unexpected_error(
+                        true,
+                        Some(format!(
+                            "Cannot keep the instrumentation's data in memory {idx}, \
+                        the app only has {} memories",
+                            app_wasm.memories.iter().count()
+                        )),
+                        None,
+                    ))),
+                }
+            }
+            InstrMemory::Dedicated => {
+                if app_wasm.memories.iter().next().is_some() {
+                    info!("Adding a dedicated memory for the instrumentation, the engine must support multi-memory");
+                }
+                let mem_id = add_instr_memory(&mut app_wasm);
+                Ok(Self::with_memory(app_wasm, table, mem_id, true))
+            }
+        }
+    }
+
+    pub(crate) fn with_memory(
+        app_wasm: walrus::Module,
+        table: SymbolTable,
+        mem_id: MemoryId,
+        is_dedicated_mem: bool,
+    ) -> Self {
+        // The app's strings live in its first memory
+        let app_mem_id = match app_wasm.memories.iter().next() {
+            Some(memory) => memory.id(),
+            None => mem_id,
+        };
+        let app_items = AppItems::new(&app_wasm);
+        let prev_metadata = whamm_section(&app_wasm);
+        let source_map = SourceMap::new(&app_wasm);
+        let (mem_base, unplaced_data) = if is_dedicated_mem {
+            // the memory is all ours
+            (0, 0)
+        } else {
+            free_mem_offset(&app_wasm, mem_id)
+        };
+
+        Self {
+            app_wasm,
+            table,
+            metadata: InsertionMetadata {
+                // curr_event: "".to_string(),
+                mem_id,
+                app_mem_id,
+                is_dedicated_mem,
+                mem_base,
+                unplaced_data,
+                curr_mem_offset: mem_base,
+                emitted_data: HashMap::new(),
+                print_fns: None,
+            },
+            print_backend: PrintBackend::HostImport,
+            script_hash: None,
+            app_items,
+            prev_metadata,
+            sites: vec![],
+            source_map,
+            inline_threshold: None,
+            outlined_bodies: HashMap::new(),
+            outlining: None,
+            local_pool: LocalPool::default(),
+            injected_per_block: HashMap::new(),
+            site_block: None,
+            site_loc: None,
+            cfgs: HashMap::new(),
+            counters: HashSet::new(),
+            batched_incrs: vec![],
+            batched_idxs: HashMap::new(),
+            instr_iter: InstrIter::new(),
+            emitting_instr: None,
+            fn_providing_contexts: vec!["whamm".to_string()],
+        }
+    }
+
+    /// The memory the instrumentation's data lives in
+    pub fn instr_mem_id(&self) -> MemoryId {
+        self.metadata.mem_id
+    }
+
+    /// Point the emitter at the current instruction, a new site
+    fn track_curr_instr(&mut self) -> bool {
+        self.count_injected();
+        let Some(curr_loc) = self.instr_iter.curr() else {
+            return false;
+        };
+        let (func_id, seq_id) = (curr_loc.wasm_func_id, curr_loc.instr_seq_id);
+        // the index found when looking for the sites is off by the code injected at the previous
+        // sites of the same block
+        let shift = self
+            .injected_per_block
+            .get(&(func_id, seq_id))
+            .copied()
+            .unwrap_or_default();
+        let index = curr_loc.index.saturating_add_signed(shift);
+        self.site_block = Some((func_id, seq_id, self.block_len(func_id, seq_id), index));
+
+        self.local_pool.next_site();
+        self.emitting_instr = Some(EmittingInstrTracker {
+            orig_instr_idx: index,
+            curr_seq_id: seq_id,
+            curr_idx: index,
+            main_seq_id: seq_id,
+            main_idx: index,
+            outer_seq_id: None,
+            outer_idx: None,
+            then_seq_id: None,
+            then_idx: None,
+            else_seq_id: None,
+            else_idx: None,
+        });
+        true
+    }
+
+    fn block_len(&self, func_id: FunctionId, seq_id: InstrSeqId) -> usize {
+        match &self.app_wasm.funcs.get(func_id).kind {
+            FunctionKind::Local(func) => func.block(seq_id).len(),
+            _ => 0,
+        }
+    }
+
+    /// Account for the instructions injected into the block of the last site (see
+    /// `injected_per_block`), and map those that aren't yet (see `site_loc`)
+    fn count_injected(&mut self) {
+        let site_loc = self.site_loc.take();
+        if let Some((func_id, seq_id, len, index)) = self.site_block.take() {
+            let injected = self.block_len(func_id, seq_id) as isize - len as isize;
+            *self
+                .injected_per_block
+                .entry((func_id, seq_id))
+                .or_default() += injected;
+            if let Some(loc) = site_loc {
+                // the instruction of the site keeps its location
+                let range = index..index + injected.max(0) as usize + 1;
+                let func = self.app_wasm.funcs.get_mut(func_id).kind.unwrap_local_mut();
+                self.source_map.map_injected(func, seq_id, range, &loc);
+            }
+        }
+    }
+
+    /// Remember that instrumentation is injected at the current location
+    fn record_site(&mut self) {
+        let Some(curr_loc) = self.instr_iter.curr() else {
+            return;
+        };
+        let Some(func_idx) = self.app_items.func_idxs.get(&curr_loc.wasm_func_id) else {
+            return;
+        };
+        let scopes = self.table.get_curr_scope_path();
+        let probe = scopes
+            .iter()
+            .filter(|scope| {
+                matches!(
+                    scope.ty,
+                    ScopeType::Provider | ScopeType::Package | ScopeType::Event
+                )
+            })
+            .map(|scope| scope.name.as_str())
+            .collect::<Vec<&str>>()
+            .join(":");
+        let mode = scopes
+            .iter()
+            .find(|scope| matches!(scope.ty, ScopeType::Probe))
+            .map(|scope| scope.name.clone())
+            .unwrap_or_default();
+
+        let site = InjectedSite {
+            func_idx: *func_idx,
+            offset: curr_loc.instr_loc.data(),
+            probe,
+            mode,
+        };
+        // the probes of a location are emitted one after the other
+        if self.sites.last() != Some(&site) {
+            self.sites.push(site);
+        }
+    }
+
+    /// Emit the increments batched per basic block (see `emit_counter_incrs`) at the start of their
+    /// basic block, after the code injected before its first instruction
+    fn emit_batched_incrs(&mut self) -> Result<(), Box<WhammError>> {
+        for batched in std::mem::take(&mut self.batched_incrs) {
+            let block = &self.cfgs[&batched.func_id].blocks[batched.block];
+            let func = self
+                .app_wasm
+                .funcs
+                .get_mut(batched.func_id)
+                .kind
+                .unwrap_local_mut();
+            let seq = func.block(block.seq);
+            let Some(idx) = block
+                .instrs
+                .iter()
+                .find_map(|loc| seq.iter().position(|(_, instr_loc)| instr_loc == loc))
+            else {
+                return Err(Box::new(ErrorGen::get_unexpected_error(
+                    true,
+                    Some(format!(
+                        "{UNEXPECTED_ERR_MSG} \
+                    Could not find the basic block to emit the increments of a counter into"
+                    )),
+                    None,
+                )));
+            };
+            func.builder_mut()
+                .instr_seq(block.seq)
+                .instr_at(
+                    idx,
+                    walrus::ir::GlobalGet {
+                        global: batched.global,
+                    },
+                )
+                .instr_at(
+                    idx + 1,
+                    walrus::ir::Const {
+                        value: walrus::ir::Value::I32(batched.total),
+                    },
+                )
+                .instr_at(
+                    idx + 2,
+                    walrus::ir::Binop {
+                        op: BinaryOp::I32Add,
+                    },
+                )
+                .instr_at(
+                    idx + 3,
+                    walrus::ir::GlobalSet {
+                        global: batched.global,
+                    },
+                );
+            if let Some(loc) = &batched.loc {
+                self.source_map
+                    .map_injected(func, block.seq, idx..idx + 4, loc);
+            }
+        }
+        self.batched_idxs.clear();
+        Ok(())
+    }
+
+    /// Where the next instruction is injected, to map the instructions injected from there on
+    /// (see `map_injected`)
+    fn injection_point(&self) -> Option<(InstrSeqId, usize)> {
+        let tracker = self.emitting_instr.as_ref()?;
+        Some((tracker.curr_seq_id, tracker.curr_idx))
+    }
+
+    /// Map the instructions injected since `from` (see `injection_point`) to the script location
+    /// they were emitted for (see `SourceMap`)
+    fn map_injected(&mut self, from: Option<(InstrSeqId, usize)>, loc: &Option<Location>) {
+        let (Some(loc), Some(curr_loc), Some((seq_id, start)), Some(tracker)) =
+            (loc, self.instr_iter.curr(), from, &self.emitting_instr)
+        else {
+            return;
+        };
+        self.site_loc = Some(loc.clone());
+        if tracker.curr_seq_id != seq_id {
+            // the emitter moved on to another sequence, e.g. into an injected conditional
+            return;
+        }
+        let end = tracker.curr_idx;
+        let func = self
+            .app_wasm
+            .funcs
+            .get_mut(self.outlining.unwrap_or(curr_loc.wasm_func_id))
+            .kind
+            .unwrap_local_mut();
+        self.source_map.map_injected(func, seq_id, start..end, loc);
+    }
+
+    /// The parameters of the function the body is outlined into, and the key of the function in
+    /// `outlined_bodies`: the sites of a probe share the function as long as the values that can't
+    /// be passed to it (i.e. strings) are the same. `None` if the body must be inlined, since it
+    /// assigns to a variable that depends on the site.
+    fn outlined_params(&self, body: &[Statement]) -> Option<(Vec<OutlinedParam>, String)> {
+        let vars = StmtVars::of(body);
+        let mut params = vec![];
+        let mut site_consts = vec![];
+        for name in vars
+            .used
+            .iter()
+            .filter(|name| !vars.declared.contains(*name))
+        {
+            let rec_id = self.table.lookup(name)?;
+            let Some(Record::Var { addr, value, .. }) = self.table.get_record(rec_id) else {
+                // e.g. a function
+                continue;
+            };
+            let arg = match (addr, value) {
+                (Some(VarAddr::Global { .. }), _) => continue,
+                (Some(VarAddr::Local { addr }), _) => SiteArg::Local(*addr),
+                (None, Some(Value::Integer { val, .. })) => SiteArg::Const(*val),
+                (None, Some(Value::Boolean { val, .. })) => SiteArg::Const(*val as i32),
+                (None, Some(Value::Str { val, .. })) => {
+                    site_consts.push(format!("{name}={val:?}"));
+                    continue;
+                }
+                _ => return None,
+            };
+            let ty = match arg {
+                SiteArg::Local(local) => self.app_wasm.locals.get(local).ty(),
+                SiteArg::Const(_) => ValType::I32,
+            };
+            params.push(OutlinedParam {
+                rec_id: *rec_id,
+                name: name.clone(),
+                ty,
+                arg,
+            });
+        }
+        // only the script's globals outlive the function
+        for name in vars.assigned.iter() {
+            if vars.declared.contains(name) {
+                continue;
+            }
+            let rec = self
+                .table
+                .lookup(name)
+                .and_then(|id| self.table.get_record(id));
+            if !matches!(
+                rec,
+                Some(Record::Var {
+                    addr: Some(VarAddr::Global { .. }),
+                    ..
+                })
+            ) {
+                return None;
+            }
+        }
+
+        let scopes: Vec<&str> = self
+            .table
+            .get_curr_scope_path()
+            .iter()
+            .map(|scope| scope.name.as_str())
+            .collect();
+        let param_tys: Vec<ValType> = params.iter().map(|param| param.ty).collect();
+        let key = format!(
+            "{}|{body:?}|{param_tys:?}|{}",
+            scopes.join(":"),
+            site_consts.join(",")
+        );
+        Some((params, key))
+    }
+
+    /// Emit the body into a function (once for all the sites sharing it, see `outlined_params`)
+    /// and a call to it at the current location
+    fn emit_outlined_body(
+        &mut self,
+        body: &mut [Statement],
+        params: Vec<OutlinedParam>,
+        key: String,
+    ) -> Result<bool, Box<WhammError>> {
+        let func_id = match self.outlined_bodies.get(&key) {
+            Some(func_id) => *func_id,
+            None => {
+                let param_tys: Vec<ValType> = params.iter().map(|param| param.ty).collect();
+                let param_locals: Vec<LocalId> = params
+                    .iter()
+                    .map(|param| add_local(&mut self.app_wasm.locals, param.ty, &param.name))
+                    .collect();
+                let mut outlined = FunctionBuilder::new(&mut self.app_wasm.types, &param_tys, &[]);
+                outlined.name(injected_name(&format!(
+                    "probe{}",
+                    self.outlined_bodies.len()
+                )));
+                let func_id = outlined.finish(param_locals.clone(), &mut self.app_wasm.funcs);
+                let entry = self
+                    .app_wasm
+                    .funcs
+                    .get(func_id)
+                    .kind
+                    .unwrap_local()
+                    .entry_block();
+
+                // while emitting the body, the variables that depend on the site are the
+                // function's parameters
+                let mut site_vars = vec![];
+                for (param, local) in params.iter().zip(param_locals) {
+                    if let Some(Record::Var { addr, value, .. }) =
+                        self.table.get_record_mut(&param.rec_id)
+                    {
+                        site_vars.push((
+                            param.rec_id,
+                            addr.replace(VarAddr::Local { addr: local }),
+                            value.take(),
+                        ));
+                    }
+                }
+                let site_tracker = self.emitting_instr.replace(EmittingInstrTracker {
+                    orig_instr_idx: 0usize,
+                    curr_seq_id: entry,
+                    curr_idx: 0usize,
+                    main_seq_id: entry,
+                    main_idx: 0usize,
+                    outer_seq_id: None,
+                    outer_idx: None,
+                    then_seq_id: None,
+                    then_idx: None,
+                    else_seq_id: None,
+                    else_idx: None,
+                });
+                self.outlining = Some(func_id);
+                let mut res = Ok(true);
+                for stmt in body.iter_mut() {
+                    res = self.emit_stmt(stmt);
+                    if res.is_err() {
+                        break;
+                    }
+                }
+                self.outlining = None;
+                self.emitting_instr = site_tracker;
+                for (rec_id, site_addr, site_value) in site_vars {
+                    if let Some(Record::Var { addr, value, .. }) =
+                        self.table.get_record_mut(&rec_id)
+                    {
+                        *addr = site_addr;
+                        *value = site_value;
+                    }
+                }
+                res?;
+
+                self.outlined_bodies.insert(key, func_id);
+                func_id
+            }
+        };
+
+        let from = self.injection_point();
+        let (Some(curr_loc), Some(tracker)) =
+            (self.instr_iter.curr_mut(), &mut self.emitting_instr)
+        else {
+            return Err(Box::new(ErrorGen::get_unexpected_error(
+                true,
+                Some(format!(
+                    "{UNEXPECTED_ERR_MSG} \
+                Something went wrong while emitting an instruction."
+                )),
+                None,
+            )));
+        };
+        let func = self
+            .app_wasm
+            .funcs
+            .get_mut(curr_loc.wasm_func_id)
+            .kind
+            .unwrap_local_mut();
+        let func_builder = func.builder_mut();
+        let mut instr_builder = func_builder.instr_seq(tracker.curr_seq_id);
+        for param in params.iter() {
+            match param.arg {
+                SiteArg::Local(local) => {
+                    instr_builder.instr_at(tracker.curr_idx, walrus::ir::LocalGet { local })
+                }
+                SiteArg::Const(val) => instr_builder.instr_at(
+                    tracker.curr_idx,
+                    walrus::ir::Const {
+                        value: walrus::ir::Value::I32(val),
+                    },
+                ),
+            };
+            tracker.curr_idx += 1;
+        }
+        instr_builder.instr_at(tracker.curr_idx, walrus::ir::Call { func: func_id });
+        tracker.curr_idx += 1;
+
+        // the call stands in for the whole body
+        if let Some(stmt) = body.first() {
+            self.map_injected(from, stmt.loc());
+        }
+        Ok(true)
+    }
+
+    /// The contents of the `whamm` custom section, as JSON. The scripts and sites of the previous
+    /// layers come first (see `--layer`).
+    pub fn metadata(&self) -> String {
+        let prev = |name: &str| -> Option<&serde_json::Value> {
+            let prev = &self.prev_metadata.as_ref()?[name];
+            (!prev.is_null()).then_some(prev)
+        };
+        let prev_list = |name: &str| -> Vec<serde_json::Value> {
+            prev(name)
+                .and_then(|prev| prev.as_array())
+                .cloned()
+                .unwrap_or_default()
+        };
+        let mut scripts = prev_list("scripts");
+        let layer = scripts.len();
+        scripts.push(serde_json::json!(self.script_hash));
+        let mut sites = prev_list("sites");
+        sites.extend(self.sites.iter().map(|site| {
+            serde_json::json!({
+                "func_index": site.func_idx,
+                "offset": site.offset,
+                "probe": site.probe,
+                "mode": site.mode,
+                "layer": layer,
+            })
+        }));
+        let (funcs, globals, data) = self.app_items.injected(&self.app_wasm);
+        let mut metadata = serde_json::json!({
+            "version": env!("CARGO_PKG_VERSION"),
+            "scripts": scripts,
+            "sites": sites,
+            "injected": { "funcs": funcs, "globals": globals, "data": data },
+            "app_funcs": self.app_items.app_funcs(&self.app_wasm),
+        });
+        let InsertionMetadata {
+            mem_id,
+            is_dedicated_mem,
+            mem_base,
+            curr_mem_offset,
+            ..
+        } = self.metadata;
+        if !is_dedicated_mem && curr_mem_offset > mem_base {
+            let mem_idx = self
+                .app_wasm
+                .memories
+                .iter()
+                .position(|memory| memory.id() == mem_id);
+            // the data of the previous layers comes before, when in the same memory
+            let start = prev("memory")
+                .filter(|prev| prev["index"].as_u64() == mem_idx.map(|idx| idx as u64))
+                .and_then(|prev| prev["start"].as_u64())
+                .unwrap_or(mem_base as u64);
+            metadata["memory"] = serde_json::json!({
+                "index": mem_idx,
+                "start": start,
+                "end": curr_mem_offset,
+            });
+        } else if let Some(prev) = prev("memory") {
+            metadata["memory"] = prev.clone();
+        }
+        serde_json::to_string_pretty(&metadata).unwrap()
+    }
+
+    /// Reserve the pages holding the instrumentation's data. A dedicated memory is sized to fit
+    /// it, the app's memory is grown when the module starts (see `grow_at_start`).
+    fn reserve_instr_mem(&mut self) -> Result<(), Box<WhammError>> {
+        let metadata = &self.metadata;
+        if metadata.curr_mem_offset == metadata.mem_base {
+            // no data to reserve room for
+            return Ok(());
+        }
+        if metadata.unplaced_data > 0 {
+            return Err(Box::new(ErrorGen::get_unexpected_error(
+                true,
+                Some(format!(
+                    "Cannot place the instrumentation's data in the app's memory, the address of {} \
+                    of its data segments is only known at runtime. Keep the instrumentation's data \
+                    in a dedicated memory with --multi-memory.",
+                    metadata.unplaced_data
+                )),
+                None,
+            )));
+        }
+        let pages = metadata.curr_mem_offset.div_ceil(WASM_PAGE_SIZE);
+        let memory = self.app_wasm.memories.get_mut(metadata.mem_id);
+        if pages <= memory.initial {
+            return Ok(());
+        }
+        let err_msg = if memory.import.is_some() {
+            Some("the memory is imported, so its size is chosen by the host")
+        } else if pages > memory.maximum.unwrap_or(MAX_WASM_PAGES) {
+            Some("the memory would grow past its maximum size")
+        } else {
+            None
+        };
+        if let Some(err_msg) = err_msg {
+            return Err(Box::new(ErrorGen::get_unexpected_error(
+                true,
+                Some(format!(
+                    "Cannot reserve {} page(s) for the instrumentation's data, {err_msg}. \
+                    Keep the instrumentation's data in a dedicated memory with --multi-memory.",
+                    pages - memory.initial
+                )),
+                None,
+            )));
+        }
+        if self.metadata.is_dedicated_mem {
+            memory.initial = pages;
+        } else {
+            self.grow_at_start(pages);
+        }
+        Ok(())
+    }
+
+    /// Grow the app's memory to `pages` when the module starts, before any code of the app runs,
+    /// and copy the instrumentation's data there. The memory's initial size is left as is, so what
+    /// the app takes for its heap at first (e.g. up to `__heap_end`) doesn't cover the data and
+    /// the memory the app grows afterwards comes past it.
+    fn grow_at_start(&mut self, pages: u32) {
+        let mem_id = self.metadata.mem_id;
+        // the segments are only copied to memory once it's grown
+        let data: Vec<(DataId, u32, usize)> = self
+            .app_wasm
+            .data
+            .iter()
+            .filter(|data| !self.app_items.data.contains(&data.id()))
+            .filter_map(|data| match data.kind {
+                DataKind::Active(ActiveData {
+                    memory,
+                    location: ActiveDataLocation::Absolute(addr),
+                }) if memory == mem_id => Some((data.id(), addr, data.value.len())),
+                _ => None,
+            })
+            .collect();
+
+        let mut reserve = FunctionBuilder::new(&mut self.app_wasm.types, &[], &[]);
+        reserve.name(injected_name("reserve_memory"));
+        let mut body = reserve.func_body();
+        // a previous instrumentation may have grown the memory further already (see `--layer`)
+        body.memory_size(mem_id)
+            .i32_const(pages as i32)
+            .binop(BinaryOp::I32LtU)
+            .if_else(
+                None,
+                |grow| {
+                    grow.i32_const(pages as i32)
+                        .memory_size(mem_id)
+                        .binop(BinaryOp::I32Sub)
+                        .memory_grow(mem_id)
+                        .i32_const(-1)
+                        .binop(BinaryOp::I32Eq)
+                        .if_else(
+                            None,
+                            |failed| {
+                                failed.unreachable();
+                            },
+                            |_| {},
+                        );
+                },
+                |_| {},
+            );
+        for (data_id, addr, len) in data.iter() {
+            body.i32_const(*addr as i32)
+                .i32_const(0)
+                .i32_const(*len as i32)
+                .memory_init(mem_id, *data_id)
+                .data_drop(*data_id);
+        }
+        // then whatever the module did at start
+        if let Some(start) = self.app_wasm.start {
+            body.call(start);
+        }
+        let reserve_id = reserve.finish(vec![], &mut self.app_wasm.funcs);
+        self.app_wasm.start = Some(reserve_id);
+
+        for (data_id, ..) in data {
+            self.app_wasm.data.get_mut(data_id).kind = DataKind::Passive;
+        }
+    }
+
+    fn emit_provided_fn(&mut self, context: &str, f: &Fn) -> Result<bool, Box<WhammError>> {
+        if context == "whamm" && f.name.name == "strcmp" {
+            self.emit_whamm_strcmp_fn(f)
+        } else if context == "whamm" && f.name.name == "print" {
+            // Only injected if the script prints something, see `print_fns`
+            Ok(true)
+        } else {
+            Err(Box::new(ErrorGen::get_unexpected_error(
+                true,
+                Some(format!(
+                    "{UNEXPECTED_ERR_MSG} \
+            Provided function, but could not find a context to provide the definition, context: {}",
+                    context
+                )),
+                None,
+            )))
+        }
+    }
+
+    /// Reserve a region of memory, returns its address
+    fn reserve_mem(&mut self, size: u32) -> u32 {
+        let addr = self.metadata.curr_mem_offset;
+        self.metadata.curr_mem_offset += size;
+        addr
+    }
+
+    /// The memory the data of the previous layer lives in (see `--layer`)
+    fn prev_instr_mem(&self) -> Option<MemoryId> {
+        let prev = self.prev_metadata.as_ref()?;
+        match prev["memory"]["index"].as_u64() {
+            Some(idx) => self
+                .app_wasm
+                .memories
+                .iter()
+                .nth(idx as usize)
+                .map(|memory| memory.id()),
+            None => self
+                .app_wasm
+                .exports
+                .iter()
+                .find_map(|export| match export.item {
+                    ExportItem::Memory(mem_id) if export.name == INSTR_MEMORY_EXPORT => {
+                        Some(mem_id)
+                    }
+                    _ => None,
+                }),
+        }
+    }
+
+    /// The helper function (e.g. `strcmp`) a previous layer injected, to be reused by this one.
+    /// Only if its data is in the same memory as this layer's, which the helper works on.
+    fn prev_helper(&self, name: &str) -> Option<FunctionId> {
+        if self.prev_instr_mem() != Some(self.metadata.mem_id) {
+            return None;
+        }
+        let name = injected_name(name);
+        self.app_wasm
+            .funcs
+            .iter()
+            .find(|func| {
+                self.app_items.is_injected(&func.id()) && func.name.as_ref() == Some(&name)
+            })
+            .map(|func| func.id())
+    }
+
+    /// The functions a previous layer injected to print through the same backend
+    fn prev_print_fns(&self) -> Option<PrintFns> {
+        let to_buffer = self
+            .app_wasm
+            .exports
+            .iter()
+            .any(|export| export.name == "whamm_output");
+        let print_str = match self.print_backend {
+            PrintBackend::HostImport => {
+                self.app_wasm.imports.get_func(HOST_MODULE, "print").ok()?
+            }
+            PrintBackend::Buffer if to_buffer => self.prev_helper("print_str")?,
+            PrintBackend::Wasi if !to_buffer => self.prev_helper("print_str")?,
+            _ => return None,
+        };
+        Some(PrintFns {
+            print_str,
+            print_i32: self.prev_helper("print_i32")?,
+            print_u32: self.prev_helper("print_u32")?,
+            print_bool: self.prev_helper("print_bool")?,
+        })
+    }
+
+    /// The functions to print the output of the instrumentation through the configured backend.
+    /// These are injected into the app the first time they're needed, unless a previous layer
+    /// already did.
+    pub(crate) fn print_fns(&mut self) -> PrintFns {
+        if let Some(print_fns) = self.metadata.print_fns {
+            return print_fns;
+        }
+        if let Some(print_fns) = self.prev_print_fns() {
+            self.metadata.print_fns = Some(print_fns);
+            return print_fns;
+        }
+        let print_str = match self.print_backend {
+            PrintBackend::HostImport => self.emit_host_print_str(),
+            PrintBackend::Buffer => self.emit_buffer_print_str(),
+            PrintBackend::Wasi => self.emit_wasi_print_str(),
+        };
+        let print_fns = PrintFns {
+            print_str,
+            print_i32: self.emit_print_int(print_str, true),
+            print_u32: self.emit_print_int(print_str, false),
+            print_bool: self.emit_print_bool(print_str),
+        };
+        self.metadata.print_fns = Some(print_fns);
+        print_fns
+    }
+
+    fn emit_host_print_str(&mut self) -> FunctionId {
+        if let Ok(print) = self.app_wasm.imports.get_func(HOST_MODULE, "print") {
+            return print;
+        }
+        let ty = self.app_wasm.types.add(&[ValType::I32, ValType::I32], &[]);
+        let (print, ..) = self.app_wasm.add_import_func(HOST_MODULE, "print", ty);
+        self.app_wasm.funcs.get_mut(print).name = Some(injected_name("print"));
+        print
+    }
+
+    fn emit_buffer_print_str(&mut self) -> FunctionId {
+        let buffer = self.reserve_mem(PRINT_BUFFER_SIZE) as i32;
+        let mem_id = self.metadata.mem_id;
+        let app_wasm = &mut self.app_wasm;
+
+        let buffer_len = app_wasm.globals.add_local(
+            ValType::I32,
+            true,
+            InitExpr::Value(walrus::ir::Value::I32(0)),
+        );
+        let buffer_addr = app_wasm.globals.add_local(
+            ValType::I32,
+            false,
+            InitExpr::Value(walrus::ir::Value::I32(buffer)),
+        );
+        app_wasm.globals.get_mut(buffer_len).name = Some(injected_name("output_len"));
+        app_wasm.globals.get_mut(buffer_addr).name = Some(injected_name("output"));
+        app_wasm.exports.add("whamm_output", buffer_addr);
+        app_wasm.exports.add("whamm_output_len", buffer_len);
+
+        // print_str(ptr, len): append as much as fits to the end of the buffer
+        let ptr = add_local(&mut app_wasm.locals, ValType::I32, "ptr");
+        let len = add_local(&mut app_wasm.locals, ValType::I32, "len");
+        let i = add_local(&mut app_wasm.locals, ValType::I32, "i");
+        let mut print_str =
+            FunctionBuilder::new(&mut app_wasm.types, &[ValType::I32, ValType::I32], &[]);
+        print_str.name(injected_name("print_str"));
+        print_str
+            .func_body()
+            // len = min(len, remaining)
+            .local_get(len)
+            .i32_const(PRINT_BUFFER_SIZE as i32)
+            .global_get(buffer_len)
+            .binop(BinaryOp::I32Sub)
+            .local_get(len)
+            .i32_const(PRINT_BUFFER_SIZE as i32)
+            .global_get(buffer_len)
+            .binop(BinaryOp::I32Sub)
+            .binop(BinaryOp::I32LtU)
+            .select(None)
+            .local_set(len)
+            .i32_const(0)
+            .local_set(i)
+            .block(None, |done| {
+                let done_id = done.id();
+                done.loop_(None, |copy| {
+                    let copy_id = copy.id();
+                    copy.local_get(i)
+                        .local_get(len)
+                        .binop(BinaryOp::I32GeU)
+                        .br_if(done_id)
+                        // buffer[buffer_len + i] = ptr[i]
+                        .i32_const(buffer)
+                        .global_get(buffer_len)
+                        .binop(BinaryOp::I32Add)
+                        .local_get(i)
+                        .binop(BinaryOp::I32Add)
+                        .local_get(ptr)
+                        .local_get(i)
+                        .binop(BinaryOp::I32Add)
+                        .load(
+                            mem_id,
+                            LoadKind::I32_8 {
+                                kind: ExtendedLoad::ZeroExtend,
+                            },
+                            MemArg {
+                                offset: 0,
+                                align: 1,
+                            },
+                        )
+                        .store(
+                            mem_id,
+                            StoreKind::I32_8 { atomic: false },
+                            MemArg {
+                                offset: 0,
+                                align: 1,
+                            },
+                        )
+                        .local_get(i)
+                        .i32_const(1)
+                        .binop(BinaryOp::I32Add)
+                        .local_set(i)
+                        .br(copy_id);
+                });
+            })
+            .global_get(buffer_len)
+            .local_get(len)
+            .binop(BinaryOp::I32Add)
+            .global_set(buffer_len);
+        print_str.finish(vec![ptr, len], &mut app_wasm.funcs)
+    }
+
+    fn emit_wasi_print_str(&mut self) -> FunctionId {
+        let fd_write = match self.app_wasm.imports.get_func(WASI_MODULE, "fd_write") {
+            Ok(fd_write) => fd_write,
+            Err(..) => {
+                let ty = self.app_wasm.types.add(
+                    &[ValType::I32, ValType::I32, ValType::I32, ValType::I32],
+                    &[ValType::I32],
+                );
+                let (fd_write, ..) = self.app_wasm.add_import_func(WASI_MODULE, "fd_write", ty);
+                self.app_wasm.funcs.get_mut(fd_write).name = Some(injected_name("fd_write"));
+                fd_write
+            }
+        };
+        // room for an iovec and `nwritten`
+        let iovec = self.reserve_mem(12) as i32;
+        let nwritten = iovec + 8;
+        let mem_id = self.metadata.mem_id;
+        let app_wasm = &mut self.app_wasm;
+        let store_arg = MemArg {
+            align: 4,
+            offset: 0,
+        };
+
+        // print_str(ptr, len): write a single iovec to stderr
+        let ptr = add_local(&mut app_wasm.locals, ValType::I32, "ptr");
+        let len = add_local(&mut app_wasm.locals, ValType::I32, "len");
+        let mut print_str =
+            FunctionBuilder::new(&mut app_wasm.types, &[ValType::I32, ValType::I32], &[]);
+        print_str.name(injected_name("print_str"));
+        print_str
+            .func_body()
+            .i32_const(iovec)
+            .local_get(ptr)
+            .store(mem_id, StoreKind::I32 { atomic: false }, store_arg)
+            .i32_const(iovec + 4)
+            .local_get(len)
+            .store(mem_id, StoreKind::I32 { atomic: false }, store_arg)
+            .i32_const(STDERR_FD)
+            .i32_const(iovec)
+            .i32_const(1)
+            .i32_const(nwritten)
+            .call(fd_write)
+            .drop();
+        print_str.finish(vec![ptr, len], &mut app_wasm.funcs)
+    }
+
+    /// print_int(val): write the digits (back to front) into a scratch buffer, then print it
+    fn emit_print_int(&mut self, print_str: FunctionId, is_signed: bool) -> FunctionId {
+        let digits_end = (self.reserve_mem(PRINT_DIGITS_SIZE) + PRINT_DIGITS_SIZE) as i32;
+        let mem_id = self.metadata.mem_id;
+        let app_wasm = &mut self.app_wasm;
+        let store_arg = MemArg {
+            align: 1,
+            offset: 0,
+        };
+
+        let val = add_local(&mut app_wasm.locals, ValType::I32, "val");
+        let pos = add_local(&mut app_wasm.locals, ValType::I32, "pos");
+        let is_neg = add_local(&mut app_wasm.locals, ValType::I32, "is_neg");
+        let mut print_int = FunctionBuilder::new(&mut app_wasm.types, &[ValType::I32], &[]);
+        print_int.name(if is_signed {
+            injected_name("print_i32")
+        } else {
+            injected_name("print_u32")
+        });
+        let mut body = print_int.func_body();
+        if is_signed {
+            body.local_get(val)
+                .i32_const(0)
+                .binop(BinaryOp::I32LtS)
+                .local_tee(is_neg)
+                .if_else(
+                    None,
+                    |then| {
+                        // use the magnitude, the unsigned ops below also handle i32::MIN
+                        then.i32_const(0)
+                            .local_get(val)
+                            .binop(BinaryOp::I32Sub)
+                            .local_set(val);
+                    },
+                    |_| {},
+                );
+        }
+        body.i32_const(digits_end)
+            .local_set(pos)
+            .loop_(None, |digits| {
+                let digits_id = digits.id();
+                digits
+                    .local_get(pos)
+                    .i32_const(1)
+                    .binop(BinaryOp::I32Sub)
+                    .local_tee(pos)
+                    .local_get(val)
+                    .i32_const(10)
+                    .binop(BinaryOp::I32RemU)
+                    .i32_const(b'0' as i32)
+                    .binop(BinaryOp::I32Add)
+                    .store(mem_id, StoreKind::I32_8 { atomic: false }, store_arg)
+                    .local_get(val)
+                    .i32_const(10)
+                    .binop(BinaryOp::I32DivU)
+                    .local_tee(val)
+                    .br_if(digits_id);
+            });
+        if is_signed {
+            body.local_get(is_neg).if_else(
+                None,
+                |then| {
+                    then.local_get(pos)
+                        .i32_const(1)
+                        .binop(BinaryOp::I32Sub)
+                        .local_tee(pos)
+                        .i32_const(b'-' as i32)
+                        .store(mem_id, StoreKind::I32_8 { atomic: false }, store_arg);
+                },
+                |_| {},
+            );
+        }
+        body.local_get(pos)
+            .i32_const(digits_end)
+            .local_get(pos)
+            .binop(BinaryOp::I32Sub)
+            .call(print_str);
+        print_int.finish(vec![val], &mut app_wasm.funcs)
+    }
+
+    fn emit_print_bool(&mut self, print_str: FunctionId) -> FunctionId {
+        let (_, true_addr) =
+            emit_data(&mut self.app_wasm.data, &mut self.metadata, b"true", "true");
+        let (_, false_addr) = emit_data(
+            &mut self.app_wasm.data,
+            &mut self.metadata,
+            b"false",
+            "false",
+        );
+        let app_wasm = &mut self.app_wasm;
+
+        let val = add_local(&mut app_wasm.locals, ValType::I32, "val");
+        let mut print_bool = FunctionBuilder::new(&mut app_wasm.types, &[ValType::I32], &[]);
+        print_bool.name(injected_name("print_bool"));
+        print_bool.func_body().local_get(val).if_else(
+            None,
+            |then| {
+                then.i32_const(true_addr as i32)
+                    .i32_const(4)
+                    .call(print_str);
+            },
+            |alt| {
+                alt.i32_const(false_addr as i32)
+                    .i32_const(5)
+                    .call(print_str);
+            },
+        );
+        print_bool.finish(vec![val], &mut app_wasm.funcs)
+    }
+
+    fn emit_whamm_strcmp_fn(&mut self, f: &Fn) -> Result<bool, Box<WhammError>> {
+        let strcmp_id = match self.prev_helper("strcmp") {
+            Some(strcmp_id) => strcmp_id,
+            None => self.emit_strcmp(),
+        };
+        let rec_id = match self.table.lookup(&f.name.name) {
+            Some(rec_id) => *rec_id,
+            _ => {
+                return Err(Box::new(ErrorGen::get_unexpected_error(
+                    true,
+                    Some(format!(
+                        "{UNEXPECTED_ERR_MSG} \
+                `strcmp` fn symbol does not exist in this scope!"
+                    )),
+                    None,
+                )));
+            }
+        };
+
+        return if let Some(rec) = self.table.get_record_mut(&rec_id) {
+            if let Record::Fn { addr, .. } = rec {
+                *addr = Some(strcmp_id);
+                Ok(true)
+            } else {
+                return Err(Box::new(ErrorGen::get_unexpected_error(
+                    true,
+                    Some(format!(
+                        "{UNEXPECTED_ERR_MSG} \
+                Incorrect global variable record, expected Record::Var, found: {:?}",
+                        rec
+                    )),
+                    None,
+                )));
+            }
+        } else {
+            return Err(Box::new(ErrorGen::get_unexpected_error(
+                true,
+                Some(format!(
+                    "{UNEXPECTED_ERR_MSG} \
+            Global variable symbol does not exist!"
+                )),
+                None,
+            )));
+        };
+    }
+
+    /// Inject `strcmp(str0_offset, str0_size, str1_offset, str1_size)`, comparing a string of the
+    /// app's memory to a string of the instrumentation's memory
+    fn emit_strcmp(&mut self) -> FunctionId {
+        let strcmp_params = vec![ValType::I32, ValType::I32, ValType::I32, ValType::I32];
+        let strcmp_result = vec![ValType::I32];
+
+        let mut strcmp =
+            FunctionBuilder::new(&mut self.app_wasm.types, &strcmp_params, &strcmp_result);
+        strcmp.name(injected_name("strcmp"));
+
+        // create params
+        let str0_offset = add_local(&mut self.app_wasm.locals, ValType::I32, "str0_offset");
+        let str0_size = add_local(&mut self.app_wasm.locals, ValType::I32, "str0_size");
+        let str1_offset = add_local(&mut self.app_wasm.locals, ValType::I32, "str1_offset");
+        let str1_size = add_local(&mut self.app_wasm.locals, ValType::I32, "str1_size");
+
+        // create locals
+        let i = add_local(&mut self.app_wasm.locals, ValType::I32, "i");
+        let str0_char = add_local(&mut self.app_wasm.locals, ValType::I32, "str0_char");
+        let str1_char = add_local(&mut self.app_wasm.locals, ValType::I32, "str1_char");
+
+        // create the body of strcmp
+        strcmp
+            .func_body()
+            .block(None, |neq_block| {
+                let neq = neq_block.id();
+
+                neq_block
+                    .block(None, |eq_block| {
+                        let eq = eq_block.id();
+
+                        // 1. Check if sizes are equal, if not return 0
+                        eq_block
+                            .local_get(str0_size)
+                            .local_get(str1_size)
+                            .binop(BinaryOp::I32Ne)
+                            .br_if(neq);
+
+                        // 2. Check if mem offset is equal, if yes return non-zero (we are comparing the same data)
+                        eq_block
+                            .local_get(str0_offset)
+                            .local_get(str1_offset)
+                            .binop(BinaryOp::I32Eq)
+                            .br_if(eq);
+
+                        // 3. iterate over each string and check equivalence of chars, if any not equal, return 0
+                        eq_block
+                            .i32_const(0)
+                            .local_set(i)
+                            .loop_(None, |loop_| {
+                                let cmp_char = loop_.id();
+
+                                // Check if we've reached the end of the string
+                                loop_
+                                    .local_get(i)
+                                    .local_get(str0_size) // (can compare with either str size, equal at this point)
+                                    .binop(BinaryOp::I32LtU)
+                                    .i32_const(0)
+                                    .binop(BinaryOp::I32Eq)
+                                    .br_if(eq); // We've reached the end without failing equality checks!
+
+                                // get char for str0
+                                loop_
+                                    .local_get(str0_offset)
+                                    .local_get(i)
+                                    .binop(BinaryOp::I32Add)
+                                    .load(
+                                        self.metadata.app_mem_id,
+                                        LoadKind::I32_8 {
+                                            kind: ExtendedLoad::ZeroExtend,
+                                        },
+                                        MemArg {
+                                            offset: 0,
+                                            align: 1,
+                                        },
+                                    )
+                                    .local_set(str0_char);
+
+                                // get char for str1
+                                loop_
+                                    .local_get(str1_offset)
+                                    .local_get(i)
+                                    .binop(BinaryOp::I32Add)
+                                    .load(
+                                        self.metadata.mem_id,
+                                        LoadKind::I32_8 {
+                                            kind: ExtendedLoad::ZeroExtend,
+                                        },
+                                        MemArg {
+                                            offset: 0,
+                                            align: 1,
+                                        },
+                                    )
+                                    .local_set(str1_char);
+
+                                // compare the two chars
+                                loop_
+                                    .local_get(str0_char)
+                                    .local_get(str1_char)
+                                    .binop(BinaryOp::I32Ne)
+                                    .br_if(neq); // If they are not equal, exit and return '0'
+
+                                // Increment i and continue loop
+                                loop_
+                                    .local_get(i)
+                                    .i32_const(1)
+                                    .binop(BinaryOp::I32Add)
+                                    .local_set(i)
+                                    .br(cmp_char);
+                            })
+                            // 4. Reached the end of each string without returning, return nonzero
+                            .br(eq);
+                    })
+                    // they are equal, return '1'
+                    .i32_const(1)
+                    .return_();
+            })
+            // they are not equal, return '0'
+            .i32_const(0)
+            .return_();
+
+        strcmp.finish(
+            vec![str0_offset, str0_size, str1_offset, str1_size],
+            &mut self.app_wasm.funcs,
+        )
+    }
+
+    fn emit_decl_stmt(&mut self, stmt: &mut Statement) -> Result<bool, Box<WhammError>> {
+        match stmt {
+            Statement::Decl { ty, var_id, .. } => {
+                // look up in symbol table
+                let mut addr = if let Expr::VarId { name, .. } = var_id {
+                    let var_rec_id = match self.table.lookup(name) {
+                        Some(rec_id) => *rec_id,
+                        None => {
+                            // TODO -- add variables from body into symbol table
+                            //         (at this point, the verifier should have run to catch variable initialization without declaration)
+                            self.table.put(
+                                name.clone(),
+                                Record::Var {
+                                    ty: ty.clone(),
+                                    name: name.clone(),
+                                    value: None,
+                                    is_comp_provided: false,
+                                    addr: None,
+                                    loc: None,
+                                },
+                            )
+                        }
+                    };
+                    match self.table.get_record_mut(&var_rec_id) {
+                        Some(Record::Var { addr, .. }) => addr,
+                        Some(ty) => {
+                            return Err(Box::new(ErrorGen::get_unexpected_error(
+                                true,
+                                Some(format!(
+                                    "{UNEXPECTED_ERR_MSG} \
+                            Incorrect variable record, expected Record::Var, found: {:?}",
+                                    ty
+                                )),
+                                None,
+                            )));
+                        }
+                        None => {
+                            return Err(Box::new(ErrorGen::get_unexpected_error(
+                                true,
+                                Some(format!(
+                                    "{UNEXPECTED_ERR_MSG} \
+                            Variable symbol does not exist!"
+                                )),
+                                None,
+                            )));
+                        }
+                    }
+                } else {
+                    return Err(Box::new(ErrorGen::get_unexpected_error(
+                        true,
+                        Some(format!(
+                            "{UNEXPECTED_ERR_MSG} \
+                    Expected VarId."
+                        )),
+                        None,
+                    )));
+                };
+
+                match &mut addr {
+                    Some(VarAddr::Global { addr: _addr }) => {
+                        // The global should already exist, do any initial setup here!
+                        match ty {
+                            DataType::Map {
+                                key_ty: _key_ty,
+                                val_ty: _val_ty,
+                            } => {
+                                // initialize map global variable
+                                // also update value at GID (probably need to set ID of map there)
+                                unimplemented!()
+                            }
+                            _ => Ok(true),
+                        }
+                    }
+                    Some(VarAddr::Local { .. }) | None => {
+                        // If the local already exists, it would be because the probe has been
+                        // emitted at another bytecode location. Simply overwrite the previously saved
+                        // address.
+                        let (walrus_ty, zero) = data_type_to_val_type(ty);
+                        let name = match var_id {
+                            Expr::VarId { name, .. } => name.as_str(),
+                            _ => "var",
+                        };
+                        let (id, is_reused) = match self.instr_iter.curr() {
+                            Some(curr_loc) => self.local_pool.take(
+                                &mut self.app_wasm.locals,
+                                self.outlining.unwrap_or(curr_loc.wasm_func_id),
+                                walrus_ty,
+                                name,
+                            ),
+                            None => (add_local(&mut self.app_wasm.locals, walrus_ty, name), false),
+                        };
+                        *addr = Some(VarAddr::Local { addr: id });
+
+                        // a previous site may have left a value in the local
+                        if let (true, Some(curr_loc), Some(tracker), InitExpr::Value(zero)) = (
+                            is_reused,
+                            self.instr_iter.curr(),
+                            &mut self.emitting_instr,
+                            zero,
+                        ) {
+                            let func = self
+                                .app_wasm
+                                .funcs
+                                .get_mut(self.outlining.unwrap_or(curr_loc.wasm_func_id))
+                                .kind
+                                .unwrap_local_mut();
+                            let func_builder = func.builder_mut();
+                            let mut instr_builder = func_builder.instr_seq(tracker.curr_seq_id);
+                            instr_builder
+                                .instr_at(tracker.curr_idx, walrus::ir::Const { value: zero });
+                            tracker.curr_idx += 1;
+                            instr_builder
+                                .instr_at(tracker.curr_idx, walrus::ir::LocalSet { local: id });
+                            tracker.curr_idx += 1;
+                        }
+                        Ok(true)
+                    }
+                }
+            }
+            _ => Err(Box::new(ErrorGen::get_unexpected_error(
+                false,
+                Some(format!(
+                    "{UNEXPECTED_ERR_MSG} \
+                    Wrong statement type, should be `assign`"
+                )),
+                None,
+            ))),
+        }
+    }
+
+    fn emit_assign_stmt(&mut self, stmt: &mut Statement) -> Result<bool, Box<WhammError>> {
+        return match stmt {
+            Statement::Assign { var_id, expr, .. } => {
+                let mut folded_expr = ExprFolder::fold_expr(expr, &self.table);
+
+                // Save off primitives to symbol table
+                // TODO -- this is only necessary for `new_target_fn_name`, remove after deprecating!
+                if let (Expr::VarId { name, .. }, Expr::Primitive { val, .. }) =
+                    (&var_id, &folded_expr)
+                {
+                    let var_rec_id = match self.table.lookup(name) {
+                        Some(rec_id) => *rec_id,
+                        _ => {
+                            return Err(Box::new(ErrorGen::get_unexpected_error(
+                                true,
+                                Some(format!(
+                                    "{UNEXPECTED_ERR_MSG} \
+                                    Attempting to emit an assign, but VarId '{name}' does not exist in this scope!"
+                                )),
+                                None,
+                            )));
+                        }
+                    };
+                    match self.table.get_record_mut(&var_rec_id) {
+                        Some(Record::Var {
+                            value,
+                            is_comp_provided,
+                            ..
+                        }) => {
+                            // the script's variables may be assigned other values at other sites
+                            // (see `propagate_consts` for those that can't)
+                            if *is_comp_provided {
+                                *value = Some(val.clone());
+                                return Ok(true);
+                            }
+                        }
+                        Some(ty) => {
+                            return Err(Box::new(ErrorGen::get_unexpected_error(
+                                true,
+                                Some(format!(
+                                    "{UNEXPECTED_ERR_MSG} \
+                                    Incorrect variable record, expected Record::Var, found: {:?}",
+                                    ty
+                                )),
+                                None,
+                            )));
+                        }
+                        None => {
+                            return Err(Box::new(ErrorGen::get_unexpected_error(
+                                true,
+                                Some(format!(
+                                    "{UNEXPECTED_ERR_MSG} \
+                                    Variable symbol does not exist!"
+                                )),
+                                None,
+                            )));
+                        }
+                    }
+                }
+
+                match self.emit_expr(&mut folded_expr) {
+                    Err(e) => Err(e),
+                    Ok(_) => {
+                        if let Some(curr_loc) = self.instr_iter.curr_mut() {
+                            if let Some(tracker) = &mut self.emitting_instr {
+                                let func = self
+                                    .app_wasm
+                                    .funcs
+                                    .get_mut(self.outlining.unwrap_or(curr_loc.wasm_func_id))
+                                    .kind
+                                    .unwrap_local_mut();
+                                let func_builder = func.builder_mut();
+                                let mut instr_builder = func_builder.instr_seq(tracker.curr_seq_id);
+
+                                // Emit the instruction that sets the variable's value to the emitted expression
+                                emit_set(
+                                    &mut self.table,
+                                    var_id,
+                                    &mut instr_builder,
+                                    &mut tracker.curr_idx,
+                                )
+                            } else {
+                                return Err(Box::new(ErrorGen::get_unexpected_error(
+                                    true,
+                                    Some(format!(
+                                        "{UNEXPECTED_ERR_MSG} \
+                                            Something went wrong while emitting an instruction."
+                                    )),
+                                    None,
+                                )));
+                            }
+                        } else {
+                            return Err(Box::new(ErrorGen::get_unexpected_error(
+                                true,
+                                Some(format!(
+                                    "{UNEXPECTED_ERR_MSG} \
+                                        Something went wrong while emitting an instruction."
+                                )),
+                                None,
+                            )));
+                        }
+                    }
+                }
+            }
+            _ => {
+                return Err(Box::new(ErrorGen::get_unexpected_error(
+                    false,
+                    Some(format!(
+                        "{UNEXPECTED_ERR_MSG} \
+                    Wrong statement type, should be `assign`"
+                    )),
+                    None,
+                )));
+            }
+        };
+    }
+}
+
+impl Emitter for WasmRewritingEmitter {
+    fn enter_scope(&mut self) -> Result<(), Box<WhammError>> {
+        self.table.enter_scope()
+    }
+    fn enter_named_scope(&mut self, scope_name: &str) -> bool {
+        self.table.enter_named_scope(scope_name)
+    }
+    fn exit_scope(&mut self) -> Result<(), Box<WhammError>> {
+        self.table.exit_scope()
+    }
+    fn reset_children(&mut self) {
+        self.table.reset_children();
+    }
+
+    fn add_func_filter(&mut self, filter: FuncFilter) {
+        self.instr_iter.func_filter.extend(filter);
+    }
+
+    fn set_jobs(&mut self, jobs: usize) {
+        self.instr_iter.jobs = jobs;
+    }
+
+    fn set_inline_threshold(&mut self, threshold: Option<usize>) {
+        self.inline_threshold = threshold;
+    }
+
+    fn coalesce_counters(&mut self, counters: HashSet<String>) {
+        // built before anything is injected, over the app's instructions
+        self.cfgs = self
+            .app_wasm
+            .funcs
+            .iter_local()
+            .map(|(func_id, func)| (func_id, Cfg::new(func)))
+            .collect();
+        self.counters = counters;
+    }
+
+    fn init_instr_iter(
+        &mut self,
+        instrs_of_interest: &HashSet<BytecodeEventKind>,
+    ) -> Result<(), Box<WhammError>> {
+        self.instr_iter
+            .init(&self.app_wasm, instrs_of_interest, &self.app_items);
+        Ok(())
+    }
+
+    /// bool -> whether there is a next instruction to process
+    fn has_next_instr(&self) -> bool {
+        self.instr_iter.has_next()
+    }
+
+    fn init_first_instr(&mut self) -> bool {
+        self.track_curr_instr()
+    }
+
+    /// bool -> whether it found a next instruction
+    fn next_instr(&mut self) -> bool {
+        if self.instr_iter.has_next() && self.instr_iter.next().is_some() {
+            return self.track_curr_instr();
+        }
+        false
+    }
+
+    /// bool -> whether the current instruction is one of the passed list of types
+    fn curr_instr_type(&mut self) -> &'static str {
+        if let Some(instr) = self.instr_iter.curr() {
+            return instr.event.name();
+        }
+        unreachable!()
+    }
+
+    fn curr_site(&self) -> Option<(u32, usize)> {
+        let curr_loc = self.instr_iter.curr()?;
+        let func_idx = self.app_items.func_idxs.get(&curr_loc.wasm_func_id)?;
+        Some((*func_idx, curr_loc.instr_loc.data() as usize))
+    }
+
+    fn incr_loc_pointer(&mut self) {
+        if let Some(tracker) = &mut self.emitting_instr {
+            tracker.curr_idx += 1;
+            tracker.main_idx += 1;
+        }
+    }
+
+    fn has_params(&mut self) -> Result<bool, Box<WhammError>> {
+        if let Some(curr_instr) = self.instr_iter.curr_mut() {
+            return Ok(!curr_instr.instr_params.is_empty());
+        }
+        Err(Box::new(ErrorGen::get_unexpected_error(
+            true,
+            Some(format!(
+                "{UNEXPECTED_ERR_MSG} \
+        Something went wrong when trying to access the current instruction."
+            )),
+            None,
+        )))
+    }
+
+    fn save_params(&mut self) -> bool {
+        if let Some(curr_loc) = self.instr_iter.curr_mut() {
+            if let Some(tracker) = &mut self.emitting_instr {
+                let func = self
+                    .app_wasm
+                    .funcs
+                    .get_mut(curr_loc.wasm_func_id)
+                    .kind
+                    .unwrap_local_mut();
+                let func_builder = func.builder_mut();
+                let mut instr_builder = func_builder.instr_seq(tracker.curr_seq_id);
+
+                // No bytecodes should have been emitted in the module yet!
+                // So, we can just save off the first * items in the stack as the args
+                // to the call, starting from the last one (on top of the stack).
+                let mut arg_recs = vec![]; // vec to retain order!
+                curr_loc
+                    .instr_params
+                    .iter()
+                    .enumerate()
+                    .rev()
+                    .for_each(|(num, param_ty)| {
+                        // take a local of the function for the param
+                        let (arg_local_id, _) = self.local_pool.take(
+                            &mut self.app_wasm.locals,
+                            curr_loc.wasm_func_id,
+                            *param_ty,
+                            &format!("arg{}", num),
+                        );
+
+                        // emit a bytecode in the event to assign the ToS to this new local
+                        instr_builder.instr_at(
+                            tracker.curr_idx,
+                            walrus::ir::LocalSet {
+                                local: arg_local_id,
+                            },
+                        );
+
+                        // update index of tracker to point to what follows our insertions
+                        tracker.curr_idx += 1;
+
+                        // also update index to point to new location of instrumented instruction!
+                        // (saved params go before the original instruction)
+                        tracker.orig_instr_idx += 1;
+
+                        // place in symbol table with var addr for future reference
+                        let arg_name = format!("arg{}", num);
+                        let id = self.table.put(
+                            arg_name.clone(),
+                            Record::Var {
+                                ty: DataType::I32, // we only support integers right now.
+                                name: arg_name.clone(),
+                                value: None,
+                                is_comp_provided: false,
+                                addr: Some(VarAddr::Local { addr: arg_local_id }),
+                                loc: None,
+                            },
+                        );
+                        arg_recs.push((arg_name, id));
+                    });
+                arg_recs.reverse();
+                curr_loc.instr_created_args = arg_recs;
+                return true;
+            }
+        }
+        false
+    }
+
+    fn emit_params(&mut self) -> Result<bool, Box<WhammError>> {
+        if let Some(curr_loc) = self.instr_iter.curr_mut() {
+            if let Some(tracker) = &mut self.emitting_instr {
+                let func = self
+                    .app_wasm
+                    .funcs
+                    .get_mut(curr_loc.wasm_func_id)
+                    .kind
+                    .unwrap_local_mut();
+                let func_builder = func.builder_mut();
+                let mut instr_builder = func_builder.instr_seq(tracker.curr_seq_id);
+
+                for (_param_name, param_rec_id) in curr_loc.instr_created_args.iter() {
+                    let param_rec = self.table.get_record_mut(param_rec_id);
+                    if let Some(Record::Var {
+                        addr: Some(VarAddr::Local { addr }),
+                        ..
+                    }) = param_rec
+                    {
+                        // Inject at tracker.orig_instr_idx to make sure that this actually emits the params
+                        // for the instrumented instruction right before that instruction is called!
+                        instr_builder.instr_at(
+                            tracker.orig_instr_idx,
+                            walrus::ir::LocalGet { local: *addr },
+                        );
+
+                        // update index to point to new location of instrumented instruction!
+                        // (re-emitted params go before the original instruction)
+                        tracker.orig_instr_idx += 1;
+                    } else {
+                        return Err(Box::new(ErrorGen::get_unexpected_error(
+                            true,
+                            Some(format!(
+                                "{UNEXPECTED_ERR_MSG} \
+                        Could not emit parameters, something went wrong..."
+                            )),
+                            None,
+                        )));
+                    }
+                }
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn define_compiler_var(
+        &mut self,
+        context: &str,
+        var_name: &str,
+    ) -> Result<bool, Box<WhammError>> {
+        define_compiler_var(&mut self.table, self.instr_iter.curr(), context, var_name)
+    }
+
+    fn fold_expr(&mut self, expr: &mut Expr) -> bool {
+        *expr = ExprFolder::fold_expr(expr, &self.table);
+        true
+    }
+    fn emit_expr(&mut self, expr: &mut Expr) -> Result<bool, Box<WhammError>> {
+        if is_print_call(expr) {
+            // make sure there's something to print with
+            self.print_fns();
+        }
+        let from = self.injection_point();
+        let mut is_success = true;
+        match expr {
+            Expr::Ternary {
+                cond, conseq, alt, ..
+            } => {
+                is_success &= self.emit_if_else();
+                is_success &= self.emit_condition();
+                is_success &= self.emit_expr(cond)?;
+                is_success &= self.emit_consequent();
+                is_success &= self.emit_expr(conseq)?;
+                is_success &= self.emit_alternate();
+                is_success &= self.emit_expr(alt)?;
+                is_success &= self.finish_branch();
+            }
+            Expr::VarId { .. }
+            | Expr::UnOp { .. }
+            | Expr::BinOp { .. }
+            | Expr::Primitive { .. }
+            | Expr::Call { .. } => {
+                // Anything else can be emitted as normal
+                if let Some(curr_loc) = self.instr_iter.curr_mut() {
+                    if let Some(tracker) = &mut self.emitting_instr {
+                        let func = self
+                            .app_wasm
+                            .funcs
+                            .get_mut(self.outlining.unwrap_or(curr_loc.wasm_func_id))
+                            .kind
+                            .unwrap_local_mut();
+                        let func_builder = func.builder_mut();
+                        let mut instr_builder = func_builder.instr_seq(tracker.curr_seq_id);
+
+                        is_success &= emit_expr(
+                            &mut self.table,
+                            &mut self.app_wasm.data,
+                            expr,
+                            &mut instr_builder,
+                            &mut self.metadata,
+                            &mut tracker.curr_idx,
+                        )?;
+                    } else {
+                        // have an error at this place when for 3 calls
+                        return Err(Box::new(ErrorGen::get_unexpected_error(
+                            true,
+                            Some(format!(
+                                "{UNEXPECTED_ERR_MSG} \
+                            Something went wrong while emitting an instruction."
+                            )),
+                            None,
+                        )));
+                    }
+                } else {
+                    return Err(Box::new(ErrorGen::get_unexpected_error(
+                        true,
+                        Some(format!(
+                            "{UNEXPECTED_ERR_MSG} \
+                        Something went wrong while emitting an instruction."
+                        )),
+                        None,
+                    )));
+                }
+            }
+        }
+        self.map_injected(from, expr.loc());
+        Ok(is_success)
+    }
+    fn emit_fn(&mut self, context: &str, f: &Fn) -> Result<bool, Box<WhammError>> {
+        // figure out if this is a provided fn.
+        if f.is_comp_provided {
+            return if self.fn_providing_contexts.contains(&context.to_string()) {
+                self.emit_provided_fn(context, f)
+            } else {
+                Err(Box::new(ErrorGen::get_unexpected_error(
+                    true,
+                    Some(format!(
+                        "{UNEXPECTED_ERR_MSG} \
+                Provided fn, but could not find a context to provide the definition, context: {}",
+                        context
+                    )),
+                    None,
+                )))
+            };
+        }
+
+        // emit non-provided fn
+        // only when we're supporting user-defined fns in script...
+        unimplemented!();
+    }
+
+    fn emit_formal_param(&mut self, _param: &(Expr, DataType)) -> Result<bool, Box<WhammError>> {
+        // only when we're supporting user-defined fns in script...
+        unimplemented!();
+    }
+
+    fn emit_global(
+        &mut self,
+        name: String,
+        ty: DataType,
+        val: &Option<Value>,
+    ) -> Result<bool, Box<WhammError>> {
+        let rec_id = match self.table.lookup(&name) {
+            Some(rec_id) => *rec_id,
+            _ => {
+                return Err(Box::new(ErrorGen::get_unexpected_error(
+                    true,
+                    Some(format!(
+                        "{UNEXPECTED_ERR_MSG} \
+                Global variable symbol does not exist in this scope!"
+                    )),
+                    None,
+                )));
+            } // Ignore, continue to emit
+        };
+
+        // e.g. `whamm$script0$count`
+        let script = self
+            .table
+            .get_curr_scope_path()
+            .iter()
+            .find(|scope| matches!(scope.ty, ScopeType::Script))
+            .map(|scope| scope.name.clone())
+            .unwrap_or_default();
+        let global_name = injected_name(&format!("{script}${name}"));
+
+        let rec = self.table.get_record_mut(&rec_id);
+        match rec {
+            Some(Record::Var { ref mut addr, .. }) => {
+                // emit global variable and set addr in symbol table
+                // this is used for user-defined global vars in the script...
+                let (walrus_ty, init_expr) = match val {
+                    // the global always holds this value (see `propagate_consts`)
+                    Some(Value::Integer { val, .. }) => {
+                        (ValType::I32, InitExpr::Value(walrus::ir::Value::I32(*val)))
+                    }
+                    Some(Value::Boolean { val, .. }) => (
+                        ValType::I32,
+                        InitExpr::Value(walrus::ir::Value::I32(*val as i32)),
+                    ),
+                    _ => data_type_to_val_type(&ty),
+                };
+                let id = self.app_wasm.globals.add_local(walrus_ty, true, init_expr);
+                self.app_wasm.globals.get_mut(id).name = Some(global_name);
+                *addr = Some(VarAddr::Global { addr: id });
+
+                Ok(true)
+            }
+            Some(&mut ref ty) => Err(Box::new(ErrorGen::get_unexpected_error(
+                true,
+                Some(format!(
+                    "{UNEXPECTED_ERR_MSG} \
+                Incorrect global variable record, expected Record::Var, found: {:?}",
+                    ty
+                )),
+                None,
+            ))),
+            None => Err(Box::new(ErrorGen::get_unexpected_error(
+                true,
+                Some(format!(
+                    "{UNEXPECTED_ERR_MSG} \
+                Global variable symbol does not exist!"
+                )),
+                None,
+            ))),
+        }
+    }
+
+    fn remove_orig(&mut self) -> bool {
+        if let Some(curr_loc) = self.instr_iter.curr_mut() {
+            if let Some(tracker) = &self.emitting_instr {
+                let func = self
+                    .app_wasm
+                    .funcs
+                    .get_mut(curr_loc.wasm_func_id)
+                    .kind
+                    .unwrap_local_mut();
+                let func_builder = func.builder_mut();
+                let mut instr_builder = func_builder.instr_seq(tracker.curr_seq_id);
+
+                instr_builder.instrs_mut().remove(tracker.curr_idx);
+                return true;
+            }
+        }
+        false
+    }
+
+    fn emit_orig(&mut self) -> bool {
+        if let Some(curr_loc) = self.instr_iter.curr_mut() {
+            if let Some(tracker) = &mut self.emitting_instr {
+                let func = self
+                    .app_wasm
+                    .funcs
+                    .get_mut(curr_loc.wasm_func_id)
+                    .kind
+                    .unwrap_local_mut();
+                let func_builder = func.builder_mut();
+                let mut instr_builder = func_builder.instr_seq(tracker.curr_seq_id);
+
+                // reset where the "orig instruction" is located in the bytecode
+                tracker.orig_instr_idx = tracker.curr_idx;
+                // keep its original location, it isn't injected
+                instr_builder.instrs_mut().insert(
+                    tracker.curr_idx,
+                    (curr_loc.instr.clone(), curr_loc.instr_loc),
+                );
+                return true;
+            }
+        }
+        false
+    }
+
+    fn emit_if(&mut self) -> bool {
+        if let Some(curr_loc) = self.instr_iter.curr_mut() {
+            if let Some(tracker) = &mut self.emitting_instr {
+                // This MUST be `self.app_wasm` so we're mutating what will be the instrumented application.
+                let func = self
+                    .app_wasm
+                    .funcs
+                    .get_mut(self.outlining.unwrap_or(curr_loc.wasm_func_id))
+                    .kind
+                    .unwrap_local_mut();
+                let func_builder = func.builder_mut();
+                let mut instr_builder = func_builder.instr_seq(tracker.curr_seq_id);
+
+                let mut outer_seq_id = None;
+                let mut outer_idx = None;
+                let mut then_seq_id = None;
+                let mut then_idx = None;
+
+                instr_builder.block_at(tracker.curr_idx, None, |outer_block| {
+                    let outer_id = outer_block.id();
+                    outer_seq_id = Some(outer_id);
+                    outer_idx = Some(0usize);
+
+                    // CONDITION SHOULD BE EMITTED HERE
+
+                    // If the block evaluates to true (any nonzero value), execute the body.
+                    // If result of predicate equals 0, break out of the probe block
+                    // to continue with the application code.
+                    outer_block
+                        .i32_const(0)
+                        .binop(BinaryOp::I32Eq)
+                        .br_if(outer_id);
+
+                    outer_block.block(None, |then| {
+                        then_seq_id = Some(then.id());
+                        then_idx = Some(0usize);
+
+                        // CONSEQUENT SHOULD BE EMITTED HERE
+                    });
+                });
+
+                // Save the block information for future reference
+                // leave outer_block_idx as 0 to enable injection of condition!
+                tracker.outer_seq_id = outer_seq_id;
+                tracker.outer_idx = outer_idx;
+                tracker.then_seq_id = then_seq_id;
+                tracker.then_idx = then_idx;
+                tracker.curr_idx += 1;
+                return true;
+            }
+        }
+        false
+    }
+
+    fn emit_if_else(&mut self) -> bool {
+        if let Some(curr_loc) = self.instr_iter.curr_mut() {
+            if let Some(tracker) = &mut self.emitting_instr {
+                // This MUST be `self.app_wasm` so we're mutating what will be the instrumented application.
+                let func = self
+                    .app_wasm
+                    .funcs
+                    .get_mut(self.outlining.unwrap_or(curr_loc.wasm_func_id))
+                    .kind
+                    .unwrap_local_mut();
+                let func_builder = func.builder_mut();
+                let mut instr_builder = func_builder.instr_seq(tracker.curr_seq_id);
+
+                let mut outer_seq_id = None;
+                let mut outer_idx = None;
+                let mut then_seq_id = None;
+                let mut then_idx = None;
+                let mut else_seq_id = None;
+                let mut else_idx = None;
+
+                instr_builder.block_at(tracker.curr_idx, None, |outer_block| {
+                    outer_seq_id = Some(outer_block.id());
+                    outer_idx = Some(0usize);
+                    outer_block.if_else(
+                        None,
+                        |then| {
+                            then_seq_id = Some(then.id());
+                            then_idx = Some(0usize);
+                        },
+                        |else_| {
+                            else_seq_id = Some(else_.id());
+                            else_idx = Some(0usize);
+                        },
+                    );
+                });
+                // leave outer_block_idx as 0 to enable injection of condition!
+
+                // Save the block information for future reference
+                tracker.outer_seq_id = outer_seq_id;
+                tracker.outer_idx = outer_idx;
+                tracker.then_seq_id = then_seq_id;
+                tracker.then_idx = then_idx;
+                tracker.else_seq_id = else_seq_id;
+                tracker.else_idx = else_idx;
+                tracker.curr_idx += 1;
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Will configure the emitter to emit subsequent expression as the condition of an if or if/else stmt
+    /// Then emits the passed condition at that location.
+    fn emit_condition(&mut self) -> bool {
+        if let Some(tracker) = &mut self.emitting_instr {
+            if let Some(outer_seq_id) = &tracker.outer_seq_id {
+                if let Some(outer_idx) = &tracker.outer_idx {
+                    tracker.curr_seq_id = *outer_seq_id;
+                    tracker.curr_idx = *outer_idx;
+                }
+            }
+        }
+        false
+    }
+
+    /// Will configure the emitter to emit subsequent statements into the consequent body of an if or if/else stmt
+    fn emit_consequent(&mut self) -> bool {
+        if let Some(tracker) = &mut self.emitting_instr {
+            if let Some(then_seq_id) = &tracker.then_seq_id {
+                if let Some(then_idx) = &tracker.then_idx {
+                    tracker.curr_seq_id = *then_seq_id;
+                    tracker.curr_idx = *then_idx;
+                }
+            }
+            return true;
+        }
+        false
+    }
+
+    /// Will configure the emitter to emit subsequent statements into the alternate body of an if/else stmt
+    fn emit_alternate(&mut self) -> bool {
+        if let Some(tracker) = &mut self.emitting_instr {
+            if let Some(else_seq_id) = &tracker.else_seq_id {
+                if let Some(else_idx) = &tracker.else_idx {
+                    tracker.curr_seq_id = *else_seq_id;
+                    tracker.curr_idx = *else_idx;
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    /// Will configure the emitter to emit subsequent statements in the outer block of some branching logic
+    fn finish_branch(&mut self) -> bool {
+        if let Some(tracker) = &mut self.emitting_instr {
+            tracker.curr_seq_id = tracker.main_seq_id;
+            tracker.curr_idx = tracker.main_idx;
+
+            tracker.outer_seq_id = None;
+            tracker.outer_idx = None;
+            tracker.then_seq_id = None;
+            tracker.then_idx = None;
+            tracker.else_seq_id = None;
+            tracker.else_idx = None;
+            return true;
+        }
+        true
+    }
+    fn emit_global_stmts(&mut self, stmts: &mut Vec<Statement>) -> Result<bool, Box<WhammError>> {
+        // NOTE: This should be done in the Module entrypoint
+        //       https://docs.rs/walrus/latest/walrus/struct.Module.html
+
+        self.count_injected();
+        if let Some(start_fid) = self.app_wasm.start {
+            if let FunctionKind::Local(local_func) = &self.app_wasm.funcs.get(start_fid).kind {
+                // shifts the sites in the entry block of the start function
+                self.site_block = Some((
+                    start_fid,
+                    local_func.entry_block(),
+                    local_func.block(local_func.entry_block()).len(),
+                    0,
+                ));
+                self.emitting_instr = Some(EmittingInstrTracker {
+                    orig_instr_idx: 0usize,
+                    curr_seq_id: local_func.entry_block(),
+                    curr_idx: 0usize,
+                    main_seq_id: local_func.entry_block(),
+                    main_idx: 0usize,
+                    outer_seq_id: None,
+                    outer_idx: None,
+                    then_seq_id: None,
+                    then_idx: None,
+                    else_seq_id: None,
+                    else_idx: None,
+                })
+            }
+        } else {
+            for stmt in stmts.iter_mut() {
+                match stmt {
+                    Statement::Decl { .. } => {
+                        // This is fine
+                    }
+                    _ => {
+                        // This is NOT fine...error!
+                        // Cannot emit this at the moment since there's no entrypoint for our module to emit initialization instructions into
+                        return Err(Box::new(ErrorGen::get_unexpected_error(
+                            true,
+                            Some(
+                                "This module has no configured entrypoint, \
+                    unable to emit a `script` with global state"
+                                    .to_string(),
+                            ),
+                            None,
+                        )));
+                    }
+                }
+            }
+            return Ok(true);
+        }
+
+        for stmt in stmts.iter_mut() {
+            // iterate over statements and emit them
+            self.emit_stmt(stmt)?;
+        }
+        Ok(true)
+    }
+
+    fn emit_body(&mut self, body: &mut Vec<Statement>) -> Result<bool, Box<WhammError>> {
+        self.record_site();
+        if self
+            .inline_threshold
+            .is_some_and(|threshold| stmts_size(body) > threshold)
+        {
+            if let Some((params, key)) = self.outlined_params(body) {
+                return self.emit_outlined_body(body, params, key);
+            }
+        }
+        for stmt in body.iter_mut() {
+            self.emit_stmt(stmt)?;
+        }
+        Ok(true)
+    }
+
+    fn emit_counter_incrs(&mut self, incrs: &[CounterIncr]) -> Result<bool, Box<WhammError>> {
+        let Some(curr_loc) = self.instr_iter.curr() else {
+            return Ok(false);
+        };
+        let func_id = curr_loc.wasm_func_id;
+        let Some(cfg) = self.cfgs.get(&func_id) else {
+            return Ok(false);
+        };
+        let Some(block) = cfg.block_of(&curr_loc.instr_loc) else {
+            return Ok(false);
+        };
+        let scopes = self.table.get_curr_scope_path();
+        let mode = scopes
+            .iter()
+            .find(|scope| matches!(scope.ty, ScopeType::Probe))
+            .map(|scope| scope.name.as_str());
+        // what runs after an instruction ending its basic block is in another one
+        let is_batched = match mode {
+            Some("before") => true,
+            Some("after") => !cfg.ends_block(&curr_loc.instr_loc),
+            _ => false,
+        };
+        if !is_batched {
+            return Ok(false);
+        }
+        let mut globals = vec![];
+        for incr in incrs.iter() {
+            if !self.counters.contains(&incr.name) {
+                return Ok(false);
+            }
+            match self
+                .table
+                .lookup(&incr.name)
+                .and_then(|id| self.table.get_record(id))
+            {
+                Some(Record::Var {
+                    ty: DataType::I32 | DataType::U32,
+                    addr: Some(VarAddr::Global { addr }),
+                    ..
+                }) => globals.push((*addr, incr)),
+                _ => return Ok(false),
+            }
+        }
+
+        self.record_site();
+        for (global, incr) in globals {
+            match self.batched_idxs.get(&(func_id, block, global)) {
+                Some(idx) => {
+                    let batched = &mut self.batched_incrs[*idx];
+                    batched.total = batched.total.wrapping_add(incr.incr);
+                }
+                None => {
+                    self.batched_idxs
+                        .insert((func_id, block, global), self.batched_incrs.len());
+                    self.batched_incrs.push(BatchedIncr {
+                        func_id,
+                        block,
+                        global,
+                        total: incr.incr,
+                        loc: incr.loc.clone(),
+                    });
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    fn has_alt_call(&mut self) -> bool {
+        // check if we should inject an alternate call!
+        // At this point the body has been visited, so "new_target_fn_name" would be defined
+        let rec_id = self
+            .table
+            .lookup(&"new_target_fn_name".to_string())
+            .copied();
+
+        if rec_id.is_none() {
+            info!("`new_target_fn_name` not configured for this probe.");
+            return false;
+        } else {
+            let (name, func_call_id) = match rec_id {
+                Some(r_id) => {
+                    let rec = self.table.get_record_mut(&r_id);
+                    if let Some(Record::Var {
+                        value: Some(Value::Str { val, .. }),
+                        ..
+                    }) = rec
+                    {
+                        (val.clone(), self.app_wasm.funcs.by_name(val))
+                    } else {
+                        ("".to_string(), None)
+                    }
+                }
+                None => ("".to_string(), None),
+            };
+            if func_call_id.is_none() {
+                info!(
+                    "Could not find function in app Wasm specified by `new_target_fn_name`: {}",
+                    name
+                );
+                return false;
+            }
+            if let Some(curr_loc) = self.instr_iter.curr_mut() {
+                curr_loc.instr_alt_call = func_call_id;
+            } else {
+                info!("The instruction iterator has not been initialized, we've hit a bug!");
+                return false;
+            }
+        }
+        true
+    }
+
+    fn emit_alt_call(&mut self) -> Result<bool, Box<WhammError>> {
+        if let Some(curr_loc) = self.instr_iter.curr_mut() {
+            if let Some(tracker) = &mut self.emitting_instr {
+                if let Some(alt_fn_id) = curr_loc.instr_alt_call {
+                    // we need to inject an alternate call to the specified fn name!
+                    let func = self
+                        .app_wasm
+                        .funcs
+                        .get_mut(curr_loc.wasm_func_id)
+                        .kind
+                        .unwrap_local_mut();
+                    let func_builder = func.builder_mut();
+                    let mut instr_builder = func_builder.instr_seq(tracker.curr_seq_id);
+
+                    // Hack to have emit_params target this new call site!
+                    tracker.orig_instr_idx = tracker.curr_idx;
+
+                    // inject call
+                    instr_builder.instr_at(tracker.curr_idx, walrus::ir::Call { func: alt_fn_id });
+                    tracker.curr_idx += 1;
+                } else {
+                    return Err(Box::new(ErrorGen::get_unexpected_error(
+                        true,
+                        Some(format!(
+                            "{UNEXPECTED_ERR_MSG} \
+                    Could not inject alternate call to function, something went wrong..."
+                        )),
+                        None,
+                    )));
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    fn emit_stmt(&mut self, stmt: &mut Statement) -> Result<bool, Box<WhammError>> {
+        let from = self.injection_point();
+        let res = match stmt {
+            Statement::Decl { .. } => self.emit_decl_stmt(stmt),
+            Statement::Assign { .. } => self.emit_assign_stmt(stmt),
+            Statement::Expr { expr, .. } => self.emit_expr(expr),
+            Statement::Return { .. } => unimplemented!(),
+            Statement::If {
+                // cond, conseq, alt, .. -- for eventual implimentation
+                ..
+            } => {
+                unimplemented!()
+            }
+        };
+        self.map_injected(from, stmt.loc());
+        res
+    }
+
+    fn dump_to_file(&mut self, output_wasm_path: String) -> Result<bool, Box<WhammError>> {
+        self.count_injected();
+        self.emit_batched_incrs()?;
+        self.reserve_instr_mem()?;
+        let metadata = self.metadata();
+        // replaced by the ones of this instrumentation, when layering on a previous one
+        self.app_wasm.customs.remove_raw(WHAMM_SECTION);
+        self.app_wasm.customs.remove_raw(SOURCE_MAP_SECTION);
+        self.app_wasm.customs.add(RawCustomSection {
+            name: WHAMM_SECTION.to_string(),
+            data: metadata.into_bytes(),
+        });
+        self.app_wasm
+            .customs
+            .add(std::mem::take(&mut self.source_map));
+        let extra_names = ExtraNames::new(&self.app_wasm);
+        self.app_wasm.customs.add(extra_names);
+        let wasm = fix_code_addresses(merge_name_sections(self.app_wasm.emit_wasm()));
+        if let Err(invalid) = validate_with_jobs(&wasm, self.instr_iter.jobs) {
+            return Err(Box::new(invalid_module_error(&wasm, &invalid)));
+        }
+        match std::fs::write(&output_wasm_path, wasm) {
+            Ok(..) => Ok(true),
+            Err(err) => Err(Box::new(ErrorGen::get_unexpected_error(
+                true,
+                Some(format!(
+                    "{UNEXPECTED_ERR_MSG} \
+                Failed to dump instrumented wasm to {} from error: {}",
+                    &output_wasm_path, err
+                )),
+                None,
+            ))),
+        }
+    }
+}
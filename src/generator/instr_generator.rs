@@ -4,15 +4,49 @@ use crate::behavior::tree::{
 };
 use crate::behavior::tree::{BehaviorTree, Node};
 use crate::common::error::ErrorGen;
+use crate::generator::counters::counter_incrs;
 use crate::generator::emitters::Emitter;
 use crate::generator::types::ExprFolder;
+use crate::parser::rules::wasm::BytecodeEventKind;
 use crate::parser::types::{Expr, Statement};
-use convert_case::{Case, Casing};
 use log::warn;
+use std::collections::HashSet;
 
 const UNEXPECTED_ERR_MSG: &str =
     "InstrGenerator: Looks like you've found a bug...please report this behavior!";
 
+/// What a probe's predicate folds to at a site (see `ProbeMatch`)
+#[derive(Clone, Debug, PartialEq)]
+pub enum FoldedPred {
+    True,
+    False,
+    /// Only known at runtime
+    Dynamic,
+}
+impl FoldedPred {
+    pub fn as_str(&self) -> &str {
+        match self {
+            FoldedPred::True => "true",
+            FoldedPred::False => "false",
+            FoldedPred::Dynamic => "dynamic",
+        }
+    }
+}
+
+/// A site of the app that a probe matched, whether or not its predicate lets it fire
+#[derive(Clone, Debug)]
+pub struct ProbeMatch {
+    /// `provider:package:event`
+    pub probe: String,
+    pub mode: String,
+    /// The index of the function in the original app
+    pub func_idx: u32,
+    /// The byte offset of the instruction in the original app
+    pub offset: usize,
+    pub instr: &'static str,
+    pub predicate: FoldedPred,
+}
+
 /// The second phase of instrumenting a Wasm module by actually emitting the
 /// instrumentation code.
 ///
@@ -33,6 +67,10 @@ pub struct InstrGenerator<'a, 'b, 'c> {
     pub curr_probe_mode: String,
     /// The current probe's body and predicate
     pub curr_probe: Option<(Option<Vec<Statement>>, Option<Expr>)>,
+    /// Every site matched by a probe so far, only collected if `Some`
+    pub matches: Option<Vec<ProbeMatch>>,
+    /// Only walk the sites to find what the probes match (see `matches`), nothing is emitted
+    pub match_only: bool,
 }
 impl InstrGenerator<'_, '_, '_> {
     pub fn run(&mut self, behavior: &BehaviorTree) -> bool {
@@ -69,6 +107,37 @@ impl InstrGenerator<'_, '_, '_> {
         }
     }
 
+    /// Remember that the current probe matched the current instruction, with its folded predicate
+    fn record_match(&mut self, pred: &Option<Expr>) {
+        if self.matches.is_none() {
+            return;
+        }
+        let Some((func_idx, offset)) = self.emitter.curr_site() else {
+            return;
+        };
+        let predicate = match pred.as_ref().map(ExprFolder::get_single_bool) {
+            // no predicate is the same as `true`
+            None | Some(Some(true)) => FoldedPred::True,
+            Some(Some(false)) => FoldedPred::False,
+            Some(None) => FoldedPred::Dynamic,
+        };
+        let instr = self.emitter.curr_instr_type();
+        let Some(matches) = &mut self.matches else {
+            return;
+        };
+        matches.push(ProbeMatch {
+            probe: format!(
+                "{}:{}:{}",
+                self.curr_provider_name, self.curr_package_name, self.curr_event_name
+            ),
+            mode: self.curr_probe_mode.clone(),
+            func_idx,
+            offset,
+            instr,
+            predicate,
+        });
+    }
+
     fn emit_cond(&mut self, cond: &usize) -> bool {
         let mut is_success = true;
         if let Some(node) = self.tree.get_node(*cond) {
@@ -266,7 +335,9 @@ impl BehaviorVisitor<bool> for InstrGenerator<'_, '_, '_> {
                 Ok(res) => {
                     if res {
                         // The current instruction has parameters, save them
-                        is_success &= self.emitter.save_params();
+                        if !self.match_only {
+                            is_success &= self.emitter.save_params();
+                        }
                     } else {
                         // If no params, return whatever was configured to do
                         return *force_success;
@@ -321,7 +392,10 @@ impl BehaviorVisitor<bool> for InstrGenerator<'_, '_, '_> {
                     // Perform 'bytecode' package logic
 
                     // Initialize the instr visitor
-                    let instrs_of_interest: Vec<String> = events.keys().cloned().collect();
+                    let instrs_of_interest: HashSet<BytecodeEventKind> = events
+                        .keys()
+                        .filter_map(|name| BytecodeEventKind::from_name(name))
+                        .collect();
                     if let Err(e) = self.emitter.init_instr_iter(&instrs_of_interest) {
                         self.err.add_error(*e)
                     }
@@ -346,21 +420,15 @@ impl BehaviorVisitor<bool> for InstrGenerator<'_, '_, '_> {
                             self.emitter.next_instr();
                         }
 
-                        let instr_ty = match self.emitter.curr_instr_type().as_str() {
-                            // Handle some special-cases
-                            "V128Bitselect" => "v128_bitselect".to_string(),
-                            "I8x16Swizzle" => "i8x16_swizzle".to_string(),
-                            "I8x16Shuffle" => "i8x16_shuffle".to_string(),
-                            other => other.to_case(Case::Snake),
-                        };
+                        let instr_ty = self.emitter.curr_instr_type();
 
                         // is this an instruction of-interest?
-                        if let Some(globals) = events.get(&instr_ty) {
+                        if let Some(globals) = events.get(instr_ty) {
                             // enter this event's scope
-                            if !self.emitter.enter_named_scope(&instr_ty) {
+                            if !self.emitter.enter_named_scope(instr_ty) {
                                 self.err.unexpected_error(true, Some(format!("{UNEXPECTED_ERR_MSG} Could not find the specified scope by name: `{}`", instr_ty)), None);
                             }
-                            self.curr_event_name = instr_ty.clone();
+                            self.curr_event_name = instr_ty.to_string();
 
                             // define this instruction type's compiler variables
                             for global in globals {
@@ -454,16 +522,19 @@ impl BehaviorVisitor<bool> for InstrGenerator<'_, '_, '_> {
                         if let Some(pred) = &mut pred_cloned {
                             // Fold predicate
                             is_success &= self.emitter.fold_expr(pred);
-
+                        }
+                        self.record_match(&pred_cloned);
+                        if self.match_only {
+                            continue;
+                        }
+                        if let Some(pred) = &pred_cloned {
                             // If the predicate evaluates to false, short-circuit!
                             if let Some(pred_as_bool) = ExprFolder::get_single_bool(pred) {
                                 // predicate has been reduced to a boolean value
                                 if !pred_as_bool {
-                                    // predicate is reduced to `false` short-circuit!
-                                    if let Err(e) = self.emitter.exit_scope() {
-                                        self.err.add_error(*e)
-                                    }
-                                    return true;
+                                    // predicate is reduced to `false`, move on to the next
+                                    // probe of this mode at the site
+                                    continue;
                                 }
                             }
                         }
@@ -495,7 +566,15 @@ impl BehaviorVisitor<bool> for InstrGenerator<'_, '_, '_> {
                     if let Some(pred) = &mut pred_cloned {
                         // Fold predicate
                         is_success &= self.emitter.fold_expr(pred);
-
+                    }
+                    self.record_match(&pred_cloned);
+                    if self.match_only {
+                        if let Err(e) = self.emitter.exit_scope() {
+                            self.err.add_error(*e)
+                        }
+                        return is_success;
+                    }
+                    if let Some(pred) = &pred_cloned {
                         // If the predicate evaluates to false, short-circuit!
                         if let Some(pred_as_bool) = ExprFolder::get_single_bool(pred) {
                             // predicate has been reduced to a boolean value
@@ -509,11 +588,11 @@ impl BehaviorVisitor<bool> for InstrGenerator<'_, '_, '_> {
                         }
                     }
                     self.curr_probe = Some((body_cloned, pred_cloned));
-                }
 
-                // Process the instructions for this single probe!
-                if let Some(node) = self.tree.get_node(*child) {
-                    is_success &= self.visit_node(node);
+                    // Process the instructions for this single probe!
+                    if let Some(node) = self.tree.get_node(*child) {
+                        is_success &= self.visit_node(node);
+                    }
                 }
             } else {
                 unreachable!()
@@ -630,7 +709,7 @@ impl BehaviorVisitor<bool> for InstrGenerator<'_, '_, '_> {
         {
             // NOTE -- this WILL NOT WORK for dfinity or microservice applications...they are stateless
             //     will need to instrument ALL entrypoints for that to work :/
-            if !self.ast.global_stmts.is_empty() {
+            if !self.ast.global_stmts.is_empty() && !self.match_only {
                 match self.emitter.emit_global_stmts(&mut self.ast.global_stmts) {
                     Err(e) => self.err.add_error(*e),
                     Ok(res) => is_success &= res,
@@ -684,11 +763,26 @@ impl BehaviorVisitor<bool> for InstrGenerator<'_, '_, '_> {
             ..
         } = node
         {
-            if let Some((Some(ref mut body), ..)) = self.curr_probe {
+            if let Some((Some(ref mut body), ref pred)) = self.curr_probe {
                 if self.curr_probe_mode == "after" {
                     // tell the emitter to point to location after instruction-of-interest
                     self.emitter.incr_loc_pointer();
                 }
+                // the increments of counters run at every site if the predicate is always true,
+                // they can be batched per basic block
+                let is_pred_true = pred
+                    .as_ref()
+                    .map_or(true, |pred| ExprFolder::get_single_bool(pred) == Some(true));
+                if let (true, Some(incrs)) = (is_pred_true, counter_incrs(body)) {
+                    match self.emitter.emit_counter_incrs(&incrs) {
+                        Err(e) => {
+                            self.err.add_error(*e);
+                            return false;
+                        }
+                        Ok(true) => return is_success,
+                        Ok(false) => {}
+                    }
+                }
                 match self.emitter.emit_body(body) {
                     Err(e) => self.err.add_error(*e),
                     Ok(res) => is_success &= res,
@@ -0,0 +1,278 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use walrus::{CustomSection, DataId, GlobalId, IdsToIndices, MemoryId, TableId};
+use wasm_encoder::{NameMap, NameSection, RawSection};
+use wasmparser::{BinaryReader, Chunk, Name, NameSectionReader, NamingReader, Parser, Payload};
+
+/// Holds the names `walrus` doesn't emit until they are merged into the `name` section
+/// (see `merge_name_sections`)
+const EXTRA_NAMES_SECTION: &str = "whamm.names";
+const NAME_SECTION: &str = "name";
+
+/// The names of the tables, memories, globals and data segments of a module.
+/// `walrus` parses them, but it only emits the names of the module, its functions and locals.
+#[derive(Debug, Default)]
+pub struct ExtraNames {
+    tables: Vec<(TableId, String)>,
+    memories: Vec<(MemoryId, String)>,
+    globals: Vec<(GlobalId, String)>,
+    data: Vec<(DataId, String)>,
+}
+impl ExtraNames {
+    pub fn new(module: &walrus::Module) -> Self {
+        Self {
+            tables: module
+                .tables
+                .iter()
+                .filter_map(|table| Some((table.id(), table.name.clone()?)))
+                .collect(),
+            memories: module
+                .memories
+                .iter()
+                .filter_map(|memory| Some((memory.id(), memory.name.clone()?)))
+                .collect(),
+            globals: module
+                .globals
+                .iter()
+                .filter_map(|global| Some((global.id(), global.name.clone()?)))
+                .collect(),
+            data: module
+                .data
+                .iter()
+                .filter_map(|data| Some((data.id(), data.name.clone()?)))
+                .collect(),
+        }
+    }
+}
+
+fn name_map(mut names: Vec<(u32, &String)>) -> NameMap {
+    // the names must be sorted by index
+    names.sort_by_key(|(idx, _)| *idx);
+    let mut name_map = NameMap::new();
+    for (idx, name) in names {
+        name_map.append(idx, name);
+    }
+    name_map
+}
+
+impl CustomSection for ExtraNames {
+    fn name(&self) -> &str {
+        EXTRA_NAMES_SECTION
+    }
+
+    fn data(&self, ids_to_indices: &IdsToIndices) -> Cow<[u8]> {
+        let mut names = NameSection::new();
+        if !self.tables.is_empty() {
+            names.tables(&name_map(
+                self.tables
+                    .iter()
+                    .map(|(id, name)| (ids_to_indices.get_table_index(*id), name))
+                    .collect(),
+            ));
+        }
+        if !self.memories.is_empty() {
+            names.memories(&name_map(
+                self.memories
+                    .iter()
+                    .map(|(id, name)| (ids_to_indices.get_memory_index(*id), name))
+                    .collect(),
+            ));
+        }
+        if !self.globals.is_empty() {
+            names.globals(&name_map(
+                self.globals
+                    .iter()
+                    .map(|(id, name)| (ids_to_indices.get_global_index(*id), name))
+                    .collect(),
+            ));
+        }
+        if !self.data.is_empty() {
+            names.data(&name_map(
+                self.data
+                    .iter()
+                    .map(|(id, name)| (ids_to_indices.get_data_index(*id), name))
+                    .collect(),
+            ));
+        }
+        Cow::Owned(names.as_custom().data.into_owned())
+    }
+}
+
+/// A section of a module
+pub(crate) struct Section<'a> {
+    pub(crate) id: u8,
+    /// The name and data of a custom section
+    pub(crate) custom: Option<(&'a str, &'a [u8])>,
+    /// The contents of the section, to copy it as-is (see `wasm_encoder::RawSection`)
+    pub(crate) contents: &'a [u8],
+}
+
+/// The sections of a module, in order
+pub(crate) fn read_sections(wasm: &[u8]) -> Option<Vec<Section>> {
+    let mut parser = Parser::new(0);
+    let mut pos = 0;
+    let mut sections = vec![];
+    loop {
+        let Chunk::Parsed { consumed, payload } = parser.parse(&wasm[pos..], true).ok()? else {
+            return None;
+        };
+        let custom = match payload {
+            Payload::Version { .. } => {
+                pos += consumed;
+                continue;
+            }
+            Payload::End => return Some(sections),
+            Payload::CustomSection { name, data, .. } => Some((name, data)),
+            Payload::CodeSectionStart { .. } => {
+                // the section is copied as a whole, not function by function
+                parser.skip_section();
+                None
+            }
+            _ => None,
+        };
+        let mut header = BinaryReader::new(&wasm[pos..]);
+        let id = header.read_u8().ok()? as u8;
+        let size = header.read_var_u32().ok()? as usize;
+        let start = pos + header.current_position();
+        sections.push(Section {
+            id,
+            custom,
+            contents: wasm.get(start..start + size)?,
+        });
+        pos = start + size;
+    }
+}
+
+/// The data of the custom sections of a module: (name, data)
+pub(crate) fn custom_sections(wasm: &[u8]) -> impl Iterator<Item = (&str, &[u8])> {
+    Parser::new(0)
+        .parse_all(wasm)
+        .map_while(Result::ok)
+        .filter_map(|payload| match payload {
+            Payload::CustomSection { name, data, .. } => Some((name, data)),
+            _ => None,
+        })
+}
+
+/// The data of the custom section of a module named `name`, if any
+pub(crate) fn custom_section<'a>(wasm: &'a [u8], name: &str) -> Option<&'a [u8]> {
+    custom_sections(wasm).find_map(|(section_name, data)| (section_name == name).then_some(data))
+}
+
+/// The subsections of a `name` section, each with its ID and size, in order
+fn read_subsections(names: &[u8]) -> Option<Vec<&[u8]>> {
+    let mut reader = NameSectionReader::new(names, 0).ok()?;
+    let mut subsections = vec![];
+    while !reader.eof() {
+        let start = reader.original_position();
+        reader.read().ok()?;
+        subsections.push(&names[start..reader.original_position()]);
+    }
+    Some(subsections)
+}
+
+/// Reads a name map: index -> name
+fn read_name_map(map: wasmparser::Result<NamingReader>) -> HashMap<u32, String> {
+    let Ok(mut map) = map else {
+        return HashMap::new();
+    };
+    (0..map.get_count())
+        .map_while(|_| map.read().ok())
+        .map(|naming| (naming.index, naming.name.to_string()))
+        .collect()
+}
+
+/// The names of the functions, locals and globals of a module
+#[derive(Debug, Default)]
+pub struct Names {
+    pub funcs: HashMap<u32, String>,
+    /// The names of the locals of each function, by function index
+    pub locals: HashMap<u32, HashMap<u32, String>>,
+    pub globals: HashMap<u32, String>,
+}
+impl Names {
+    /// Read the names from the `name` section of a module, if any
+    pub fn read(wasm: &[u8]) -> Self {
+        let mut names = Self::default();
+        let Some(reader) = custom_section(wasm, NAME_SECTION)
+            .and_then(|name_section| NameSectionReader::new(name_section, 0).ok())
+        else {
+            return names;
+        };
+        for name in reader.into_iter().map_while(Result::ok) {
+            match name {
+                Name::Function(map) => names.funcs = read_name_map(map.get_map()),
+                Name::Global(map) => names.globals = read_name_map(map.get_map()),
+                Name::Local(map) => {
+                    let Ok(mut funcs) = map.get_indirect_map() else {
+                        continue;
+                    };
+                    for _ in 0..funcs.get_indirect_count() {
+                        let Ok(func) = funcs.read() else {
+                            break;
+                        };
+                        names
+                            .locals
+                            .insert(func.indirect_index, read_name_map(func.get_map()));
+                    }
+                }
+                _ => {}
+            }
+        }
+        names
+    }
+}
+
+/// Merge the names `walrus` doesn't emit (see `ExtraNames`) into the `name` section of a module
+/// emitted by `walrus`. The module is returned as-is if it can't be read.
+pub fn merge_name_sections(wasm: Vec<u8>) -> Vec<u8> {
+    let Some(sections) = read_sections(&wasm) else {
+        return wasm;
+    };
+    let names_of = |name: &str| {
+        sections
+            .iter()
+            .filter_map(|section| section.custom)
+            .find(|(section_name, _)| *section_name == name)
+            .map(|(_, data)| data)
+    };
+    let Some(extra_names) = names_of(EXTRA_NAMES_SECTION) else {
+        return wasm;
+    };
+    let (Some(mut subsections), Some(extra_subsections)) = (
+        read_subsections(names_of(NAME_SECTION).unwrap_or_default()),
+        read_subsections(extra_names),
+    ) else {
+        return wasm;
+    };
+    // the subsections must be in the order of their IDs, their first byte
+    subsections.extend(extra_subsections);
+    subsections.sort_by_key(|subsection| subsection[0]);
+    let merged = wasm_encoder::CustomSection {
+        name: Cow::Borrowed(NAME_SECTION),
+        data: Cow::Owned(subsections.concat()),
+    };
+
+    // the merged section goes where `walrus` put the `name` section, or else the extra names
+    let has_names = names_of(NAME_SECTION).is_some();
+    let mut module = wasm_encoder::Module::new();
+    for section in sections.iter() {
+        match section.custom.map(|(name, _)| name) {
+            Some(NAME_SECTION) => {
+                module.section(&merged);
+            }
+            Some(EXTRA_NAMES_SECTION) => {
+                if !has_names && !merged.data.is_empty() {
+                    module.section(&merged);
+                }
+            }
+            _ => {
+                module.section(&RawSection {
+                    id: section.id,
+                    data: section.contents,
+                });
+            }
+        }
+    }
+    module.finish()
+}
@@ -0,0 +1,355 @@
+use crate::generator::name_section::custom_section;
+use crate::parser::types::Location;
+use pest::error::LineColLocation;
+use serde_json::{json, Value};
+use std::borrow::Cow;
+use std::ops::Range;
+use walrus::ir::{Instr, InstrLocId, InstrSeqId};
+use walrus::{
+    CodeTransform, CustomSection, FunctionId, IdsToIndices, LocalFunction, RawCustomSection,
+};
+
+/// The custom section mapping the injected instructions back to the script (see `SourceMap`)
+pub const SOURCE_MAP_SECTION: &str = "whamm.sourcemap";
+
+/// The locations of the instructions parsed from the app are their offsets in the app binary,
+/// the injected instructions get locations from here on to tell them apart.
+const INJECTED_LOC_BASE: u32 = 0x8000_0000;
+
+/// The scripts of a source map section, and its ranges with the index of their script
+type SectionContents = (Vec<Option<String>>, Vec<(Option<usize>, SourceRange)>);
+
+/// A span of the script: (line, column) to (line, column)
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScriptSpan {
+    pub start: (usize, usize),
+    pub end: (usize, usize),
+}
+impl ScriptSpan {
+    fn from(loc: &Location) -> Self {
+        match loc.line_col {
+            LineColLocation::Pos(pos) => Self {
+                start: pos,
+                end: pos,
+            },
+            LineColLocation::Span(start, end) => Self { start, end },
+        }
+    }
+}
+
+/// A range of injected instructions in the instrumented binary
+#[derive(Debug, PartialEq)]
+pub struct SourceRange {
+    /// The index of the function in the instrumented binary
+    pub func_idx: u32,
+    /// The offset of the first instruction in the instrumented binary
+    pub start: usize,
+    /// The offset right after the last instruction
+    pub end: usize,
+    /// The path of the script the instructions were injected for
+    pub script: Option<String>,
+    pub span: ScriptSpan,
+}
+
+/// Maps the instructions injected into the app to the statement or expression of the script they
+/// were emitted for.
+///
+/// walrus only knows where an instruction ends up in the instrumented binary while emitting it.
+/// So, the injected instructions are given locations (see `map_injected`) that are resolved
+/// to their offsets in `apply_code_transform`. This requires the app to be parsed with
+/// `ModuleConfig::preserve_code_transform`, otherwise the source map is empty.
+///
+/// The code injected by a previous instrumentation (see `--layer`) keeps the locations it was
+/// parsed with, its offsets in the app. These are looked up in the ranges of the previous source
+/// map, so the new one covers the code of every layer.
+#[derive(Debug, Default)]
+pub struct SourceMap {
+    pub script_path: Option<String>,
+    /// The scripts of the previous instrumentations
+    prev_scripts: Vec<Option<String>>,
+    /// The spans of the scripts, with the index of the script in `prev_scripts` for the spans of
+    /// a previous instrumentation (none for the spans of `script_path`)
+    spans: Vec<(Option<usize>, ScriptSpan)>,
+    /// The index of the span of each injected instruction, by location
+    instr_spans: Vec<usize>,
+    /// The ranges of the code a previous instrumentation injected into the app, by offset in the
+    /// app: (start, end, index of the span), sorted
+    prev_ranges: Vec<(usize, usize, usize)>,
+    /// The ranges of injected instructions, once the app is emitted
+    ranges: Vec<(FunctionId, usize, usize, usize)>,
+}
+impl SourceMap {
+    /// The source map of the app to instrument, carrying over the ranges of its `whamm.sourcemap`
+    /// section if it was already instrumented (see `--layer`)
+    pub fn new(app_wasm: &walrus::Module) -> Self {
+        let mut source_map = Self::default();
+        let Some(section) = Self::section(app_wasm) else {
+            return source_map;
+        };
+        let (scripts, ranges) = Self::read(section).unwrap_or_default();
+        source_map.prev_scripts = scripts;
+        for (script_idx, range) in ranges {
+            let span_idx = source_map.span_idx(script_idx, range.span);
+            source_map
+                .prev_ranges
+                .push((range.start, range.end, span_idx));
+        }
+        source_map.prev_ranges.sort();
+        source_map
+    }
+
+    fn span_idx(&mut self, script: Option<usize>, span: ScriptSpan) -> usize {
+        let span = (script, span);
+        match self.spans.iter().position(|other| *other == span) {
+            Some(span_idx) => span_idx,
+            None => {
+                self.spans.push(span);
+                self.spans.len() - 1
+            }
+        }
+    }
+
+    /// Map the instructions injected at `range` of the sequence `seq` of the function, and the
+    /// instructions nested in them (e.g. the body of an injected `if`), to the script location
+    /// they were emitted for. Only the injected code is visited, not the rest of the function.
+    pub fn map_injected(
+        &mut self,
+        func: &mut LocalFunction,
+        seq: InstrSeqId,
+        range: Range<usize>,
+        loc: &Location,
+    ) {
+        let span_idx = self.span_idx(None, ScriptSpan::from(loc));
+        let mut seqs = vec![(seq, range)];
+        while let Some((seq, range)) = seqs.pop() {
+            let instrs = &mut func.block_mut(seq).instrs;
+            let range = range.start.min(instrs.len())..range.end.min(instrs.len());
+            for (instr, instr_loc) in instrs[range].iter_mut() {
+                // the instructions parsed from the app keep their location
+                if instr_loc.is_default() {
+                    let loc = INJECTED_LOC_BASE + self.instr_spans.len() as u32;
+                    *instr_loc = InstrLocId::new(loc);
+                    self.instr_spans.push(span_idx);
+                }
+                match instr {
+                    Instr::Block(block) => seqs.push((block.seq, 0..usize::MAX)),
+                    Instr::Loop(block) => seqs.push((block.seq, 0..usize::MAX)),
+                    Instr::IfElse(if_else) => {
+                        seqs.push((if_else.consequent, 0..usize::MAX));
+                        seqs.push((if_else.alternative, 0..usize::MAX));
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    fn span_of(&self, loc: &InstrLocId) -> Option<usize> {
+        if loc.is_default() {
+            return None;
+        }
+        if loc.data() < INJECTED_LOC_BASE {
+            // an instruction parsed from the app, it may have been injected by a previous
+            // instrumentation
+            let offset = loc.data() as usize;
+            let pos = self
+                .prev_ranges
+                .partition_point(|(start, ..)| *start <= offset);
+            let (_, end, span_idx) = self.prev_ranges[..pos].last()?;
+            return (offset < *end).then_some(*span_idx);
+        }
+        self.instr_spans
+            .get((loc.data() - INJECTED_LOC_BASE) as usize)
+            .copied()
+    }
+
+    /// The range of injected instructions at an offset of a function of the instrumented app,
+    /// read from its source map section
+    pub fn lookup(
+        app_wasm: &walrus::Module,
+        func_idx: u32,
+        offset: usize,
+    ) -> Result<Option<SourceRange>, String> {
+        Self::lookup_in(Self::section(app_wasm), func_idx, offset)
+    }
+
+    /// All the ranges of injected instructions of an instrumented app, read from its source map
+    /// section
+    pub fn ranges(app_wasm: &walrus::Module) -> Result<Vec<SourceRange>, String> {
+        Self::ranges_in(Self::section(app_wasm))
+    }
+
+    /// Same as `lookup`, for an instrumented app that is still in binary form
+    pub fn lookup_binary(
+        wasm: &[u8],
+        func_idx: u32,
+        offset: usize,
+    ) -> Result<Option<SourceRange>, String> {
+        Self::lookup_in(Self::section_of(wasm), func_idx, offset)
+    }
+
+    /// All the ranges of injected instructions of an instrumented app in binary form, read from
+    /// its source map section
+    pub fn ranges_binary(wasm: &[u8]) -> Result<Vec<SourceRange>, String> {
+        Self::ranges_in(Self::section_of(wasm))
+    }
+
+    fn section(app_wasm: &walrus::Module) -> Option<&[u8]> {
+        app_wasm.customs.iter().find_map(|(_, section)| {
+            section
+                .as_any()
+                .downcast_ref::<RawCustomSection>()
+                .filter(|section| section.name == SOURCE_MAP_SECTION)
+                .map(|section| &section.data[..])
+        })
+    }
+
+    fn section_of(wasm: &[u8]) -> Option<&[u8]> {
+        custom_section(wasm, SOURCE_MAP_SECTION)
+    }
+
+    fn lookup_in(
+        section: Option<&[u8]>,
+        func_idx: u32,
+        offset: usize,
+    ) -> Result<Option<SourceRange>, String> {
+        let ranges = Self::ranges_in(section)?;
+        Ok(ranges.into_iter().find(|range| {
+            range.func_idx == func_idx && range.start <= offset && offset < range.end
+        }))
+    }
+
+    fn ranges_in(section: Option<&[u8]>) -> Result<Vec<SourceRange>, String> {
+        let Some(section) = section else {
+            return Err(format!(
+                "The module has no `{SOURCE_MAP_SECTION}` custom section, was it instrumented by whamm!?"
+            ));
+        };
+        let (scripts, ranges) = Self::read(section)?;
+        Ok(ranges
+            .into_iter()
+            .map(|(script_idx, mut range)| {
+                range.script = script_idx.and_then(|idx| scripts.get(idx)?.clone());
+                range
+            })
+            .collect())
+    }
+
+    /// The scripts and the ranges of a source map section, with the index of the script of each
+    /// range (the `script` of the ranges isn't set)
+    fn read(section: &[u8]) -> Result<SectionContents, String> {
+        let source_map: Value = serde_json::from_slice(section).map_err(|e| {
+            format!("Could not read the `{SOURCE_MAP_SECTION}` custom section: {e}")
+        })?;
+
+        let scripts = source_map["scripts"]
+            .as_array()
+            .map(|scripts| {
+                scripts
+                    .iter()
+                    .map(|path| path.as_str().map(|path| path.to_string()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let ranges = source_map["ranges"].as_array().cloned().unwrap_or_default();
+        let ranges = ranges
+            .iter()
+            .filter_map(|range| {
+                let field = |name: &str| range[name].as_u64().map(|val| val as usize);
+                let (Some(func_idx), Some(start), Some(end)) =
+                    (field("func_index"), field("start"), field("end"))
+                else {
+                    return None;
+                };
+                let span: Vec<usize> = range["span"]
+                    .as_array()?
+                    .iter()
+                    .filter_map(|val| val.as_u64().map(|val| val as usize))
+                    .collect();
+                let [start_line, start_col, end_line, end_col] = span[..] else {
+                    return None;
+                };
+                let range = SourceRange {
+                    func_idx: func_idx as u32,
+                    start,
+                    end,
+                    script: None,
+                    span: ScriptSpan {
+                        start: (start_line, start_col),
+                        end: (end_line, end_col),
+                    },
+                };
+                Some((field("script"), range))
+            })
+            .collect();
+        Ok((scripts, ranges))
+    }
+}
+
+impl CustomSection for SourceMap {
+    fn name(&self) -> &str {
+        SOURCE_MAP_SECTION
+    }
+
+    fn data(&self, ids_to_indices: &IdsToIndices) -> Cow<[u8]> {
+        // the scripts of the previous instrumentations, then this one
+        let mut scripts = self.prev_scripts.clone();
+        scripts.push(self.script_path.clone());
+        let ranges: Vec<Value> = self
+            .ranges
+            .iter()
+            .map(|(func, start, end, span_idx)| {
+                let (script_idx, span) = &self.spans[*span_idx];
+                let script_idx = script_idx.unwrap_or(self.prev_scripts.len());
+                json!({
+                    "func_index": ids_to_indices.get_func_index(*func),
+                    "start": start,
+                    "end": end,
+                    "script": script_idx,
+                    "span": [span.start.0, span.start.1, span.end.0, span.end.1],
+                })
+            })
+            .collect();
+        let source_map = json!({
+            "scripts": scripts,
+            "ranges": ranges,
+        });
+        Cow::Owned(source_map.to_string().into_bytes())
+    }
+
+    fn apply_code_transform(&mut self, transform: &CodeTransform) {
+        let mut funcs = transform.function_ranges.clone();
+        funcs.sort_by_key(|(_, range)| range.start);
+        // every instruction with a location, in the order they are in the binary
+        let mut instrs: Vec<(usize, Option<usize>)> = transform
+            .instruction_map
+            .iter()
+            .map(|(loc, offset)| (*offset, self.span_of(loc)))
+            .collect();
+        instrs.sort();
+
+        self.ranges.clear();
+        for (i, (offset, span_idx)) in instrs.iter().enumerate() {
+            let Some(span_idx) = span_idx else {
+                continue;
+            };
+            let func_pos = funcs.partition_point(|(_, range)| range.start <= *offset);
+            let Some((func, func_range)) = func_pos.checked_sub(1).map(|pos| &funcs[pos]) else {
+                continue;
+            };
+            // the range ends where the next instruction with a location starts
+            let end = match instrs.get(i + 1) {
+                Some((next, _)) if *next < func_range.end => *next,
+                _ => func_range.end,
+            };
+            match self.ranges.last_mut() {
+                Some((last_func, _, last_end, last_span))
+                    if last_func == func && *last_end == *offset && last_span == span_idx =>
+                {
+                    *last_end = end;
+                }
+                _ => self.ranges.push((*func, *offset, end, *span_idx)),
+            }
+        }
+    }
+}
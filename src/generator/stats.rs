@@ -0,0 +1,174 @@
+use crate::generator::diff::{diff, read_funcs, Change};
+use crate::generator::instr_generator::{FoldedPred, ProbeMatch};
+use serde_json::{json, Value};
+use wasmparser::FunctionBody;
+
+/// The sites a probe matched, by what its predicate folded to
+#[derive(Debug, Default, PartialEq)]
+pub struct ProbeStats {
+    /// `provider:package:event`
+    pub probe: String,
+    pub mode: String,
+    pub sites: usize,
+    /// The sites where the predicate folded to `false`, nothing was injected there
+    pub eliminated: usize,
+    /// The sites where the predicate is only known at runtime
+    pub dynamic: usize,
+}
+
+/// How a function of the app grew with the instrumentation
+#[derive(Debug)]
+pub struct FuncStats {
+    /// The index of the function in the instrumented app
+    pub func_idx: u32,
+    /// The index of the function in the app
+    pub app_func_idx: u32,
+    pub name: Option<String>,
+    /// The size of the function's body in the app, in bytes
+    pub app_size: usize,
+    /// The size of the function's body in the instrumented app, in bytes
+    pub size: usize,
+    pub injected_instrs: usize,
+    pub injected_locals: u32,
+}
+
+/// A summary of what instrumenting an app did
+#[derive(Debug)]
+pub struct Stats {
+    /// In the order the probes first matched a site
+    pub probes: Vec<ProbeStats>,
+    /// The functions of the app that were changed
+    pub funcs: Vec<FuncStats>,
+    pub injected_funcs: usize,
+    pub injected_globals: usize,
+    /// The size of the bodies of all the functions of the app, in bytes
+    pub app_code_size: usize,
+    /// The size of the bodies of all the functions of the instrumented app, in bytes
+    pub code_size: usize,
+}
+
+/// The number of locals of a function, parameters excluded
+fn num_locals(body: &FunctionBody) -> Result<u32, String> {
+    let mut num = 0;
+    let mut reader = body.get_locals_reader().map_err(|e| e.to_string())?;
+    for _ in 0..reader.get_count() {
+        let (count, _) = reader.read().map_err(|e| e.to_string())?;
+        num += count;
+    }
+    Ok(num)
+}
+
+/// The size of a function's body, in bytes
+fn body_size(body: &FunctionBody) -> usize {
+    body.range().end - body.range().start
+}
+
+/// Summarizes the sites the probes matched (see `InstrGenerator::matches`) and what was
+/// injected into the app to instrument them.
+pub fn stats(matches: &[ProbeMatch], app: &[u8], instrumented: &[u8]) -> Result<Stats, String> {
+    let mut probes: Vec<ProbeStats> = vec![];
+    for site in matches.iter() {
+        let idx = match probes
+            .iter()
+            .position(|probe| probe.probe == site.probe && probe.mode == site.mode)
+        {
+            Some(idx) => idx,
+            None => {
+                probes.push(ProbeStats {
+                    probe: site.probe.clone(),
+                    mode: site.mode.clone(),
+                    ..Default::default()
+                });
+                probes.len() - 1
+            }
+        };
+        let probe = &mut probes[idx];
+        probe.sites += 1;
+        match site.predicate {
+            FoldedPred::True => {}
+            FoldedPred::False => probe.eliminated += 1,
+            FoldedPred::Dynamic => probe.dynamic += 1,
+        }
+    }
+
+    let module_diff = diff(app, instrumented)?;
+    let (app_imported, app_bodies) = read_funcs(app)?;
+    let (imported, bodies) = read_funcs(instrumented)?;
+    let mut funcs = vec![];
+    for func in module_diff.changed_funcs {
+        let Some(app_func_idx) = func.app_func_idx else {
+            continue;
+        };
+        let (Some(app_body), Some(body)) = (
+            app_func_idx
+                .checked_sub(app_imported)
+                .and_then(|idx| app_bodies.get(idx as usize)),
+            func.func_idx
+                .checked_sub(imported)
+                .and_then(|idx| bodies.get(idx as usize)),
+        ) else {
+            continue;
+        };
+        funcs.push(FuncStats {
+            func_idx: func.func_idx,
+            app_func_idx,
+            name: func.name,
+            app_size: body_size(app_body),
+            size: body_size(body),
+            injected_instrs: func
+                .instrs
+                .iter()
+                .filter(|instr| instr.change == Change::Injected)
+                .count(),
+            injected_locals: num_locals(body)?.saturating_sub(num_locals(app_body)?),
+        });
+    }
+
+    Ok(Stats {
+        probes,
+        funcs,
+        injected_funcs: module_diff.injected_funcs.len(),
+        injected_globals: module_diff.injected_globals.len(),
+        app_code_size: app_bodies.iter().map(body_size).sum(),
+        code_size: bodies.iter().map(body_size).sum(),
+    })
+}
+
+impl Stats {
+    pub fn injected_instrs(&self) -> usize {
+        self.funcs.iter().map(|func| func.injected_instrs).sum()
+    }
+
+    pub fn injected_locals(&self) -> u32 {
+        self.funcs.iter().map(|func| func.injected_locals).sum()
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "probes": self.probes.iter().map(|probe| json!({
+                "probe": probe.probe,
+                "mode": probe.mode,
+                "sites": probe.sites,
+                "eliminated": probe.eliminated,
+                "dynamic": probe.dynamic,
+            })).collect::<Vec<Value>>(),
+            "funcs": self.funcs.iter().map(|func| json!({
+                "func_index": func.func_idx,
+                "app_func_index": func.app_func_idx,
+                "name": func.name,
+                "app_size": func.app_size,
+                "size": func.size,
+                "injected_instrs": func.injected_instrs,
+                "injected_locals": func.injected_locals,
+            })).collect::<Vec<Value>>(),
+            "injected": {
+                "funcs": self.injected_funcs,
+                "globals": self.injected_globals,
+                "locals": self.injected_locals(),
+                "instrs": self.injected_instrs(),
+            },
+            "app_code_size": self.app_code_size,
+            "code_size": self.code_size,
+        })
+    }
+}
@@ -2,11 +2,18 @@
 // = Setup Logging =
 // =================
 
+use crate::behavior::builder_visitor::{build_behavior_tree, SimpleAST};
 use crate::common::error::ErrorGen;
+use crate::generator::cfg::Cfg;
+use crate::generator::const_prop::propagate_consts;
+use crate::generator::counters::counters;
+use crate::generator::emitters::WasmRewritingEmitter;
+use crate::generator::init_generator::InitGenerator;
+use crate::generator::instr_generator::{FoldedPred, InstrGenerator, ProbeMatch};
 use crate::generator::types::ExprFolder;
 use crate::parser::tests;
 use crate::parser::types::Expr::{BinOp as ExprBinOp, VarId};
-use crate::parser::types::{BinOp, DataType, Expr, Value, Whamm};
+use crate::parser::types::{BinOp, DataType, Expr, Statement, Value, Whamm};
 use crate::verifier::types::{Record, ScopeType, SymbolTable};
 use crate::verifier::verifier;
 use log::error;
@@ -295,3 +302,222 @@ wasm::call:alt /
         }
     };
 }
+
+#[test]
+pub fn cfg_of_branches() {
+    setup_logger();
+    let wasm = wabt::wat2wasm(
+        r#"
+(module
+  (func (param i32) (result i32)
+    block
+      local.get 0
+      br_if 0
+      loop
+        local.get 0
+        i32.const 1
+        i32.sub
+        local.tee 0
+        br_if 0
+      end
+    end
+    local.get 0))
+    "#,
+    )
+    .unwrap();
+    let module = walrus::Module::from_buffer(&wasm).unwrap();
+    let (_, func) = module.funcs.iter_local().next().unwrap();
+    let cfg = Cfg::new(func);
+
+    let instrs: Vec<usize> = cfg.blocks.iter().map(|block| block.instrs.len()).collect();
+    assert_eq!(vec![1, 2, 1, 5, 0, 0, 1], instrs);
+    let succs: Vec<&[usize]> = cfg
+        .blocks
+        .iter()
+        .map(|block| block.succs.as_slice())
+        .collect();
+    // the `br_if` out of the block skips the loop, the one in the loop goes back to its start
+    let expected: Vec<&[usize]> = vec![&[1], &[2, 6], &[3], &[3, 4], &[5], &[6], &[]];
+    assert_eq!(expected, succs);
+
+    let br_if = cfg.blocks[1].instrs[1];
+    assert_eq!(Some(1), cfg.block_of(&br_if));
+    assert!(cfg.ends_block(&br_if));
+    assert!(!cfg.ends_block(&cfg.blocks[1].instrs[0]));
+    // falls through to the end of the function
+    assert!(!cfg.ends_block(&cfg.blocks[6].instrs[0]));
+}
+
+#[test]
+pub fn propagate_constant_globals() {
+    setup_logger();
+    let script = r#"
+i32 DEBUG = 0;
+i32 LEVEL = DEBUG + 2;
+i32 count;
+bool on;
+wasm::call:before / LEVEL == 2 && !on / {
+    i32 DEBUG;
+    DEBUG = count;
+    count = count + 1;
+}
+    "#;
+    let mut err = ErrorGen::new("".to_string(), "".to_string(), 0);
+    let mut whamm = tests::get_ast(script, &mut err).unwrap();
+    let mut table = verifier::build_symbol_table(&mut whamm, &mut err);
+
+    // the body assigns to its own `DEBUG`
+    let consts = propagate_consts(&mut whamm, &mut table);
+    assert_eq!(vec!["DEBUG", "LEVEL", "on"], consts);
+
+    let script = whamm.scripts.first().unwrap();
+    assert_eq!(
+        Some(Value::Integer {
+            ty: DataType::I32,
+            val: 2
+        }),
+        script.globals["LEVEL"].value
+    );
+    assert_eq!(None, script.globals["count"].value);
+    // the globals are initialized with their value instead
+    assert!(script
+        .global_stmts
+        .iter()
+        .all(|stmt| matches!(stmt, Statement::Decl { .. })));
+}
+
+#[test]
+pub fn counters_read_elsewhere() {
+    setup_logger();
+    let script = r#"
+i32 count;
+i32 calls;
+i32 hits;
+i32 start = calls;
+get_count() -> i32 {
+    return count;
+}
+wasm:bytecode:call:before {
+    count = count + 1;
+    calls = calls + 1;
+    hits = hits + 1;
+}
+wasm:bytecode:call:after {
+    i32 seen;
+    seen = get_count();
+}
+    "#;
+    let mut err = ErrorGen::new("".to_string(), "".to_string(), 0);
+    let whamm = tests::get_ast(script, &mut err).unwrap();
+    let mut ast = SimpleAST::new();
+    build_behavior_tree(&whamm, &mut ast, &mut err);
+
+    // `count` is read by a function the probes call, `calls` by a global statement
+    let counters: Vec<String> = counters(&ast, &whamm).into_iter().collect();
+    assert_eq!(vec!["hits"], counters);
+}
+
+/// Instrument an app making three calls with the probes of `script` followed by the ones of
+/// `extra`, returns the sites they matched and the metadata of the instrumented app (nothing is
+/// emitted if `match_only`)
+///
+/// A script cannot hold two probes of the same mode on an event, the probes of `extra` are added
+/// to the ones of `script` and run in its scope.
+fn match_sites(
+    script: &str,
+    extra: &str,
+    match_only: bool,
+) -> (Vec<ProbeMatch>, serde_json::Value) {
+    let mut err = ErrorGen::new("".to_string(), "".to_string(), 0);
+    let mut whamm = tests::get_ast(script, &mut err).unwrap();
+    let extra = tests::get_ast(extra, &mut err).unwrap();
+    let table = verifier::build_symbol_table(&mut whamm, &mut err);
+    let mut ast = SimpleAST::new();
+    let mut behavior = build_behavior_tree(&whamm, &mut ast, &mut err);
+    behavior.reset();
+    for provider in extra.scripts[0].providers.values() {
+        for package in provider.packages() {
+            for event in package.events() {
+                for (mode, probes) in event.probes() {
+                    ast.probes
+                        .entry(provider.name())
+                        .or_default()
+                        .entry(package.name())
+                        .or_default()
+                        .entry(event.name())
+                        .or_default()
+                        .entry(mode.clone())
+                        .or_default()
+                        .extend(probes.iter().map(|probe| Box::new(probe.as_ref())));
+                }
+            }
+        }
+    }
+
+    let app_bytes = wabt::wat2wasm(
+        "(module (func $callee) (func $main call $callee call $callee call $callee))",
+    )
+    .unwrap();
+    let app_wasm = walrus::Module::from_buffer(&app_bytes).unwrap();
+    let mut emitter = WasmRewritingEmitter::new(app_wasm, table);
+    let mut init = InitGenerator {
+        emitter: Box::new(&mut emitter),
+        context_name: "".to_string(),
+        err: &mut err,
+    };
+    assert!(init.run(&whamm));
+    let mut instr = InstrGenerator {
+        tree: &behavior,
+        emitter: Box::new(&mut emitter),
+        ast,
+        context_name: "".to_string(),
+        curr_provider_name: "".to_string(),
+        curr_package_name: "".to_string(),
+        curr_event_name: "".to_string(),
+        curr_probe_mode: "".to_string(),
+        curr_probe: None,
+        matches: Some(vec![]),
+        match_only,
+        err: &mut err,
+    };
+    instr.run(&behavior);
+    let matches = instr.matches.take().unwrap();
+    assert!(!err.has_errors);
+    (matches, serde_json::from_str(&emitter.metadata()).unwrap())
+}
+
+#[test]
+pub fn probes_after_folded_false_predicate() {
+    setup_logger();
+    // two `before` probes of the call event, the first never fires
+    let script = r#"
+i32 count;
+wasm:bytecode:call:before / target_fn_type == "import" / {
+    count = count + 1;
+}
+    "#;
+    let extra = r#"
+wasm:bytecode:call:before {
+    count = count + 2;
+}
+    "#;
+    let (dry_run, _) = match_sites(script, extra, true);
+    let (matches, metadata) = match_sites(script, extra, false);
+
+    // `--dry-run` and `--stats` see the same sites
+    let preds = |matches: &[ProbeMatch]| -> Vec<(usize, FoldedPred)> {
+        matches
+            .iter()
+            .map(|site| (site.offset, site.predicate.clone()))
+            .collect()
+    };
+    assert_eq!(matches.len(), 6);
+    assert_eq!(preds(&dry_run), preds(&matches));
+    let eliminated = matches
+        .iter()
+        .filter(|site| site.predicate == FoldedPred::False)
+        .count();
+    assert_eq!(eliminated, 3);
+    // the second probe is still injected at every call
+    assert_eq!(metadata["sites"].as_array().unwrap().len(), 3);
+}
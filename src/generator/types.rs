@@ -363,7 +363,24 @@ impl ExprFolder {
         todo!()
     }
 
-    fn fold_call(call: &Expr, _table: &SymbolTable) -> Expr {
+    fn fold_call(call: &Expr, table: &SymbolTable) -> Expr {
+        if let Expr::Call {
+            fn_target,
+            args: Some(args),
+            loc,
+        } = &call
+        {
+            // the call itself cannot be folded, but its arguments can
+            return Expr::Call {
+                fn_target: fn_target.clone(),
+                args: Some(
+                    args.iter()
+                        .map(|arg| Box::new(ExprFolder::fold_expr(arg, table)))
+                        .collect(),
+                ),
+                loc: loc.clone(),
+            };
+        }
         call.clone()
     }
     fn fold_var_id(var_id: &Expr, table: &SymbolTable) -> Expr {
@@ -0,0 +1,83 @@
+use crate::common::parallel::{map_chunks, num_threads};
+use wasmparser::{
+    BinaryReaderError, Import, ImportSectionEntryType, Parser, Payload, ValidPayload, Validator,
+    WasmFeatures,
+};
+
+/// Why a module is invalid
+#[derive(Debug)]
+pub struct InvalidModule {
+    pub message: String,
+    /// The offset in the module where it stopped being valid
+    pub offset: usize,
+    /// The index of the function it stopped being valid in, if any
+    pub func_idx: Option<u32>,
+}
+impl InvalidModule {
+    fn from(err: BinaryReaderError, func_idx: Option<u32>) -> Self {
+        Self {
+            message: err.message().to_string(),
+            offset: err.offset(),
+            func_idx,
+        }
+    }
+}
+
+/// Validate a module, type-checking every function. The same features are enabled as when
+/// `walrus` parses a module.
+pub fn validate(wasm: &[u8]) -> Result<(), InvalidModule> {
+    validate_with_jobs(wasm, 1)
+}
+
+/// Like `validate`, type-checking `jobs` functions in parallel (see `num_threads`). The error is
+/// the one of the first invalid function whatever the number of jobs.
+pub fn validate_with_jobs(wasm: &[u8], jobs: usize) -> Result<(), InvalidModule> {
+    let mut validator = Validator::new();
+    validator.wasm_features(WasmFeatures {
+        reference_types: true,
+        multi_value: true,
+        bulk_memory: true,
+        simd: true,
+        threads: true,
+        multi_memory: true,
+        ..WasmFeatures::default()
+    });
+
+    // the functions are type-checked once the whole module is read, like `validate_all` does
+    let mut imported_funcs = 0;
+    let mut funcs = vec![];
+    for payload in Parser::new(0).parse_all(wasm) {
+        let payload = payload.map_err(|err| InvalidModule::from(err, None))?;
+        if let Payload::ImportSection(imports) = &payload {
+            imported_funcs += imports
+                .clone()
+                .into_iter()
+                .filter(|import| {
+                    matches!(
+                        import,
+                        Ok(Import {
+                            ty: ImportSectionEntryType::Function(_),
+                            ..
+                        })
+                    )
+                })
+                .count() as u32;
+        }
+        let valid = validator
+            .payload(&payload)
+            .map_err(|err| InvalidModule::from(err, None))?;
+        if let ValidPayload::Func(func_validator, body) = valid {
+            funcs.push((imported_funcs + funcs.len() as u32, func_validator, body));
+        }
+    }
+    map_chunks(funcs, num_threads(jobs), |funcs| {
+        for (func_idx, mut func_validator, body) in funcs {
+            func_validator
+                .validate(&body)
+                .map_err(|err| InvalidModule::from(err, Some(func_idx)))?;
+        }
+        Ok(())
+    })
+    .into_iter()
+    .collect()
+}
@@ -1,12 +1,24 @@
 extern crate core;
 
-use cli::{Cmd, WhammCli};
+use cli::{Cmd, InstrArgs, WhammCli};
 
 use crate::behavior::builder_visitor::*;
+use crate::behavior::optimizer::optimize_behavior_tree;
+use crate::behavior::serialize::{behavior_from_json, behavior_to_json};
 use crate::common::error::ErrorGen;
-use crate::generator::emitters::{Emitter, WasmRewritingEmitter};
+use crate::common::func_filter::FuncFilter;
+use crate::generator::const_prop::propagate_consts;
+use crate::generator::counters::counters;
+use crate::generator::debug_info::has_debug_info;
+use crate::generator::diff::diff;
+use crate::generator::emitters::{
+    has_whamm_section, hash_script, is_instrumented, Emitter, InstrMemory, MonitorModuleEmitter,
+    PrintBackend, VirgilEmitter, WasiEmitter, WasmRewritingEmitter,
+};
 use crate::generator::init_generator::InitGenerator;
-use crate::generator::instr_generator::InstrGenerator;
+use crate::generator::instr_generator::{FoldedPred, InstrGenerator, ProbeMatch};
+use crate::generator::source_map::SourceMap;
+use crate::generator::stats::{stats, Stats};
 use crate::parser::whamm_parser::*;
 
 pub mod behavior;
@@ -19,11 +31,14 @@ pub mod verifier;
 use clap::Parser;
 use graphviz_rust::cmd::{CommandArg, Format};
 use graphviz_rust::exec_dot;
-use log::{error, info};
+use log::{error, info, warn};
 use project_root::get_project_root;
-use std::path::PathBuf;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
 use std::process::exit;
-use walrus::Module;
+use termcolor::{BufferWriter, ColorChoice};
+use wabt::{wasm2wat, wat2wasm};
+use walrus::{Module, ModuleConfig};
 
 use crate::behavior::tree::BehaviorTree;
 use crate::behavior::visualize::visualization_to_file;
@@ -63,13 +78,17 @@ fn try_main() -> Result<(), failure::Error> {
             run_info(spec, globals, functions);
         }
         Cmd::Instr(args) => {
-            run_instr(
-                args.app,
-                args.script,
-                args.output_path,
-                args.virgil,
-                args.run_verifier,
-            );
+            run_instr(*args);
+        }
+        Cmd::Symbolize { wasm, func, offset } => {
+            run_symbolize(wasm, func, offset);
+        }
+        Cmd::Diff {
+            app,
+            instrumented,
+            script,
+        } => {
+            run_diff(app, instrumented, script);
         }
         Cmd::VisWasm { wasm, output_path } => {
             run_vis_wasm(wasm, output_path);
@@ -78,8 +97,10 @@ fn try_main() -> Result<(), failure::Error> {
             script,
             run_verifier,
             output_path,
+            optimize_tree,
+            format,
         } => {
-            run_vis_script(script, run_verifier, output_path);
+            run_vis_script(script, run_verifier, output_path, optimize_tree, format);
         }
     }
 
@@ -94,55 +115,205 @@ fn run_info(spec: String, print_globals: bool, print_functions: bool) {
     err.fatal_report("PrintInfo");
 }
 
-fn run_instr(
-    app_wasm_path: String,
-    script_path: String,
-    output_wasm_path: String,
-    emit_virgil: bool,
-    run_verifier: bool,
-) {
+fn run_instr(args: InstrArgs) {
+    let InstrArgs {
+        app: app_wasm_path,
+        script: script_path,
+        output_path: output_wasm_path,
+        emit,
+        only_fn,
+        skip_fn,
+        virgil: emit_virgil,
+        monitor_module: emit_monitor_module,
+        wasi: emit_wasi,
+        print_backend,
+        multi_memory,
+        instr_memory,
+        strip_debug,
+        preserve_debug,
+        dry_run,
+        stats: print_stats,
+        jobs,
+        inline_threshold,
+        coalesce_counters,
+        optimize_tree,
+        behavior: behavior_path,
+        layer,
+        run_verifier,
+    } = args;
+
     // Set up error reporting mechanism
     let mut err = ErrorGen::new(script_path.clone(), "".to_string(), MAX_ERRORS);
 
     // Process the script
     let mut whamm = get_script_ast(&script_path, &mut err);
-    let symbol_table = get_symbol_table(&mut whamm, run_verifier, &mut err);
-    let (behavior_tree, simple_ast) = build_behavior(&whamm, &mut err);
+    let mut symbol_table = get_symbol_table(&mut whamm, run_verifier, &mut err);
+    let consts = propagate_consts(&mut whamm, &mut symbol_table);
+    if !consts.is_empty() {
+        info!("Propagating the constant globals: {}", consts.join(", "));
+    }
+    let (mut behavior_tree, mut simple_ast) = match &behavior_path {
+        Some(path) => load_behavior(path, &whamm, &mut err),
+        None => build_behavior(&whamm, &mut err),
+    };
+    if optimize_tree {
+        let removed = optimize_behavior_tree(&mut behavior_tree, &mut simple_ast, &whamm);
+        info!("Removed {removed} nodes from the behavior tree");
+    }
 
     // If there were any errors encountered, report and exit!
     err.check_has_errors();
 
     // Read app Wasm into Walrus module
-    let _config = walrus::ModuleConfig::new();
     if !PathBuf::from(&app_wasm_path).exists() {
         error!("Wasm module does not exist at: {}", app_wasm_path);
         exit(1);
     }
-    let app_wasm = Module::from_file(app_wasm_path).unwrap();
+    let app_bytes = read_app(&app_wasm_path, &mut err);
+    if has_debug_info(&app_bytes) && !strip_debug && !preserve_debug {
+        warn!(
+            "The app at {app_wasm_path} has DWARF debug info, it is updated so that injected code \
+            maps to the line of the instruction before it. Pass --strip-debug to drop it instead, \
+            or --preserve-debug to keep it without this warning."
+        );
+    }
+    // the code transform is needed to build the source map (see `SourceMap`)
+    let app_wasm = ModuleConfig::new()
+        .generate_dwarf(!strip_debug)
+        .preserve_code_transform(true)
+        .parse(&app_bytes)
+        .unwrap();
+    if is_instrumented(&app_wasm) {
+        let reason = if !layer {
+            Some("instrument the original app instead, or pass --layer to add to its instrumentation")
+        } else if !has_whamm_section(&app_wasm) || SourceMap::ranges(&app_wasm).is_err() {
+            // the source map tells the code injected into the app's functions
+            Some("its `whamm` or `whamm.sourcemap` custom section was stripped, so it can't be instrumented again")
+        } else if multi_memory
+            && app_wasm
+                .exports
+                .iter()
+                .any(|export| export.name == "whamm_memory")
+        {
+            Some("its instrumentation already has a dedicated memory, layer without --multi-memory")
+        } else if print_backend.as_deref() == Some("buffer")
+            && app_wasm
+                .exports
+                .iter()
+                .any(|export| export.name == "whamm_output")
+        {
+            Some("its instrumentation already prints to a buffer, layer with another print backend")
+        } else {
+            None
+        };
+        if let Some(reason) = reason {
+            err.add_error(ErrorGen::get_unexpected_error(
+                true,
+                Some(format!(
+                    "The app at {app_wasm_path} was already instrumented by whamm!, {reason}."
+                )),
+                None,
+            ));
+        }
+    }
+    // recorded in the `whamm` custom section of the instrumented app
+    let script_hash = hash_script(&std::fs::read_to_string(&script_path).unwrap_or_default());
 
-    // Configure the emitter based on target instrumentation code format
-    let mut emitter = if emit_virgil {
-        unimplemented!();
+    let instr_memory = if multi_memory {
+        InstrMemory::Dedicated
+    } else if let Some(idx) = instr_memory {
+        InstrMemory::App(idx)
     } else {
-        WasmRewritingEmitter::new(app_wasm, symbol_table)
+        InstrMemory::Default
     };
 
-    // Phase 0 of instrumentation (emit globals and provided fns)
-    let mut init = InitGenerator {
-        emitter: Box::new(&mut emitter),
-        context_name: "".to_string(),
-        err: &mut err,
+    // Configure the emitter based on target instrumentation code format
+    let (mut emitter, output_path): (Box<dyn Emitter>, String) = if emit_virgil {
+        (
+            Box::new(VirgilEmitter::new(app_wasm, &app_bytes, symbol_table)),
+            // the app is left untouched, output the monitor instead
+            PathBuf::from(&output_wasm_path)
+                .with_extension("v3")
+                .to_string_lossy()
+                .to_string(),
+        )
+    } else if emit_monitor_module {
+        (
+            Box::new(MonitorModuleEmitter::new(
+                app_wasm,
+                &app_bytes,
+                symbol_table,
+            )),
+            output_wasm_path,
+        )
+    } else if let Some(print_backend) = print_backend {
+        let mut emitter =
+            WasmRewritingEmitter::new_with_memory(app_wasm, symbol_table, instr_memory)
+                .unwrap_or_else(|e| {
+                    err.add_error(*e);
+                    exit(1)
+                });
+        emitter.print_backend = match print_backend.as_str() {
+            "buffer" => PrintBackend::Buffer,
+            _ => PrintBackend::HostImport,
+        };
+        emitter.script_hash = Some(script_hash);
+        emitter.source_map.script_path = Some(script_path.clone());
+        (Box::new(emitter), output_wasm_path)
+    } else if emit_wasi {
+        let mut emitter = WasiEmitter::new_with_memory(app_wasm, symbol_table, instr_memory)
+            .unwrap_or_else(|e| {
+                err.add_error(*e);
+                exit(1)
+            });
+        emitter.emitter.script_hash = Some(script_hash);
+        emitter.emitter.source_map.script_path = Some(script_path.clone());
+        (Box::new(emitter), output_wasm_path)
+    } else {
+        let mut emitter =
+            WasmRewritingEmitter::new_with_memory(app_wasm, symbol_table, instr_memory)
+                .unwrap_or_else(|e| {
+                    err.add_error(*e);
+                    exit(1)
+                });
+        emitter.script_hash = Some(script_hash);
+        emitter.source_map.script_path = Some(script_path.clone());
+        (Box::new(emitter), output_wasm_path)
     };
-    init.run(&whamm);
-    // If there were any errors encountered, report and exit!
-    err.check_has_errors();
+
+    emitter.set_jobs(jobs);
+    emitter.set_inline_threshold(inline_threshold);
+    if coalesce_counters {
+        emitter.coalesce_counters(counters(&simple_ast, &whamm));
+    }
+    match FuncFilter::new(&only_fn, &skip_fn) {
+        Ok(func_filter) => emitter.add_func_filter(func_filter),
+        Err(e) => err.add_error(ErrorGen::get_unexpected_error(true, Some(e), None)),
+    }
+    // the directives of the scripts add to the flags
+    for script in whamm.scripts.iter() {
+        emitter.add_func_filter(script.func_filter.clone());
+    }
+
+    // Phase 0 of instrumentation (emit globals and provided fns), a dry run only matches the
+    // probes to the sites
+    if dry_run.is_none() {
+        let mut init = InitGenerator {
+            emitter: Box::new(emitter.as_mut()),
+            context_name: "".to_string(),
+            err: &mut err,
+        };
+        init.run(&whamm);
+        // If there were any errors encountered, report and exit!
+        err.check_has_errors();
+    }
 
     // Phase 1 of instrumentation (actually emits the instrumentation code)
     // This structure is necessary since we need to have the fns/globals injected (a single time)
     // and ready to use in every body/predicate.
     let mut instr = InstrGenerator {
         tree: &behavior_tree,
-        emitter: Box::new(&mut emitter),
+        emitter: Box::new(emitter.as_mut()),
         ast: simple_ast,
         err: &mut err,
         context_name: "".to_string(),
@@ -151,21 +322,373 @@ fn run_instr(
         curr_event_name: "".to_string(),
         curr_probe_mode: "".to_string(),
         curr_probe: None,
+        matches: (dry_run.is_some() || print_stats.is_some()).then(Vec::new),
+        match_only: dry_run.is_some(),
     };
     instr.run(&behavior_tree);
+    let matches = instr.matches.take().unwrap_or_default();
     // If there were any errors encountered, report and exit!
     err.check_has_errors();
 
+    if let Some(format) = dry_run {
+        // nothing is written
+        match format.as_str() {
+            "json" => println!("{}", matches_to_json(&matches)),
+            _ => print_matches(&matches),
+        }
+        return;
+    }
+
     // create output path if it doesn't exist
-    if !PathBuf::from(&output_wasm_path).exists() {
-        std::fs::create_dir_all(PathBuf::from(&output_wasm_path).parent().unwrap()).unwrap();
+    if !PathBuf::from(&output_path).exists() {
+        std::fs::create_dir_all(PathBuf::from(&output_path).parent().unwrap()).unwrap();
     }
 
-    if let Err(e) = emitter.dump_to_file(output_wasm_path) {
+    // the binary is written first, then printed as text if requested
+    let wat_path = PathBuf::from(&output_path).with_extension("wat");
+    let output_path = if emit == "wasm" {
+        output_path
+    } else {
+        PathBuf::from(&output_path)
+            .with_extension("wasm")
+            .to_string_lossy()
+            .to_string()
+    };
+    if let Err(e) = emitter.dump_to_file(output_path.clone()) {
         err.add_error(*e)
     }
     // If there were any errors encountered, report and exit!
     err.check_has_errors();
+
+    if let Some(format) = print_stats {
+        let res = std::fs::read(&output_path)
+            .map_err(|e| e.to_string())
+            .and_then(|wasm| stats(&matches, &app_bytes, &wasm));
+        match res {
+            Ok(stats) => match format.as_str() {
+                "json" => println!(
+                    "{}",
+                    serde_json::to_string_pretty(&stats.to_json()).unwrap()
+                ),
+                _ => print_stats_table(&stats),
+            },
+            Err(e) => err.add_error(ErrorGen::get_unexpected_error(
+                true,
+                Some(format!(
+                    "Cannot summarize the instrumentation of {output_path}: {e}"
+                )),
+                None,
+            )),
+        }
+        err.check_has_errors();
+    }
+
+    if emit != "wasm" {
+        write_wat(&output_path, &wat_path, &mut err);
+        if emit == "wat" {
+            let _ = std::fs::remove_file(&output_path);
+        }
+        err.check_has_errors();
+    }
+}
+
+/// The sites matched by the probes, as a JSON array
+fn matches_to_json(matches: &[ProbeMatch]) -> String {
+    let matches: Vec<serde_json::Value> = matches
+        .iter()
+        .map(|site| {
+            serde_json::json!({
+                "probe": site.probe,
+                "mode": site.mode,
+                "func_index": site.func_idx,
+                "offset": site.offset,
+                "instr": site.instr,
+                "predicate": site.predicate.as_str(),
+            })
+        })
+        .collect();
+    serde_json::to_string_pretty(&matches).unwrap()
+}
+
+/// Prints rows of cells as columns aligned under the header
+fn print_table<const N: usize>(header: [&str; N], rows: &[[String; N]]) {
+    let widths: Vec<usize> = (0..N)
+        .map(|col| {
+            rows.iter()
+                .map(|row| row[col].len())
+                .chain([header[col].len()])
+                .max()
+                .unwrap_or_default()
+        })
+        .collect();
+    let print_row = |row: Vec<&str>| {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(widths.iter())
+            .map(|(cell, width)| format!("{cell:width$}"))
+            .collect();
+        println!("{}", cells.join("  ").trim_end());
+    };
+    print_row(header.to_vec());
+    for row in rows.iter() {
+        print_row(row.iter().map(|cell| cell.as_str()).collect());
+    }
+}
+
+/// Prints the sites matched by the probes as a table
+fn print_matches(matches: &[ProbeMatch]) {
+    let rows: Vec<[String; 6]> = matches
+        .iter()
+        .map(|site| {
+            [
+                site.probe.clone(),
+                site.mode.clone(),
+                site.func_idx.to_string(),
+                format!("{:#x}", site.offset),
+                site.instr.to_string(),
+                site.predicate.as_str().to_string(),
+            ]
+        })
+        .collect();
+    print_table(
+        [
+            "PROBE",
+            "MODE",
+            "FUNCTION",
+            "OFFSET",
+            "INSTRUCTION",
+            "PREDICATE",
+        ],
+        &rows,
+    );
+    let probes: HashSet<(&String, &String)> = matches
+        .iter()
+        .map(|site| (&site.probe, &site.mode))
+        .collect();
+    println!(
+        "{} site(s) matched by {} probe(s), {} of them can't fire (the predicate is `false`)",
+        matches.len(),
+        probes.len(),
+        matches
+            .iter()
+            .filter(|site| site.predicate == FoldedPred::False)
+            .count()
+    );
+}
+
+/// Prints the summary of the instrumentation as tables
+fn print_stats_table(stats: &Stats) {
+    let growth = |from: usize, to: usize| {
+        let percent = if from == 0 {
+            0.0
+        } else {
+            (to as f64 - from as f64) * 100.0 / from as f64
+        };
+        format!("{:+} ({percent:+.1}%)", to as i64 - from as i64)
+    };
+
+    let rows: Vec<[String; 5]> = stats
+        .probes
+        .iter()
+        .map(|probe| {
+            [
+                probe.probe.clone(),
+                probe.mode.clone(),
+                probe.sites.to_string(),
+                probe.eliminated.to_string(),
+                probe.dynamic.to_string(),
+            ]
+        })
+        .collect();
+    print_table(["PROBE", "MODE", "SITES", "ELIMINATED", "DYNAMIC"], &rows);
+    println!();
+
+    let rows: Vec<[String; 6]> = stats
+        .funcs
+        .iter()
+        .map(|func| {
+            [
+                match &func.name {
+                    Some(name) => format!("{} `{name}`", func.app_func_idx),
+                    None => func.app_func_idx.to_string(),
+                },
+                func.app_size.to_string(),
+                func.size.to_string(),
+                growth(func.app_size, func.size),
+                func.injected_instrs.to_string(),
+                func.injected_locals.to_string(),
+            ]
+        })
+        .collect();
+    print_table(
+        ["FUNCTION", "SIZE", "NEW SIZE", "GROWTH", "INSTRS", "LOCALS"],
+        &rows,
+    );
+    println!();
+
+    println!(
+        "Injected {} function(s), {} global(s), {} local(s) and {} instruction(s)",
+        stats.injected_funcs,
+        stats.injected_globals,
+        stats.injected_locals(),
+        stats.injected_instrs()
+    );
+    println!(
+        "Code size: {} -> {} bytes, {}",
+        stats.app_code_size,
+        stats.code_size,
+        growth(stats.app_code_size, stats.code_size)
+    );
+}
+
+/// Reads the app's Wasm module, converting it to binary if it's in text form (`.wat`)
+fn read_app(app_path: &str, err: &mut ErrorGen) -> Vec<u8> {
+    let bytes = std::fs::read(app_path).unwrap();
+    if PathBuf::from(app_path)
+        .extension()
+        .is_some_and(|ext| ext == "wat")
+    {
+        match wat2wasm(&bytes) {
+            Ok(wasm) => wasm,
+            Err(e) => {
+                err.add_error(ErrorGen::get_unexpected_error(
+                    true,
+                    Some(format!("Cannot read the Wasm text at {app_path}: {e}")),
+                    None,
+                ));
+                err.check_has_errors();
+                exit(1);
+            }
+        }
+    } else {
+        bytes
+    }
+}
+
+/// Prints the instrumented Wasm module in text form
+fn write_wat(wasm_path: &str, wat_path: &Path, err: &mut ErrorGen) {
+    let res = std::fs::read(wasm_path)
+        .map_err(|e| e.to_string())
+        .and_then(|wasm| wasm2wat(wasm).map_err(|e| e.to_string()))
+        .and_then(|wat| std::fs::write(wat_path, wat).map_err(|e| e.to_string()));
+    if let Err(e) = res {
+        err.add_error(ErrorGen::get_unexpected_error(
+            true,
+            Some(format!(
+                "Cannot print the instrumented Wasm module at {wasm_path} as text to {}: {e}",
+                wat_path.display()
+            )),
+            None,
+        ));
+    }
+}
+
+fn run_symbolize(wasm_path: String, func_idx: u32, offset: usize) {
+    let app_wasm = match Module::from_file(&wasm_path) {
+        Ok(app_wasm) => app_wasm,
+        Err(e) => {
+            error!("Cannot read the Wasm module at {}: {}", wasm_path, e);
+            exit(1);
+        }
+    };
+    let range = match SourceMap::lookup(&app_wasm, func_idx, offset) {
+        Ok(res) => res,
+        Err(e) => {
+            error!("{}", e);
+            exit(1);
+        }
+    };
+    let Some(range) = range else {
+        println!(
+            "Function {} at offset {:#x} was not injected by a statement of the script",
+            func_idx, offset
+        );
+        return;
+    };
+
+    let (line, col) = range.span.start;
+    let script_path = range.script.unwrap_or("<script>".to_string());
+    println!(
+        "{}:{}:{} (function {}, offsets {:#x}..{:#x})",
+        script_path, line, col, range.func_idx, range.start, range.end
+    );
+    // show the line of the script, if it's still around
+    if let Some(text) = std::fs::read_to_string(&script_path)
+        .ok()
+        .and_then(|script| script.lines().nth(line - 1).map(|text| text.to_string()))
+    {
+        println!("    {}", text.trim());
+    }
+}
+
+fn run_diff(app_path: String, instrumented_path: Option<String>, script_path: Option<String>) {
+    // whether the instrumented app is a temporary file to delete once read
+    let (instrumented_path, is_temp) = match (instrumented_path, script_path) {
+        (Some(instrumented_path), _) => (instrumented_path, false),
+        (None, Some(script_path)) => {
+            // instrument the app with the defaults of `whamm instr`, to a file of this process
+            // so that concurrent runs don't overwrite each other's
+            let output_path = std::env::temp_dir()
+                .join(format!("whamm_diff_{}.wasm", std::process::id()))
+                .to_string_lossy()
+                .to_string();
+            run_instr(InstrArgs {
+                app: app_path.clone(),
+                script: script_path,
+                output_path: output_path.clone(),
+                emit: "wasm".to_string(),
+                only_fn: vec![],
+                skip_fn: vec![],
+                virgil: false,
+                monitor_module: false,
+                wasi: false,
+                print_backend: None,
+                multi_memory: false,
+                instr_memory: None,
+                strip_debug: false,
+                preserve_debug: true,
+                dry_run: None,
+                stats: None,
+                jobs: 1,
+                inline_threshold: None,
+                coalesce_counters: false,
+                optimize_tree: false,
+                behavior: None,
+                layer: false,
+                run_verifier: true,
+            });
+            (output_path, true)
+        }
+        (None, None) => unreachable!("clap requires the instrumented app or a script"),
+    };
+    let mut err = ErrorGen::new("".to_string(), "".to_string(), MAX_ERRORS);
+    let app_bytes = read_app(&app_path, &mut err);
+    let instrumented_bytes = std::fs::read(&instrumented_path);
+    if is_temp {
+        let _ = std::fs::remove_file(&instrumented_path);
+    }
+    let instrumented_bytes = match instrumented_bytes {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            error!(
+                "Cannot read the Wasm module at {}: {}",
+                instrumented_path, e
+            );
+            exit(1);
+        }
+    };
+    match diff(&app_bytes, &instrumented_bytes) {
+        Ok(diff) => {
+            let writer = BufferWriter::stdout(ColorChoice::Auto);
+            let mut buffer = writer.buffer();
+            diff.print(&mut buffer);
+            writer.print(&buffer).expect("Failed to print the diff");
+        }
+        Err(e) => {
+            error!("{}", e);
+            exit(1);
+        }
+    }
 }
 
 fn run_vis_wasm(wasm_path: String, output_path: String) {
@@ -203,15 +726,28 @@ fn run_vis_wasm(wasm_path: String, output_path: String) {
     }
 }
 
-fn run_vis_script(script_path: String, run_verifier: bool, output_path: String) {
+fn run_vis_script(
+    script_path: String,
+    run_verifier: bool,
+    output_path: String,
+    optimize_tree: bool,
+    format: String,
+) {
     // Set up error reporting mechanism
     let mut err = ErrorGen::new(script_path.clone(), "".to_string(), MAX_ERRORS);
 
     let mut whamm = get_script_ast(&script_path, &mut err);
     // building the symbol table is necessary since it does some minor manipulations of the AST
     // (adds declared globals to the script AST node)
-    let _symbol_table = get_symbol_table(&mut whamm, run_verifier, &mut err);
-    let (behavior_tree, ..) = build_behavior(&whamm, &mut err);
+    let mut symbol_table = get_symbol_table(&mut whamm, run_verifier, &mut err);
+    if optimize_tree {
+        // the same as when instrumenting
+        propagate_consts(&mut whamm, &mut symbol_table);
+    }
+    let (mut behavior_tree, mut simple_ast) = build_behavior(&whamm, &mut err);
+    if optimize_tree {
+        optimize_behavior_tree(&mut behavior_tree, &mut simple_ast, &whamm);
+    }
 
     // if there are any errors, should report and exit!
     err.check_has_errors();
@@ -220,6 +756,16 @@ fn run_vis_script(script_path: String, run_verifier: bool, output_path: String)
         std::fs::create_dir_all(PathBuf::from(&output_path).parent().unwrap()).unwrap();
     }
 
+    if format == "json" {
+        let json_path = PathBuf::from(&output_path).with_extension("json");
+        let json = behavior_to_json(&behavior_tree, &simple_ast, &whamm);
+        if let Err(error) = std::fs::write(&json_path, json) {
+            error!("Cannot write to {}: {}", json_path.display(), error);
+            exit(1);
+        }
+        exit(0);
+    }
+
     let path = match get_pb(&PathBuf::from(output_path.clone())) {
         Ok(pb) => pb,
         Err(_) => exit(1),
@@ -271,6 +817,29 @@ fn get_script_ast(script_path: &String, err: &mut ErrorGen) -> Whamm {
     }
 }
 
+/// Loads the behavior tree written to `path` by `vis-script --format json` for the script of
+/// `whamm`
+fn load_behavior<'a>(
+    path: &String,
+    whamm: &'a Whamm,
+    err: &mut ErrorGen,
+) -> (BehaviorTree, SimpleAST<'a>) {
+    let json = match std::fs::read_to_string(path) {
+        Ok(json) => json,
+        Err(error) => {
+            error!("Cannot read specified file {}: {}", path, error);
+            exit(1);
+        }
+    };
+    match behavior_from_json(&json, whamm) {
+        Ok(behavior) => behavior,
+        Err(e) => {
+            err.add_error(ErrorGen::get_unexpected_error(true, Some(e), None));
+            exit(1);
+        }
+    }
+}
+
 fn build_behavior<'a>(whamm: &'a Whamm, err: &mut ErrorGen) -> (BehaviorTree, SimpleAST<'a>) {
     // Build the behavior tree from the AST
     let mut simple_ast = SimpleAST::new();
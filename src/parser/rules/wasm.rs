@@ -168,6 +168,8 @@ impl Package for WasmPackage {
     }
 }
 
+/// The kinds of bytecode events, one for each variant of walrus' `Instr`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum BytecodeEventKind {
     Block,
     Loop,
@@ -219,56 +221,112 @@ pub enum BytecodeEventKind {
     TableCopy,
 }
 impl BytecodeEventKind {
-    fn name(&self) -> String {
+    pub const ALL: [BytecodeEventKind; 48] = [
+        BytecodeEventKind::Block,
+        BytecodeEventKind::Loop,
+        BytecodeEventKind::Call,
+        BytecodeEventKind::CallIndirect,
+        BytecodeEventKind::LocalGet,
+        BytecodeEventKind::LocalSet,
+        BytecodeEventKind::LocalTee,
+        BytecodeEventKind::GlobalGet,
+        BytecodeEventKind::GlobalSet,
+        BytecodeEventKind::Const,
+        BytecodeEventKind::Binop,
+        BytecodeEventKind::Unop,
+        BytecodeEventKind::Select,
+        BytecodeEventKind::Unreachable,
+        BytecodeEventKind::Br,
+        BytecodeEventKind::BrIf,
+        BytecodeEventKind::IfElse,
+        BytecodeEventKind::BrTable,
+        BytecodeEventKind::Drop,
+        BytecodeEventKind::Return,
+        BytecodeEventKind::MemorySize,
+        BytecodeEventKind::MemoryGrow,
+        BytecodeEventKind::MemoryInit,
+        BytecodeEventKind::DataDrop,
+        BytecodeEventKind::MemoryCopy,
+        BytecodeEventKind::MemoryFill,
+        BytecodeEventKind::Load,
+        BytecodeEventKind::Store,
+        BytecodeEventKind::AtomicRmw,
+        BytecodeEventKind::Cmpxchg,
+        BytecodeEventKind::AtomicNotify,
+        BytecodeEventKind::AtomicWait,
+        BytecodeEventKind::AtomicFence,
+        BytecodeEventKind::TableGet,
+        BytecodeEventKind::TableSet,
+        BytecodeEventKind::TableGrow,
+        BytecodeEventKind::TableSize,
+        BytecodeEventKind::TableFill,
+        BytecodeEventKind::RefNull,
+        BytecodeEventKind::RefIsNull,
+        BytecodeEventKind::RefFunc,
+        BytecodeEventKind::V128Bitselect,
+        BytecodeEventKind::I8x16Swizzle,
+        BytecodeEventKind::I8x16Shuffle,
+        BytecodeEventKind::LoadSimd,
+        BytecodeEventKind::TableInit,
+        BytecodeEventKind::ElemDrop,
+        BytecodeEventKind::TableCopy,
+    ];
+
+    /// The kind of the event named `name` in a probe specification, e.g. `br_if`
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+
+    pub fn name(&self) -> &'static str {
         match self {
-            BytecodeEventKind::Block => "block".to_string(),
-            BytecodeEventKind::Loop => "loop".to_string(),
-            BytecodeEventKind::Call => "call".to_string(),
-            BytecodeEventKind::CallIndirect => "call_indirect".to_string(),
-            BytecodeEventKind::LocalGet => "local_get".to_string(),
-            BytecodeEventKind::LocalSet => "local_set".to_string(),
-            BytecodeEventKind::LocalTee => "local_tee".to_string(),
-            BytecodeEventKind::GlobalGet => "global_get".to_string(),
-            BytecodeEventKind::GlobalSet => "global_set".to_string(),
-            BytecodeEventKind::Const => "const".to_string(),
-            BytecodeEventKind::Binop => "binop".to_string(),
-            BytecodeEventKind::Unop => "unop".to_string(),
-            BytecodeEventKind::Select => "select".to_string(),
-            BytecodeEventKind::Unreachable => "unreachable".to_string(),
-            BytecodeEventKind::Br => "br".to_string(),
-            BytecodeEventKind::BrIf => "br_if".to_string(),
-            BytecodeEventKind::IfElse => "if_else".to_string(),
-            BytecodeEventKind::BrTable => "br_table".to_string(),
-            BytecodeEventKind::Drop => "drop".to_string(),
-            BytecodeEventKind::Return => "return".to_string(),
-            BytecodeEventKind::MemorySize => "memory_size".to_string(),
-            BytecodeEventKind::MemoryGrow => "memory_grow".to_string(),
-            BytecodeEventKind::MemoryInit => "memory_init".to_string(),
-            BytecodeEventKind::DataDrop => "data_drop".to_string(),
-            BytecodeEventKind::MemoryCopy => "memory_copy".to_string(),
-            BytecodeEventKind::MemoryFill => "memory_fill".to_string(),
-            BytecodeEventKind::Load => "load".to_string(),
-            BytecodeEventKind::Store => "store".to_string(),
-            BytecodeEventKind::AtomicRmw => "atomic_rmw".to_string(),
-            BytecodeEventKind::Cmpxchg => "cmpxchg".to_string(),
-            BytecodeEventKind::AtomicNotify => "atomic_notify".to_string(),
-            BytecodeEventKind::AtomicWait => "atomic_wait".to_string(),
-            BytecodeEventKind::AtomicFence => "atomic_fence".to_string(),
-            BytecodeEventKind::TableGet => "table_get".to_string(),
-            BytecodeEventKind::TableSet => "table_set".to_string(),
-            BytecodeEventKind::TableGrow => "table_grow".to_string(),
-            BytecodeEventKind::TableSize => "table_size".to_string(),
-            BytecodeEventKind::TableFill => "table_fill".to_string(),
-            BytecodeEventKind::RefNull => "ref_null".to_string(),
-            BytecodeEventKind::RefIsNull => "ref_is_null".to_string(),
-            BytecodeEventKind::RefFunc => "ref_func".to_string(),
-            BytecodeEventKind::V128Bitselect => "v128_bitselect".to_string(),
-            BytecodeEventKind::I8x16Swizzle => "i8x16_swizzle".to_string(),
-            BytecodeEventKind::I8x16Shuffle => "i8x16_shuffle".to_string(),
-            BytecodeEventKind::LoadSimd => "load_simd".to_string(),
-            BytecodeEventKind::TableInit => "table_init".to_string(),
-            BytecodeEventKind::ElemDrop => "elem_drop".to_string(),
-            BytecodeEventKind::TableCopy => "table_copy".to_string(),
+            BytecodeEventKind::Block => "block",
+            BytecodeEventKind::Loop => "loop",
+            BytecodeEventKind::Call => "call",
+            BytecodeEventKind::CallIndirect => "call_indirect",
+            BytecodeEventKind::LocalGet => "local_get",
+            BytecodeEventKind::LocalSet => "local_set",
+            BytecodeEventKind::LocalTee => "local_tee",
+            BytecodeEventKind::GlobalGet => "global_get",
+            BytecodeEventKind::GlobalSet => "global_set",
+            BytecodeEventKind::Const => "const",
+            BytecodeEventKind::Binop => "binop",
+            BytecodeEventKind::Unop => "unop",
+            BytecodeEventKind::Select => "select",
+            BytecodeEventKind::Unreachable => "unreachable",
+            BytecodeEventKind::Br => "br",
+            BytecodeEventKind::BrIf => "br_if",
+            BytecodeEventKind::IfElse => "if_else",
+            BytecodeEventKind::BrTable => "br_table",
+            BytecodeEventKind::Drop => "drop",
+            BytecodeEventKind::Return => "return",
+            BytecodeEventKind::MemorySize => "memory_size",
+            BytecodeEventKind::MemoryGrow => "memory_grow",
+            BytecodeEventKind::MemoryInit => "memory_init",
+            BytecodeEventKind::DataDrop => "data_drop",
+            BytecodeEventKind::MemoryCopy => "memory_copy",
+            BytecodeEventKind::MemoryFill => "memory_fill",
+            BytecodeEventKind::Load => "load",
+            BytecodeEventKind::Store => "store",
+            BytecodeEventKind::AtomicRmw => "atomic_rmw",
+            BytecodeEventKind::Cmpxchg => "cmpxchg",
+            BytecodeEventKind::AtomicNotify => "atomic_notify",
+            BytecodeEventKind::AtomicWait => "atomic_wait",
+            BytecodeEventKind::AtomicFence => "atomic_fence",
+            BytecodeEventKind::TableGet => "table_get",
+            BytecodeEventKind::TableSet => "table_set",
+            BytecodeEventKind::TableGrow => "table_grow",
+            BytecodeEventKind::TableSize => "table_size",
+            BytecodeEventKind::TableFill => "table_fill",
+            BytecodeEventKind::RefNull => "ref_null",
+            BytecodeEventKind::RefIsNull => "ref_is_null",
+            BytecodeEventKind::RefFunc => "ref_func",
+            BytecodeEventKind::V128Bitselect => "v128_bitselect",
+            BytecodeEventKind::I8x16Swizzle => "i8x16_swizzle",
+            BytecodeEventKind::I8x16Shuffle => "i8x16_shuffle",
+            BytecodeEventKind::LoadSimd => "load_simd",
+            BytecodeEventKind::TableInit => "table_init",
+            BytecodeEventKind::ElemDrop => "elem_drop",
+            BytecodeEventKind::TableCopy => "table_copy",
         }
     }
 }
@@ -1452,7 +1510,7 @@ impl BytecodeEvent {
 }
 impl Event for BytecodeEvent {
     fn name(&self) -> String {
-        self.kind.name()
+        self.kind.name().to_string()
     }
 
     fn loc(&self) -> &Option<Location> {
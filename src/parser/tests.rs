@@ -226,6 +226,16 @@ wasm:bytecode:br:before {
     "wasm:bytecode:call:alt { i32 arg; }",
     "wasm:bytecode:call:alt { arg = 1; }",
     "wasm:bytecode:call:alt { arg0 = 1; }",
+    // directives
+    r#"
+@only_fn("handle_*");
+@skip_fn("alloc*", "export:free", "3..10");
+wasm:bytecode:call:alt { }
+    "#,
+    r#"
+wasm:bytecode:call:alt { }
+@skip_fn("4");
+    "#,
 ];
 
 const FATAL_SCRIPTS: &[&str] = &[
@@ -312,6 +322,10 @@ map<i32, i32> count;
     r#"
 map<i32, i32> arg0;
     "#,
+    // bad directives
+    r#"@ignore_fn("alloc"); wasm:bytecode:call:alt { }"#,
+    "@skip_fn(); wasm:bytecode:call:alt { }",
+    "@skip_fn(alloc); wasm:bytecode:call:alt { }",
 ];
 
 const SPECIAL: &[&str] = &["BEGIN { }", "END { }", "wasm:::alt { }", "wasm:::alt { }"];
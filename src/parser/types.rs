@@ -3,6 +3,7 @@ use std::collections::HashMap;
 use termcolor::{Buffer, ColorChoice, WriteColor};
 
 use crate::common::error::{ErrorGen, WhammError};
+use crate::common::func_filter::FuncFilter;
 use crate::common::terminal::{green, grey_italics, long_line, magenta, white, yellow};
 use crate::parser::rules::{
     print_provider_docs, provider_factory, Event, Package, Probe, Provider, WhammProvider,
@@ -499,7 +500,26 @@ impl Whamm {
             Some(DataType::Boolean),
         );
 
-        vec![strcmp]
+        let print_params = vec![(
+            Expr::VarId {
+                is_comp_provided: true,
+                name: "fmt".to_string(),
+                loc: None,
+            },
+            DataType::Str,
+        )];
+
+        let print = ProvidedFunction::new(
+            "print".to_string(),
+            "Print to the output of the instrumentation (followed by a newline). \
+            Each `{}` in the format string is replaced by the next argument, e.g. \
+            `print(\"call to {} with {}\", target_imp_name, arg0)`."
+                .to_string(),
+            print_params,
+            Some(DataType::Null),
+        );
+
+        vec![strcmp, print]
     }
 
     fn get_provided_globals() -> HashMap<String, ProvidedGlobal> {
@@ -652,6 +672,8 @@ pub struct Script {
     pub fns: Vec<Fn>,                     // User-provided
     pub globals: HashMap<String, Global>, // User-provided, should be VarId
     pub global_stmts: Vec<Statement>,
+    /// Which functions of the app to instrument (see the `@only_fn` and `@skip_fn` directives)
+    pub func_filter: FuncFilter,
 }
 impl Default for Script {
     fn default() -> Self {
@@ -666,6 +688,7 @@ impl Script {
             fns: vec![],
             globals: HashMap::new(),
             global_stmts: vec![],
+            func_filter: FuncFilter::default(),
         }
     }
 
@@ -2,6 +2,7 @@ use crate::parser::types;
 use types::{BinOp, Block, FnId, Rule, UnOp, WhammParser, PRATT_PARSER};
 
 use crate::common::error::{ErrorGen, WhammError};
+use crate::common::func_filter::FuncPattern;
 use crate::parser::types::{
     DataType, Expr, Location, ProbeSpec, Script, SpecPart, Statement, Value, Whamm,
 };
@@ -208,6 +209,25 @@ pub fn process_pair(whamm: &mut Whamm, script_count: usize, pair: Pair<Rule>, er
 
             trace!("Exiting probe_def");
         }
+        Rule::directive => {
+            trace!("Entering directive");
+            let mut pair = pair.into_inner();
+            let name = pair.next().unwrap().as_str();
+            let script: &mut Script = whamm.scripts.get_mut(script_count).unwrap();
+            for pattern in pair {
+                let loc = LineColLocation::from(pattern.as_span());
+                match FuncPattern::new(&string_from_rule(&pattern)) {
+                    Ok(pattern) => match name {
+                        "only_fn" => script.func_filter.only.push(pattern),
+                        _ => script.func_filter.skip.push(pattern),
+                    },
+                    Err(e) => {
+                        err.parse_error(false, Some(e), Some(loc), vec![Rule::STRING], vec![])
+                    }
+                }
+            }
+            trace!("Exiting directive");
+        }
         Rule::EOI => {}
         //Rule looks like this: fn_def = { ID ~ "(" ~ ( param ) ? ~ ("," ~ param )* ~ ")" ~ "->" ~ TYPE ~ block }
         Rule::fn_def => {
@@ -1151,19 +1171,7 @@ fn expr_primary(pair: Pair<Rule>) -> Result<Expr, Vec<WhammError>> {
         }
         Rule::STRING => {
             trace!("Entering STRING");
-            let mut val: String = pair.as_str().parse().unwrap();
-            if val.starts_with('\"') {
-                val = val
-                    .strip_prefix('\"')
-                    .expect("Should never get here...")
-                    .to_string();
-            }
-            if val.ends_with('\"') {
-                val = val
-                    .strip_suffix('\"')
-                    .expect("Should never get here...")
-                    .to_string();
-            }
+            let val = string_from_rule(&pair);
 
             trace!("Exiting STRING");
             return Ok(Expr::Primitive {
@@ -1182,6 +1190,24 @@ fn expr_primary(pair: Pair<Rule>) -> Result<Expr, Vec<WhammError>> {
     }
 }
 
+/// The contents of a STRING, without its quotes
+fn string_from_rule(pair: &Pair<Rule>) -> String {
+    let mut val: String = pair.as_str().parse().unwrap();
+    if val.starts_with('\"') {
+        val = val
+            .strip_prefix('\"')
+            .expect("Should never get here...")
+            .to_string();
+    }
+    if val.ends_with('\"') {
+        val = val
+            .strip_suffix('\"')
+            .expect("Should never get here...")
+            .to_string();
+    }
+    val
+}
+
 fn expr_from_pair(pair: Pair<Rule>) -> Result<Expr, Vec<WhammError>> {
     return match pair.as_rule() {
         Rule::ternary => {
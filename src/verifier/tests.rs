@@ -303,6 +303,39 @@ wasm::call:alt /
             i32 strcmp;
         }
     "#,
+    // print
+    r#"
+wasm:bytecode:call:before {
+    print("{} and {}", arg0);
+}
+    "#,
+    r#"
+wasm:bytecode:call:before {
+    print(arg0);
+}
+    "#,
+    r#"
+(i32, i32) t;
+wasm:bytecode:call:before {
+    print("{}", t);
+}
+    "#,
+    r#"
+i32 a;
+wasm:bytecode:call:before {
+    a = print("a");
+}
+    "#,
+    // only known at runtime, not provided yet
+    r#"
+wasm:bytecode:br_if:before / condition == 1 / {}
+    "#,
+    r#"
+i32 a;
+wasm:bytecode:br_if:before {
+    a = condition;
+}
+    "#,
 ];
 
 // =============
@@ -349,11 +382,11 @@ wasm::call:alt /
             let table = verifier::build_symbol_table(&mut ast, &mut err);
             println!("{:#?}", table);
 
-            // 7 scopes: whamm, strcmp, script0, wasm, bytecode, call, alt
-            let num_scopes = 7;
-            // records: num_scopes PLUS (str_addr, value, wasm_bytecode_loc, new_target_fn_name, target_imp_name, target_fn_type, target_imp_module)
-            // TODO -- change to + 8 when add back: arg[0:9]+
-            let num_recs = num_scopes + 7;
+            // 8 scopes: whamm, strcmp, print, script0, wasm, bytecode, call, alt
+            let num_scopes = 8;
+            // records: num_scopes PLUS (str_addr, value, fmt, wasm_bytecode_loc, new_target_fn_name, target_imp_name, target_fn_type, target_imp_module)
+            // TODO -- change to + 9 when add back: arg[0:9]+
+            let num_recs = num_scopes + 8;
 
             // asserts on very high level table structure
             assert_eq!(num_scopes, table.scopes.len());
@@ -427,6 +460,22 @@ pub fn test_template() {
     };
 }
 #[test]
+pub fn test_print() {
+    setup_logger();
+    let mut err = ErrorGen::new("".to_string(), "".to_string(), 0);
+    let script = r#"
+        i32 count;
+        wasm:bytecode:call:before {
+            count = count + 1;
+            print("no args");
+            print("call #{} to {} with {}, {}", count, target_imp_name, arg0, count > 1);
+        }
+    "#;
+    assert!(is_valid_script(script, &mut err));
+    err.report();
+    assert!(!err.has_errors);
+}
+#[test]
 pub fn test_expect_fatal() {
     let result = std::panic::catch_unwind(|| {
         expect_fatal_error();
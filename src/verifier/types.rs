@@ -42,6 +42,18 @@ impl SymbolTable {
         self.scopes.get_mut(self.curr_scope)
     }
 
+    /// The scopes from the root down to the current scope
+    pub fn get_curr_scope_path(&self) -> Vec<&Scope> {
+        let mut path = vec![];
+        let mut curr = self.get_curr_scope();
+        while let Some(scope) = curr {
+            path.push(scope);
+            curr = scope.parent.and_then(|id| self.scopes.get(id));
+        }
+        path.reverse();
+        path
+    }
+
     pub fn set_curr_scope_info(&mut self, name: String, ty: ScopeType) {
         let curr = self.get_curr_scope_mut().unwrap();
         curr.name = name;
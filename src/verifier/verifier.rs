@@ -12,6 +12,10 @@ use crate::verifier::types::{Record, SymbolTable};
 const UNEXPECTED_ERR_MSG: &str =
     "TypeChecker: Looks like you've found a bug...please report this behavior! Exiting now...";
 
+/// The globals provided by events whose value is only known at runtime (e.g. on top of the
+/// stack), these aren't emitted yet
+pub const RUNTIME_ONLY_GLOBALS: &[&str] = &["condition"];
+
 pub fn build_symbol_table(ast: &mut Whamm, err: &mut ErrorGen) -> SymbolTable {
     let mut visitor = SymbolTableBuilder {
         table: SymbolTable::new(),
@@ -495,7 +499,21 @@ impl<'b> WhammVisitor<'b, Option<DataType>> for TypeChecker<'_> {
                 // get type from symbol table
                 if let Some(id) = self.table.lookup(name) {
                     if let Some(rec) = self.table.get_record(id) {
-                        if let Record::Var { ty, .. } = rec {
+                        if let Record::Var {
+                            ty,
+                            is_comp_provided,
+                            ..
+                        } = rec
+                        {
+                            if *is_comp_provided && RUNTIME_ONLY_GLOBALS.contains(&name.as_str()) {
+                                self.err.type_check_error(
+                                    false,
+                                    format!(
+                                        "`{name}` is only known at runtime, it can't be used yet"
+                                    ),
+                                    &loc.clone().map(|l| l.line_col),
+                                );
+                            }
                             return Some(ty.clone());
                         } else {
                             // unexpected record type
@@ -599,6 +617,11 @@ impl<'b> WhammVisitor<'b, Option<DataType>> for TypeChecker<'_> {
                             );
                             //continue to check for other errors even after emmitting this one
                         }
+                        if *is_comp_provided && fn_name == "print" {
+                            // `print` takes a format string followed by any number of arguments
+                            check_print_args(self.err, args.as_deref(), &actual_param_tys, loc);
+                            return Some(ret_ty.clone());
+                        }
                         //check if the
                         // look up param
                         let mut expected_param_tys = vec![];
@@ -760,6 +783,65 @@ impl<'b> WhammVisitor<'b, Option<DataType>> for TypeChecker<'_> {
     }
 }
 
+/// The first argument of `print` must be a literal format string, with a `{}` for
+/// each of the remaining arguments. Only primitive values can be printed.
+fn check_print_args(
+    err: &mut ErrorGen,
+    args: Option<&[Box<Expr>]>,
+    actual_param_tys: &[Option<DataType>],
+    loc: &Option<Location>,
+) {
+    let Some((fmt, fmt_args)) = args.and_then(|args| args.split_first()) else {
+        err.type_check_error(
+            false,
+            "`print` expects a format string as its first argument".to_owned(),
+            &loc.clone().map(|l| l.line_col),
+        );
+        return;
+    };
+    let Expr::Primitive {
+        val: Value::Str { val: fmt_str, .. },
+        ..
+    } = fmt.as_ref()
+    else {
+        err.type_check_error(
+            false,
+            "The format string of `print` must be a string literal".to_owned(),
+            &fmt.loc().clone().map(|l| l.line_col),
+        );
+        return;
+    };
+
+    let num_placeholders = fmt_str.matches("{}").count();
+    if num_placeholders != fmt_args.len() {
+        err.type_check_error(
+            false,
+            format!(
+                "The format string of `print` has {} placeholder(s), but {} argument(s) were given",
+                num_placeholders,
+                fmt_args.len()
+            ),
+            &fmt.loc().clone().map(|l| l.line_col),
+        );
+    }
+    for (arg, ty) in fmt_args.iter().zip(actual_param_tys.iter().skip(1)) {
+        match ty {
+            Some(DataType::I32)
+            | Some(DataType::U32)
+            | Some(DataType::Boolean)
+            | Some(DataType::Str)
+            | Some(DataType::AssumeGood) => {}
+            _ => {
+                err.type_check_error(
+                    false,
+                    format! {"Cannot print a value of type {:?}", ty},
+                    &arg.loc().clone().map(|l| l.line_col),
+                );
+            }
+        }
+    }
+}
+
 pub fn type_check(ast: &Whamm, st: &mut SymbolTable, err: &mut ErrorGen) -> bool {
     let mut type_checker = TypeChecker {
         table: st,
@@ -0,0 +1,18 @@
+(module
+  (type (;0;) (func (param i32 i64)))
+  (type (;1;) (func))
+  (func $log (type 0) (param i32 i64))
+  (func $main (type 1)
+    i32.const 1
+    i64.const 10
+    call $log
+    block
+      i32.const 2
+      i64.const 20
+      call $log
+    end
+    i32.const 3
+    i64.const 30
+    call $log)
+  (memory (;0;) 1)
+  (export "main" (func $main)))
@@ -0,0 +1,10 @@
+(module
+  (type (;0;) (func (param i32) (result i32)))
+  (func (;0;) (type 0) (param i32) (result i32)
+    block
+      local.get 0
+      br_if 0
+      local.get 0
+      local.set 0
+    end
+    local.get 0))
@@ -0,0 +1,17 @@
+(module
+  (type (;0;) (func))
+  (func $alloc (type 0)
+    call $noop)
+  (func $handle_get (type 0)
+    call $noop)
+  (func $handle_put (type 0)
+    call $noop
+    call $noop)
+  (func $noop (type 0))
+  (func $main (type 0)
+    call $handle_get
+    call $handle_put
+    call $alloc)
+  (memory (;0;) 1)
+  (export "malloc" (func $alloc))
+  (export "main" (func $main)))
@@ -0,0 +1,24 @@
+(module
+    (type (;0;) (func))
+    (type (;1;) (func (param i32)))
+    (type (;2;) (func (param i32 i32) (result i32)))
+    (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (type 1)))
+
+    (func $add (type 2) (param i32 i32) (result i32)
+        local.get 0
+        local.get 1
+        i32.add
+    )
+
+    (func $_start (type 0)
+        i32.const 1
+        i32.const 2
+        call $add
+        drop
+        i32.const 0
+        call $proc_exit
+    )
+
+    (export "_start" (func $_start))
+    (memory (export "memory") 17)
+)
@@ -1,16 +1,30 @@
 mod common;
 
+use gimli::write::{Address, DwarfUnit, EndianVec, LineProgram, LineString, Sections};
+use gimli::{Encoding, EndianSlice, Format, LineEncoding, LittleEndian};
 use log::error;
 use std::fs;
 use std::path::Path;
 use std::process::{Command, Stdio};
 use wabt::{wasm2wat, wat2wasm};
-use walrus::Module;
+use walrus::{ActiveData, ActiveDataLocation, DataKind, ExportItem, RawCustomSection};
+use walrus::{FunctionBuilder, Module, ModuleConfig, ValType};
 use whamm::behavior::builder_visitor::{build_behavior_tree, SimpleAST};
-use whamm::common::error::ErrorGen;
-use whamm::generator::emitters::{Emitter, WasmRewritingEmitter};
+use whamm::common::error::{ErrorGen, WhammError};
+use whamm::generator::debug_info::has_debug_info;
+use whamm::generator::diff::{diff, Change};
+use whamm::generator::emitters::{
+    hash_script, is_instrumented, Emitter, InstrMemory, MonitorModuleEmitter, PrintBackend,
+    VirgilEmitter, WasiEmitter, WasmRewritingEmitter, WHAMM_SECTION,
+};
 use whamm::generator::init_generator::InitGenerator;
 use whamm::generator::instr_generator::InstrGenerator;
+use whamm::generator::source_map::{SourceMap, SOURCE_MAP_SECTION};
+use whamm::generator::stats::stats;
+use whamm::generator::validation::validate;
+use whamm::parser::whamm_parser::parse_script;
+use whamm::verifier::types::SymbolTable;
+use whamm::verifier::verifier::build_symbol_table;
 
 const APP_WASM_PATH: &str = "tests/apps/dfinity/users.wasm";
 
@@ -64,6 +78,8 @@ fn instrument_dfinity_with_fault_injection() {
             curr_event_name: "".to_string(),
             curr_probe_mode: "".to_string(),
             curr_probe: None,
+            matches: None,
+            match_only: false,
             err: &mut err,
         };
         // TODO add assertions here once I have error logic in place to check that it worked!
@@ -129,6 +145,327 @@ fn instrument_handwritten_wasm_call() {
     println!("{}", wat_data);
 }
 
+/// This test confirms that an app in text form can be instrumented, and that the
+/// instrumented app can be output in text form.
+#[test]
+fn instrument_handwritten_wat() {
+    common::setup_logger();
+    let executable = "target/debug/whamm";
+    let out_dir = format!("{OUT_BASE_DIR}/emit_wat");
+    let _ = fs::remove_dir_all(&out_dir);
+
+    let instr = |emit: &str, out_name: &str| {
+        Command::new(executable)
+            .arg("instr")
+            .arg("--script")
+            .arg("tests/scripts/instr.mm")
+            .arg("--app")
+            .arg("tests/apps/handwritten/add.wat")
+            .arg("--emit")
+            .arg(emit)
+            .arg("--output-path")
+            .arg(format!("{out_dir}/{out_name}"))
+            .output()
+            .expect("failed to execute process")
+    };
+
+    let res = instr("both", "both.wasm");
+    assert!(res.status.success());
+    assert!(Module::from_file(format!("{out_dir}/both.wasm")).is_ok());
+    let wat_data = fs::read_to_string(format!("{out_dir}/both.wat")).unwrap();
+    assert!(wat2wasm(wat_data).is_ok());
+
+    // only the text
+    let res = instr("wat", "text.wasm");
+    assert!(res.status.success());
+    assert!(Path::new(&format!("{out_dir}/text.wat")).exists());
+    assert!(!Path::new(&format!("{out_dir}/text.wasm")).exists());
+}
+
+/// Runs both phases of instrumentation with the emitter built from the script's symbol table.
+fn generate_with<E: Emitter>(script_text: &str, new_emitter: impl FnOnce(SymbolTable) -> E) -> E {
+    let mut err = ErrorGen::new("".to_string(), script_text.to_string(), 0);
+    let mut whamm = parse_script(&script_text.to_string(), &mut err).unwrap();
+    let symbol_table = build_symbol_table(&mut whamm, &mut err);
+    err.fatal_report("Integration Test");
+    let mut emitter = new_emitter(symbol_table);
+
+    let mut simple_ast = SimpleAST::new();
+    let mut behavior = build_behavior_tree(&whamm, &mut simple_ast, &mut err);
+    behavior.reset();
+
+    let mut init = InitGenerator {
+        emitter: Box::new(&mut emitter),
+        context_name: "".to_string(),
+        err: &mut err,
+    };
+    assert!(init.run(&whamm));
+    err.fatal_report("Integration Test");
+
+    let mut instr = InstrGenerator {
+        tree: &behavior,
+        emitter: Box::new(&mut emitter),
+        ast: simple_ast,
+        context_name: "".to_string(),
+        curr_provider_name: "".to_string(),
+        curr_package_name: "".to_string(),
+        curr_event_name: "".to_string(),
+        curr_probe_mode: "".to_string(),
+        curr_probe: None,
+        matches: None,
+        match_only: false,
+        err: &mut err,
+    };
+    instr.run(&behavior);
+    err.fatal_report("Integration Test");
+
+    emitter
+}
+
+/// Same as `generate_with`, then dumps the result to `out_path`.
+fn instrument_with<E: Emitter>(
+    script_text: &str,
+    new_emitter: impl FnOnce(SymbolTable) -> E,
+    out_path: &str,
+) -> Result<bool, Box<WhammError>> {
+    generate_with(script_text, new_emitter).dump_to_file(out_path.to_string())
+}
+
+/// This test confirms that the Virgil source of a Wizard monitor is generated for
+/// the handwritten app, leaving the app itself untouched.
+#[test]
+fn instrument_handwritten_wasm_virgil() {
+    common::setup_logger();
+    let script_text = fs::read_to_string("tests/scripts/instr.mm").unwrap();
+    let app_bytes = fs::read("tests/apps/handwritten/basic.wasm").unwrap();
+    let app_wasm = Module::from_buffer(&app_bytes).unwrap();
+    let emitter = generate_with(&script_text, |table| {
+        VirgilEmitter::new(app_wasm, &app_bytes, table)
+    });
+
+    let monitor = emitter.to_virgil();
+    assert!(monitor.contains("class WhammMonitor extends Monitor"));
+    assert!(monitor.contains("var i: int;"));
+    // every call in the app is probed, all share the same probe body
+    assert_eq!(
+        monitor.matches("Instrumentation.insertLocalProbe").count(),
+        3
+    );
+    assert_eq!(monitor.matches("extends Probe").count(), 1);
+    assert!(monitor.contains("i = 10;"));
+}
+
+/// This test confirms that the arguments of an instruction are bound in the Virgil monitor from
+/// the operand stack, and that an `after` probe can't use them.
+#[test]
+fn instrument_handwritten_wasm_virgil_args() {
+    common::setup_logger();
+    let app_bytes = fs::read("tests/apps/handwritten/basic.wasm").unwrap();
+    let script_text = r#"
+        i32 i;
+        wasm:bytecode:call:before / target_imp_name == "debug_print" / {
+            i = arg0 + 2;
+        }
+    "#;
+    let app_wasm = Module::from_buffer(&app_bytes).unwrap();
+    let monitor = generate_with(script_text, |table| {
+        VirgilEmitter::new(app_wasm, &app_bytes, table)
+    })
+    .to_virgil();
+    // `debug_print` takes two arguments, the first is below the second on the stack
+    assert!(monitor.contains("var arg0 = Values.unbox_i(accessor.getOperand(1));"));
+    assert!(!monitor.contains("var arg1"));
+
+    let script_text = script_text.replace("call:before", "call:after");
+    let result = std::panic::catch_unwind(|| {
+        let app_wasm = Module::from_buffer(&app_bytes).unwrap();
+        generate_with(&script_text, |table| {
+            VirgilEmitter::new(app_wasm, &app_bytes, table)
+        })
+    });
+    assert!(
+        result.is_err(),
+        "Expected `arg0` of an `after` probe to be rejected"
+    );
+}
+
+/// This test confirms that a valid monitor module, and a manifest of where to attach its
+/// probe callbacks, is generated for the handwritten app.
+#[test]
+fn instrument_handwritten_wasm_monitor_module() {
+    common::setup_logger();
+    let script_text = r#"
+        i32 count;
+        wasm:bytecode:call:before {
+            count = count + 1;
+        }
+        wasm:bytecode:call:after / target_imp_name == "debug_print" / {
+            i32 i;
+            i = arg0 + 2;
+        }
+    "#;
+    let app_bytes = fs::read("tests/apps/handwritten/basic.wasm").unwrap();
+    let app_wasm = Module::from_buffer(&app_bytes).unwrap();
+    let mut emitter = generate_with(script_text, |table| {
+        MonitorModuleEmitter::new(app_wasm, &app_bytes, table)
+    });
+
+    // a `before` probe at every call, an `after` probe at both calls to `debug_print`
+    let manifest = emitter.manifest();
+    assert_eq!(manifest.matches("\"mode\": \"before\"").count(), 3);
+    assert_eq!(manifest.matches("\"mode\": \"after\"").count(), 2);
+    // the offsets are from the start of the app binary, where the calls are
+    let probes: serde_json::Value = serde_json::from_str(&manifest).unwrap();
+    assert_eq!(
+        probes["probes"][0],
+        serde_json::json!({ "func_index": 2, "offset": 114, "mode": "before", "callback": "probe_0" })
+    );
+    for probe in probes["probes"].as_array().unwrap() {
+        if probe["mode"] == "before" {
+            let offset = probe["offset"].as_u64().unwrap() as usize;
+            assert_eq!(app_bytes[offset], 0x10);
+        }
+    }
+
+    // the monitor must be valid and export every callback in the manifest
+    let monitor_bytes = emitter.monitor_wasm().emit_wasm();
+    let monitor = Module::from_buffer(&monitor_bytes).unwrap();
+    for i in 0..5 {
+        assert!(monitor.exports.get_func(format!("probe_{i}")).is_ok());
+    }
+}
+
+/// This test confirms that the script's output is wired to WASI for an app that imports WASI:
+/// the `fd_write` import is added, `print` uses it and the report runs when the app finishes.
+#[test]
+fn instrument_handwritten_wasm_wasi() {
+    common::setup_logger();
+    let script_text = r#"
+        i32 count;
+        wasm:bytecode:call:before {
+            count = count + 1;
+            print("call to {} with {}", target_fn_type, arg0);
+        }
+    "#;
+    let app_bytes = wat2wasm(fs::read("tests/apps/handwritten/wasi.wat").unwrap()).unwrap();
+    let app_wasm = Module::from_buffer(&app_bytes).unwrap();
+    let out_path = format!("{OUT_BASE_DIR}/wasi_{OUT_WASM_NAME}");
+    let res = instrument_with(
+        script_text,
+        |table| WasiEmitter::new(app_wasm, table).ok().unwrap(),
+        &out_path,
+    );
+    assert!(matches!(res, Ok(true)));
+
+    let out = Module::from_file(&out_path).unwrap();
+    assert!(out
+        .imports
+        .get_func("wasi_snapshot_preview1", "fd_write")
+        .is_ok());
+    // the report runs after `_start` returns...
+    let start = out.funcs.get(out.exports.get_func("_start").unwrap());
+    assert_eq!(start.name.as_deref(), Some("whamm$start"));
+    // ...and before the app exits
+    let wat = wasm2wat(fs::read(&out_path).unwrap()).unwrap();
+    assert_eq!(wat.matches("call $whamm$proc_exit").count(), 1);
+    assert!(wat.contains("count: "));
+    // the constant parts of each `print` are folded together
+    assert!(wat.contains("call to local with "));
+    assert!(wat.contains("call to import with "));
+    assert_eq!(wat.matches("call $whamm$print_i32").count(), 3);
+}
+
+const PRINT_SCRIPT: &str = r#"
+    wasm:bytecode:call:before {
+        print("calling {}", target_fn_type);
+    }
+"#;
+
+#[test]
+fn instrument_handwritten_wasm_dedicated_memory() {
+    common::setup_logger();
+    let app_bytes = wat2wasm(fs::read("tests/apps/handwritten/add.wat").unwrap()).unwrap();
+    let app_wasm = Module::from_buffer(&app_bytes).unwrap();
+    let out_path = format!("{OUT_BASE_DIR}/dedicated_memory_{OUT_WASM_NAME}");
+    let res = instrument_with(
+        PRINT_SCRIPT,
+        |table| {
+            let Ok(mut emitter) =
+                WasmRewritingEmitter::new_with_memory(app_wasm, table, InstrMemory::Dedicated)
+            else {
+                panic!("could not add a dedicated memory");
+            };
+            emitter.print_backend = PrintBackend::Buffer;
+            emitter
+        },
+        &out_path,
+    );
+    assert!(matches!(res, Ok(true)));
+
+    let out = Module::from_file(&out_path).unwrap();
+    let memories: Vec<_> = out.memories.iter().map(|memory| memory.id()).collect();
+    assert_eq!(memories.len(), 2);
+    let instr_mem = memories[1];
+    assert!(out
+        .exports
+        .iter()
+        .any(|export| export.name == "whamm_memory"
+            && matches!(export.item, ExportItem::Memory(id) if id == instr_mem)));
+    // the app's memory is left untouched
+    assert!(out.data.iter().all(|data| matches!(
+        data.kind,
+        DataKind::Active(ActiveData { memory, .. }) if memory == instr_mem
+    )));
+    assert!(out
+        .data
+        .iter()
+        .any(|data| data.value.starts_with(b"calling local")));
+}
+
+#[test]
+fn instrument_handwritten_wasm_no_memory() {
+    common::setup_logger();
+    let app_bytes = wat2wasm(
+        r#"(module
+            (func $callee)
+            (func $main call $callee)
+            (export "main" (func $main)))"#,
+    )
+    .unwrap();
+    let app_wasm = Module::from_buffer(&app_bytes).unwrap();
+    let out_path = format!("{OUT_BASE_DIR}/no_memory_{OUT_WASM_NAME}");
+    let res = instrument_with(
+        PRINT_SCRIPT,
+        |table| WasmRewritingEmitter::new(app_wasm, table),
+        &out_path,
+    );
+    assert!(matches!(res, Ok(true)));
+
+    let out = Module::from_file(&out_path).unwrap();
+    assert_eq!(out.memories.iter().count(), 1);
+    assert!(out
+        .exports
+        .iter()
+        .any(|export| export.name == "whamm_memory"));
+    assert!(out
+        .data
+        .iter()
+        .any(|data| data.value.starts_with(b"calling local")));
+}
+
+#[test]
+fn instrument_handwritten_wasm_missing_memory() {
+    common::setup_logger();
+    let app_bytes = wat2wasm(fs::read("tests/apps/handwritten/add.wat").unwrap()).unwrap();
+    let app_wasm = Module::from_buffer(&app_bytes).unwrap();
+    assert!(WasmRewritingEmitter::new_with_memory(
+        app_wasm,
+        SymbolTable::new(),
+        InstrMemory::App(1)
+    )
+    .is_err());
+}
+
 #[test]
 fn instrument_control_flow() {
     common::setup_logger();
@@ -185,3 +522,1080 @@ fn instrument_with_replay() {
     // TODO -- change this when you've supported this monitor type
     assert_eq!(processed_scripts.len(), 0);
 }
+
+#[test]
+fn instrument_handwritten_wasm_data_placement() {
+    common::setup_logger();
+    let app_bytes = wat2wasm(
+        r#"(module
+            (func $callee)
+            (func $main call $callee)
+            (memory 1)
+            (global (export "__heap_base") i32 (i32.const 70000))
+            (data (i32.const 16) "app data")
+            (export "main" (func $main)))"#,
+    )
+    .unwrap();
+    let app_wasm = Module::from_buffer(&app_bytes).unwrap();
+    let out_path = format!("{OUT_BASE_DIR}/data_placement_{OUT_WASM_NAME}");
+    let res = instrument_with(
+        PRINT_SCRIPT,
+        |table| WasmRewritingEmitter::new(app_wasm, table),
+        &out_path,
+    );
+    assert!(matches!(res, Ok(true)));
+
+    let mut out = Module::from_file(&out_path).unwrap();
+    for data in out.data.iter() {
+        if data.value == b"app data" {
+            assert!(matches!(
+                data.kind,
+                DataKind::Active(ActiveData {
+                    location: ActiveDataLocation::Absolute(16),
+                    ..
+                })
+            ));
+        } else {
+            // the instrumentation's data is copied to memory at start...
+            assert!(matches!(data.kind, DataKind::Passive));
+        }
+    }
+    // ...once the memory is grown, it isn't part of the memory the app starts with
+    assert_eq!(out.memories.iter().next().unwrap().initial, 1);
+    let start = out.funcs.get(out.start.unwrap());
+    assert_eq!(start.name.as_deref(), Some("whamm$reserve_memory"));
+    let wat = wasm2wat(fs::read(&out_path).unwrap()).unwrap();
+    assert!(wat.contains("memory.grow"));
+    assert!(wat.contains("memory.init"));
+    // the instrumentation's data is placed past `__heap_base`
+    let section = out.customs.remove_raw(WHAMM_SECTION).unwrap();
+    let metadata: serde_json::Value = serde_json::from_slice(&section.data).unwrap();
+    assert_eq!(metadata["memory"]["index"], 0);
+    assert!(metadata["memory"]["start"].as_u64().unwrap() >= 70000);
+}
+
+#[test]
+fn instrument_handwritten_wasm_imported_memory() {
+    common::setup_logger();
+    let app_bytes = wat2wasm(
+        r#"(module
+            (import "env" "memory" (memory 1))
+            (func $callee)
+            (func $main call $callee)
+            (export "main" (func $main)))"#,
+    )
+    .unwrap();
+    let app_wasm = Module::from_buffer(&app_bytes).unwrap();
+    let out_path = format!("{OUT_BASE_DIR}/imported_memory_{OUT_WASM_NAME}");
+    // there is no room for the instrumentation's data in a memory provided by the host
+    let res = instrument_with(
+        PRINT_SCRIPT,
+        |table| WasmRewritingEmitter::new(app_wasm, table),
+        &out_path,
+    );
+    assert!(res.is_err());
+}
+
+#[test]
+fn instrument_handwritten_wasm_metadata_section() {
+    common::setup_logger();
+    let app_bytes = wat2wasm(fs::read("tests/apps/handwritten/add.wat").unwrap()).unwrap();
+    let app_wasm = Module::from_buffer(&app_bytes).unwrap();
+    assert!(!is_instrumented(&app_wasm));
+    let out_path = format!("{OUT_BASE_DIR}/metadata_section_{OUT_WASM_NAME}");
+    let res = instrument_with(
+        PRINT_SCRIPT,
+        |table| {
+            let mut emitter = WasmRewritingEmitter::new(app_wasm, table);
+            emitter.script_hash = Some(hash_script(PRINT_SCRIPT));
+            emitter
+        },
+        &out_path,
+    );
+    assert!(matches!(res, Ok(true)));
+
+    let mut out = Module::from_file(&out_path).unwrap();
+    assert!(is_instrumented(&out));
+    let section = out.customs.remove_raw(WHAMM_SECTION).unwrap();
+    // the injected items still mark the app as instrumented
+    assert!(is_instrumented(&out));
+    let metadata: serde_json::Value = serde_json::from_slice(&section.data).unwrap();
+    assert_eq!(
+        metadata["scripts"],
+        serde_json::json!([hash_script(PRINT_SCRIPT)])
+    );
+    // one site per call in function 1
+    let sites = metadata["sites"].as_array().unwrap();
+    assert_eq!(sites.len(), 3);
+    for site in sites {
+        assert_eq!(site["layer"], 0);
+        assert_eq!(site["func_index"], 1);
+        assert_eq!(site["probe"], "wasm:bytecode:call");
+        assert_eq!(site["mode"], "before");
+    }
+    // the injected `whamm::print` import is the first function
+    assert_eq!(metadata["injected"]["funcs"][0], 0);
+    // both functions of the app, moved past the injected import
+    let app_funcs: Vec<(u32, u32)> = serde_json::from_value(metadata["app_funcs"].clone()).unwrap();
+    assert_eq!(app_funcs.len(), 2);
+    assert!(app_funcs.iter().all(|(idx, _)| *idx != 0));
+    let mut app_idxs: Vec<u32> = app_funcs.iter().map(|(_, app_idx)| *app_idx).collect();
+    app_idxs.sort();
+    assert_eq!(app_idxs, vec![0, 1]);
+}
+
+#[test]
+fn instrument_handwritten_wasm_layered() {
+    common::setup_logger();
+    let app_bytes = wat2wasm(fs::read("tests/apps/handwritten/add.wat").unwrap()).unwrap();
+    // as `whamm instr` does, for the source map to tell what the first instrumentation injected
+    let app_wasm = ModuleConfig::new()
+        .preserve_code_transform(true)
+        .parse(&app_bytes)
+        .unwrap();
+    let first_path = format!("{OUT_BASE_DIR}/layer_first_{OUT_WASM_NAME}");
+    let res = instrument_with(
+        PRINT_SCRIPT,
+        |table| {
+            let mut emitter = WasmRewritingEmitter::new(app_wasm, table);
+            emitter.script_hash = Some(hash_script(PRINT_SCRIPT));
+            emitter
+        },
+        &first_path,
+    );
+    assert!(matches!(res, Ok(true)));
+
+    // an instrumented app is only instrumented again with --layer
+    let out_path = format!("{OUT_BASE_DIR}/layer_{OUT_WASM_NAME}");
+    let whamm = |script: &str, app_path: &str, out_path: &str, args: &[&str]| {
+        Command::new("target/debug/whamm")
+            .arg("instr")
+            .arg("--script")
+            .arg(script)
+            .arg("--app")
+            .arg(app_path)
+            .arg("--output-path")
+            .arg(out_path)
+            .args(args)
+            .output()
+            .expect("failed to execute process")
+    };
+    let script = "tests/scripts/instr.mm";
+    assert!(!whamm(script, &first_path, &out_path, &[]).status.success());
+    assert!(whamm(script, &first_path, &out_path, &["--layer"])
+        .status
+        .success());
+
+    let mut first = Module::from_file(&first_path).unwrap();
+    let mut out = Module::from_file(&out_path).unwrap();
+    let section = |module: &mut Module| -> serde_json::Value {
+        let section = module.customs.remove_raw(WHAMM_SECTION).unwrap();
+        serde_json::from_slice(&section.data).unwrap()
+    };
+    let (first, out) = (section(&mut first), section(&mut out));
+    // only the calls of the app are instrumented again, not those of the first instrumentation
+    let app_func = first["app_funcs"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|funcs| funcs[1] == 1)
+        .unwrap()[0]
+        .clone();
+    let layer_sites = |metadata: &serde_json::Value, layer: usize| -> Vec<serde_json::Value> {
+        let sites = metadata["sites"].as_array().unwrap();
+        sites
+            .iter()
+            .filter(|site| site["layer"] == layer)
+            .cloned()
+            .collect()
+    };
+    let sites = layer_sites(&out, 1);
+    assert_eq!(sites.len(), 3);
+    assert!(sites.iter().all(|site| site["func_index"] == app_func));
+    // the sites and scripts of the first instrumentation are kept
+    assert_eq!(layer_sites(&out, 0), layer_sites(&first, 0));
+    let scripts = [PRINT_SCRIPT, &fs::read_to_string(script).unwrap()].map(hash_script);
+    assert_eq!(out["scripts"], serde_json::json!(scripts));
+    // both instrumentations are injected, the second one adds the global `i` and reuses the
+    // functions of the first one
+    let num_injected = |metadata: &serde_json::Value, kind: &str| {
+        metadata["injected"][kind].as_array().unwrap().len()
+    };
+    assert!(num_injected(&out, "globals") > num_injected(&first, "globals"));
+    assert_eq!(num_injected(&out, "funcs"), num_injected(&first, "funcs"));
+
+    // the source map of the second layer still covers the code of the first one
+    let ranges = SourceMap::ranges(&Module::from_file(&out_path).unwrap()).unwrap();
+    assert!(ranges.iter().any(|range| range.script.is_none()));
+    assert!(ranges
+        .iter()
+        .any(|range| range.script.as_deref() == Some(script)));
+
+    // so a third layer instruments neither: the first one injected calls, the second one
+    // `global.set`s
+    let third_script = format!("{OUT_BASE_DIR}/layer_third.mm");
+    fs::write(
+        &third_script,
+        r#"
+i32 count;
+wasm:bytecode:call:before {
+    count = count + 1;
+}
+wasm:bytecode:global_set:before {
+    count = count + 1;
+    print("count: {}", count);
+}
+"#,
+    )
+    .unwrap();
+    let third_path = format!("{OUT_BASE_DIR}/layer_third_{OUT_WASM_NAME}");
+    assert!(whamm(&third_script, &out_path, &third_path, &["--layer"])
+        .status
+        .success());
+    let mut third_wasm = Module::from_file(&third_path).unwrap();
+    let third = section(&mut third_wasm);
+    assert_eq!(third["sites"].as_array().unwrap().len(), 9);
+    let sites = layer_sites(&third, 2);
+    // the calls of the app
+    assert_eq!(sites.len(), 3);
+    assert!(sites.iter().all(|site| {
+        let (func_idx, offset) = (site["func_index"].as_u64(), site["offset"].as_u64());
+        !ranges.iter().any(|range| {
+            func_idx == Some(range.func_idx as u64)
+                && (range.start..range.end).contains(&(offset.unwrap() as usize))
+        })
+    }));
+    // the helpers injected by the first layer are reused
+    for helper in ["whamm$strcmp", "whamm$print_i32"] {
+        let count = third_wasm
+            .funcs
+            .iter()
+            .filter(|func| func.name.as_deref() == Some(helper))
+            .count();
+        assert_eq!(count, 1, "{helper}");
+    }
+}
+
+#[test]
+fn instrument_handwritten_wasm_source_map() {
+    common::setup_logger();
+    let app_bytes = wat2wasm(fs::read("tests/apps/handwritten/add.wat").unwrap()).unwrap();
+    let app_wasm = ModuleConfig::new()
+        .preserve_code_transform(true)
+        .parse(&app_bytes)
+        .unwrap();
+    let out_path = format!("{OUT_BASE_DIR}/source_map_{OUT_WASM_NAME}");
+    let res = instrument_with(
+        PRINT_SCRIPT,
+        |table| {
+            let mut emitter = WasmRewritingEmitter::new(app_wasm, table);
+            emitter.source_map.script_path = Some("print.mm".to_string());
+            emitter
+        },
+        &out_path,
+    );
+    assert!(matches!(res, Ok(true)));
+
+    let out = Module::from_file(&out_path).unwrap();
+    let section = out
+        .customs
+        .iter()
+        .filter_map(|(_, section)| section.as_any().downcast_ref::<RawCustomSection>())
+        .find(|section| section.name == SOURCE_MAP_SECTION);
+    let source_map: serde_json::Value = serde_json::from_slice(&section.unwrap().data).unwrap();
+    assert_eq!(source_map["scripts"], serde_json::json!(["print.mm"]));
+    let ranges = source_map["ranges"].as_array().unwrap();
+    assert!(!ranges.is_empty());
+    // everything was injected for the `print` statement
+    assert!(ranges
+        .iter()
+        .all(|range| range["span"] == serde_json::json!([3, 9, 3, 43])));
+
+    let func_idx = ranges[0]["func_index"].as_u64().unwrap() as u32;
+    let end = ranges[0]["end"].as_u64().unwrap() as usize;
+    let range = SourceMap::lookup(&out, func_idx, end - 1).unwrap().unwrap();
+    assert_eq!(range.script.as_deref(), Some("print.mm"));
+    // the `print` statement
+    assert_eq!(range.span.start, (3, 9));
+    // the app's code isn't mapped
+    let range = SourceMap::lookup(&out, func_idx, 0).unwrap();
+    assert!(range.is_none());
+}
+
+#[test]
+fn instrument_handwritten_wasm_names() {
+    common::setup_logger();
+    let app_bytes = wat2wasm(
+        r#"(module
+            (func $callee)
+            (func $main call $callee call $callee)
+            (memory 1)
+            (global (mut i32) (i32.const 0))
+            (data (i32.const 16) "app data")
+            (export "main" (func $main)))"#,
+    )
+    .unwrap();
+    let mut app_wasm = Module::from_buffer(&app_bytes).unwrap();
+    // names that `walrus` doesn't emit by itself
+    let global = app_wasm.globals.iter().next().unwrap().id();
+    app_wasm.globals.get_mut(global).name = Some("app_global".to_string());
+    let data = app_wasm.data.iter().next().unwrap().id();
+    app_wasm.data.get_mut(data).name = Some("app_data".to_string());
+
+    let script = r#"
+        i32 count;
+        wasm:bytecode:call:before {
+            count = count + 1;
+            print("calling {}", target_fn_type);
+        }
+    "#;
+    let out_path = format!("{OUT_BASE_DIR}/names_{OUT_WASM_NAME}");
+    let res = instrument_with(
+        script,
+        |table| WasmRewritingEmitter::new(app_wasm, table),
+        &out_path,
+    );
+    assert!(matches!(res, Ok(true)));
+
+    let out = Module::from_file(&out_path).unwrap();
+    let global_names: Vec<&str> = out
+        .globals
+        .iter()
+        .filter_map(|g| g.name.as_deref())
+        .collect();
+    assert!(global_names.contains(&"app_global"));
+    assert!(global_names.contains(&"whamm$script0$count"));
+    // `walrus` loses the names of data segments and locals when parsing
+    let wat = wasm2wat(fs::read(&out_path).unwrap()).unwrap();
+    assert!(wat.contains("(data $app_data"));
+    assert!(wat.contains("(data $whamm$print_text"));
+    // both calls print the same text, it's only added to memory once
+    assert_eq!(wat.matches("calling local").count(), 1);
+    let func_names: Vec<&str> = out.funcs.iter().filter_map(|f| f.name.as_deref()).collect();
+    assert!(func_names.contains(&"main"));
+    assert!(func_names.contains(&"whamm$print"));
+    assert!(func_names.contains(&"whamm$print_i32"));
+    assert!(wat.contains("(param $whamm$val i32)"));
+}
+
+/// Adds DWARF debug info with a line table mapping each (address, line)
+fn with_line_table(mut wasm: Vec<u8>, rows: &[(u64, u64)], end: u64) -> Vec<u8> {
+    let encoding = Encoding {
+        format: Format::Dwarf32,
+        version: 4,
+        address_size: 4,
+    };
+    let mut dwarf = DwarfUnit::new(encoding);
+    let mut program = LineProgram::new(
+        encoding,
+        LineEncoding::default(),
+        LineString::String(b"/app".to_vec()),
+        LineString::String(b"app.c".to_vec()),
+        None,
+    );
+    let dir = program.default_directory();
+    let file = program.add_file(LineString::String(b"app.c".to_vec()), dir, None);
+    let start = rows[0].0;
+    program.begin_sequence(Some(Address::Constant(start)));
+    for (addr, line) in rows {
+        program.row().address_offset = addr - start;
+        program.row().file = file;
+        program.row().line = *line;
+        program.generate_row();
+    }
+    program.end_sequence(end - start);
+    dwarf.unit.line_program = program;
+
+    let mut sections = Sections::new(EndianVec::new(LittleEndian));
+    dwarf.write(&mut sections).unwrap();
+    sections
+        .for_each(|id, data| {
+            // a custom section
+            wasm.push(0);
+            wasm_encoder::Encode::encode(
+                &wasm_encoder::CustomSection {
+                    name: id.name().into(),
+                    data: data.slice().into(),
+                },
+                &mut wasm,
+            );
+            Ok::<(), ()>(())
+        })
+        .unwrap();
+    wasm
+}
+
+/// The (address, line) rows of the line table of the module's DWARF debug info
+fn line_rows(module: &Module) -> Vec<(u64, u64)> {
+    let dwarf = module
+        .debug
+        .dwarf
+        .borrow(|section| EndianSlice::new(section, LittleEndian));
+    let header = dwarf.units().next().unwrap().unwrap();
+    let unit = dwarf.unit(header).unwrap();
+    let mut rows = unit.line_program.unwrap().rows();
+    let mut res = vec![];
+    while let Some((_, row)) = rows.next_row().unwrap() {
+        if !row.end_sequence() {
+            res.push((row.address(), row.line().unwrap().get()));
+        }
+    }
+    res
+}
+
+/// The addresses of the instructions of the exported `main` function
+fn main_instr_addrs(module: &Module) -> Vec<u64> {
+    let main = module.exports.get_func("main").unwrap();
+    module
+        .funcs
+        .get(main)
+        .kind
+        .unwrap_local()
+        .instruction_mapping
+        .iter()
+        .map(|(addr, _)| *addr as u64)
+        .collect()
+}
+
+#[test]
+fn instrument_handwritten_wasm_debug_info() {
+    common::setup_logger();
+    let app_bytes = wat2wasm(
+        r#"(module
+            (func $callee)
+            (func $main call $callee call $callee)
+            (memory 1)
+            (export "main" (func $main)))"#,
+    )
+    .unwrap();
+    // one line per instruction of `main`: call, call, end
+    let addrs = main_instr_addrs(&Module::from_buffer(&app_bytes).unwrap());
+    let rows: Vec<(u64, u64)> = addrs.iter().copied().zip(10..).collect();
+    let app_bytes = with_line_table(app_bytes, &rows, addrs.last().unwrap() + 1);
+    assert!(has_debug_info(&app_bytes));
+
+    // preserved
+    let app_wasm = ModuleConfig::new()
+        .generate_dwarf(true)
+        .parse(&app_bytes)
+        .unwrap();
+    let out_path = format!("{OUT_BASE_DIR}/preserve_debug_{OUT_WASM_NAME}");
+    let res = instrument_with(
+        PRINT_SCRIPT,
+        |table| WasmRewritingEmitter::new(app_wasm, table),
+        &out_path,
+    );
+    assert!(matches!(res, Ok(true)));
+    let out = Module::from_file(&out_path).unwrap();
+    let out_rows = line_rows(&out);
+    let out_addrs = main_instr_addrs(&out);
+    // the same lines, at the new addresses of the original instructions
+    assert_eq!(
+        out_rows.iter().map(|(_, line)| *line).collect::<Vec<_>>(),
+        vec![10, 11, 12]
+    );
+    assert!(out_rows.iter().all(|(addr, _)| out_addrs.contains(addr)));
+    // code was injected before the first call
+    assert!(out_rows[0].0 > rows[0].0);
+
+    // stripped
+    let app_wasm = Module::from_buffer(&app_bytes).unwrap();
+    let out_path = format!("{OUT_BASE_DIR}/strip_debug_{OUT_WASM_NAME}");
+    let res = instrument_with(
+        PRINT_SCRIPT,
+        |table| WasmRewritingEmitter::new(app_wasm, table),
+        &out_path,
+    );
+    assert!(matches!(res, Ok(true)));
+    assert!(!has_debug_info(&fs::read(&out_path).unwrap()));
+}
+
+#[test]
+fn instrument_handwritten_wasm_validation() {
+    common::setup_logger();
+    let app_bytes = wat2wasm(fs::read("tests/apps/handwritten/add.wat").unwrap()).unwrap();
+    let app_wasm = ModuleConfig::new()
+        .preserve_code_transform(true)
+        .parse(&app_bytes)
+        .unwrap();
+    let out_path = format!("{OUT_BASE_DIR}/validation_{OUT_WASM_NAME}");
+    let res = instrument_with(
+        PRINT_SCRIPT,
+        |table| WasmRewritingEmitter::new(app_wasm, table),
+        &out_path,
+    );
+    assert!(matches!(res, Ok(true)));
+    let mut out_bytes = fs::read(&out_path).unwrap();
+    assert!(validate(&out_bytes).is_ok());
+
+    // break the first injected instruction (with a `drop` of nothing)
+    let out = Module::from_buffer(&out_bytes).unwrap();
+    let section = out
+        .customs
+        .iter()
+        .filter_map(|(_, section)| section.as_any().downcast_ref::<RawCustomSection>())
+        .find(|section| section.name == SOURCE_MAP_SECTION);
+    let source_map: serde_json::Value = serde_json::from_slice(&section.unwrap().data).unwrap();
+    let range = &source_map["ranges"][0];
+    let func_idx = range["func_index"].as_u64().unwrap() as u32;
+    let start = range["start"].as_u64().unwrap() as usize;
+    out_bytes[start] = 0x1a;
+
+    let invalid = validate(&out_bytes).unwrap_err();
+    assert_eq!(invalid.func_idx, Some(func_idx));
+    assert_eq!(invalid.offset, start);
+    // mapped back to the `print` statement
+    let range = SourceMap::lookup_binary(&out_bytes, func_idx, invalid.offset).unwrap();
+    assert_eq!(range.unwrap().span.start, (3, 9));
+
+    // an invalid app isn't written
+    let mut app_wasm = Module::from_buffer(&app_bytes).unwrap();
+    let mut broken = FunctionBuilder::new(&mut app_wasm.types, &[], &[ValType::I32]);
+    broken.name("broken".to_string());
+    broken.finish(vec![], &mut app_wasm.funcs);
+    let out_path = format!("{OUT_BASE_DIR}/invalid_{OUT_WASM_NAME}");
+    let _ = fs::remove_file(&out_path);
+    let res = instrument_with(
+        PRINT_SCRIPT,
+        |table| WasmRewritingEmitter::new(app_wasm, table),
+        &out_path,
+    );
+    assert!(res.is_err());
+    assert!(!Path::new(&out_path).exists());
+}
+
+#[test]
+fn instrument_handwritten_wasm_diff() {
+    common::setup_logger();
+    let app_bytes = wat2wasm(fs::read("tests/apps/handwritten/add.wat").unwrap()).unwrap();
+    let app_wasm = ModuleConfig::new()
+        .preserve_code_transform(true)
+        .parse(&app_bytes)
+        .unwrap();
+    let out_path = format!("{OUT_BASE_DIR}/diff_{OUT_WASM_NAME}");
+    let res = instrument_with(
+        PRINT_SCRIPT,
+        |table| WasmRewritingEmitter::new(app_wasm, table),
+        &out_path,
+    );
+    assert!(matches!(res, Ok(true)));
+
+    let diff = diff(&app_bytes, &fs::read(&out_path).unwrap()).unwrap();
+    assert!(diff
+        .injected_funcs
+        .iter()
+        .any(|func| func.name.as_deref() == Some("whamm$print")));
+    // only the function with the calls changed
+    assert_eq!(diff.changed_funcs.len(), 1);
+    let func = &diff.changed_funcs[0];
+    assert_eq!(func.app_func_idx, Some(1));
+    // the `print` was injected...
+    assert!(func
+        .instrs
+        .iter()
+        .any(|instr| instr.change == Change::Injected && instr.script_loc == Some((3, 9))));
+    assert!(func
+        .instrs
+        .iter()
+        .any(|instr| instr.change == Change::Injected
+            && instr.injected_ref.as_deref() == Some("whamm$print")));
+    // ...and the calls of the app are all there
+    let app_instrs: Vec<&str> = func
+        .instrs
+        .iter()
+        .filter(|instr| instr.change == Change::Same)
+        .map(|instr| instr.text.as_str())
+        .collect();
+    assert_eq!(app_instrs.len(), 4);
+    assert!(app_instrs[..3].iter().all(|text| text.starts_with("Call")));
+    assert!(!func
+        .instrs
+        .iter()
+        .any(|instr| instr.change == Change::Removed));
+}
+
+#[test]
+fn instrument_handwritten_wasm_dry_run() {
+    common::setup_logger();
+    let executable = "target/debug/whamm";
+    let out_path = format!("{OUT_BASE_DIR}/dry_run_{OUT_WASM_NAME}");
+    let _ = fs::remove_file(&out_path);
+
+    let res = Command::new(executable)
+        .arg("instr")
+        .arg("--script")
+        .arg("tests/scripts/instr.mm")
+        .arg("--app")
+        .arg("tests/apps/handwritten/add.wat")
+        .arg("--output-path")
+        .arg(&out_path)
+        .arg("--dry-run")
+        .arg("json")
+        .output()
+        .expect("failed to execute process");
+    assert!(res.status.success());
+
+    let matches: serde_json::Value = serde_json::from_slice(&res.stdout).unwrap();
+    let matches = matches.as_array().unwrap();
+    // the three calls in the app's function
+    assert_eq!(matches.len(), 3);
+    for site in matches {
+        assert_eq!(site["probe"], "wasm:bytecode:call");
+        assert_eq!(site["mode"], "before");
+        assert_eq!(site["func_index"], 1);
+        assert_eq!(site["instr"], "call");
+        assert_eq!(site["predicate"], "true");
+    }
+    // nothing was written
+    assert!(!Path::new(&out_path).exists());
+}
+
+#[test]
+fn instrument_handwritten_wasm_stats() {
+    common::setup_logger();
+    let executable = "target/debug/whamm";
+    let out_path = format!("{OUT_BASE_DIR}/stats_{OUT_WASM_NAME}");
+
+    let res = Command::new(executable)
+        .arg("instr")
+        .arg("--script")
+        .arg("tests/scripts/instr.mm")
+        .arg("--app")
+        .arg("tests/apps/handwritten/add.wat")
+        .arg("--output-path")
+        .arg(&out_path)
+        .arg("--stats")
+        .arg("json")
+        .output()
+        .expect("failed to execute process");
+    assert!(res.status.success());
+    assert!(Path::new(&out_path).exists());
+
+    let stats: serde_json::Value = serde_json::from_slice(&res.stdout).unwrap();
+    let probes = stats["probes"].as_array().unwrap();
+    assert_eq!(probes.len(), 1);
+    assert_eq!(probes[0]["probe"], "wasm:bytecode:call");
+    assert_eq!(probes[0]["sites"], 3);
+    assert_eq!(probes[0]["eliminated"], 0);
+    assert_eq!(probes[0]["dynamic"], 0);
+    // only the function with the calls grew
+    let funcs = stats["funcs"].as_array().unwrap();
+    assert_eq!(funcs.len(), 1);
+    assert_eq!(funcs[0]["app_func_index"], 1);
+    assert!(funcs[0]["size"].as_u64() > funcs[0]["app_size"].as_u64());
+    assert!(stats["injected"]["instrs"].as_u64().unwrap() > 0);
+    assert_eq!(stats["injected"]["instrs"], funcs[0]["injected_instrs"]);
+    assert!(stats["code_size"].as_u64() > stats["app_code_size"].as_u64());
+}
+
+/// The functions with sites matched by the script in `tests/apps/handwritten/funcs.wat`, as listed
+/// by `whamm instr --dry-run` with the extra `args`
+fn dry_run_funcs(script_path: &str, args: &[&str]) -> Vec<u64> {
+    let res = Command::new("target/debug/whamm")
+        .arg("instr")
+        .arg("--script")
+        .arg(script_path)
+        .arg("--app")
+        .arg("tests/apps/handwritten/funcs.wat")
+        .arg("--dry-run")
+        .arg("json")
+        .args(args)
+        .output()
+        .expect("failed to execute process");
+    assert!(res.status.success());
+    let matches: serde_json::Value = serde_json::from_slice(&res.stdout).unwrap();
+    let mut funcs: Vec<u64> = matches
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|site| site["func_index"].as_u64().unwrap())
+        .collect();
+    funcs.dedup();
+    funcs
+}
+
+#[test]
+fn instrument_handwritten_wasm_func_filter() {
+    common::setup_logger();
+    let script_path = "tests/scripts/instr.mm";
+    // functions 0 (`alloc`, exported as `malloc`), 1 (`handle_get`), 2 (`handle_put`) and 4
+    // (`main`) make calls
+    assert_eq!(dry_run_funcs(script_path, &[]), vec![0, 1, 2, 4]);
+    assert_eq!(
+        dry_run_funcs(script_path, &["--only-fn", "handle_*"]),
+        vec![1, 2]
+    );
+    assert_eq!(
+        dry_run_funcs(
+            script_path,
+            &["--skip-fn", "export:malloc", "--skip-fn", "2..4"]
+        ),
+        vec![1]
+    );
+    assert_eq!(
+        dry_run_funcs(
+            script_path,
+            &["--only-fn", "export:*", "--skip-fn", "alloc"]
+        ),
+        vec![4]
+    );
+
+    // the directives of the script add to the flags
+    let script_path = format!("{OUT_BASE_DIR}/func_filter.mm");
+    fs::write(
+        &script_path,
+        r#"
+            @skip_fn("alloc");
+            wasm:bytecode:call:before { }
+            @only_fn("handle_*", "4");
+        "#,
+    )
+    .unwrap();
+    assert_eq!(dry_run_funcs(&script_path, &[]), vec![1, 2, 4]);
+    assert_eq!(
+        dry_run_funcs(&script_path, &["--skip-fn", "handle_get"]),
+        vec![2, 4]
+    );
+}
+
+#[test]
+fn instrument_handwritten_wasm_event_kinds() {
+    common::setup_logger();
+    // events named with more than one word (`local_get`, `br_if`) match their instructions
+    let script_path = format!("{OUT_BASE_DIR}/event_kinds.mm");
+    fs::write(
+        &script_path,
+        r#"
+            wasm:bytecode:local_get:before { }
+            wasm:bytecode:br_if:before { }
+        "#,
+    )
+    .unwrap();
+    let res = Command::new("target/debug/whamm")
+        .arg("instr")
+        .arg("--script")
+        .arg(&script_path)
+        .arg("--app")
+        .arg("tests/apps/handwritten/branch.wat")
+        .arg("--dry-run")
+        .arg("json")
+        .output()
+        .expect("failed to execute process");
+    assert!(res.status.success());
+    let matches: serde_json::Value = serde_json::from_slice(&res.stdout).unwrap();
+    let instrs: Vec<&str> = matches
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|site| site["instr"].as_str().unwrap())
+        .collect();
+    assert_eq!(instrs, vec!["local_get", "br_if", "local_get", "local_get"]);
+}
+
+#[test]
+fn instrument_handwritten_wasm_with_jobs() {
+    common::setup_logger();
+    let app_bytes = wat2wasm(fs::read("tests/apps/handwritten/funcs.wat").unwrap()).unwrap();
+    let instrumented: Vec<Vec<u8>> = [1, 3, 0]
+        .iter()
+        .map(|jobs| {
+            let out_path = format!("{OUT_BASE_DIR}/jobs_{jobs}_{OUT_WASM_NAME}");
+            let res = instrument_with(
+                PRINT_SCRIPT,
+                |table| {
+                    let app_wasm = Module::from_buffer(&app_bytes).unwrap();
+                    let mut emitter = WasmRewritingEmitter::new(app_wasm, table);
+                    emitter.set_jobs(*jobs);
+                    emitter
+                },
+                &out_path,
+            );
+            assert!(matches!(res, Ok(true)));
+            fs::read(&out_path).unwrap()
+        })
+        .collect();
+    // the output doesn't depend on the number of jobs
+    assert!(instrumented.iter().all(|wasm| *wasm == instrumented[0]));
+}
+
+/// The `--stats json` of instrumenting `tests/apps/handwritten/funcs.wat` with the extra `args`
+fn funcs_stats(script_path: &str, args: &[&str]) -> serde_json::Value {
+    app_stats("tests/apps/handwritten/funcs.wat", script_path, args)
+}
+
+fn app_stats(app_path: &str, script_path: &str, args: &[&str]) -> serde_json::Value {
+    let res = Command::new("target/debug/whamm")
+        .arg("instr")
+        .arg("--script")
+        .arg(script_path)
+        .arg("--app")
+        .arg(app_path)
+        .arg("--output-path")
+        .arg(format!("{OUT_BASE_DIR}/stats_{OUT_WASM_NAME}"))
+        .arg("--stats")
+        .arg("json")
+        .args(args)
+        .output()
+        .expect("failed to execute process");
+    assert!(res.status.success());
+    serde_json::from_slice(&res.stdout).unwrap()
+}
+
+#[test]
+fn instrument_handwritten_wasm_outlined_bodies() {
+    common::setup_logger();
+    let script_path = format!("{OUT_BASE_DIR}/outlined.mm");
+    fs::write(
+        &script_path,
+        r#"
+            i32 count;
+            wasm:bytecode:call:before {
+                count = count + 1;
+                print("calling {}", target_fn_type);
+            }
+        "#,
+    )
+    .unwrap();
+    let inlined = funcs_stats(&script_path, &[]);
+    // the body is smaller than the threshold
+    assert_eq!(
+        funcs_stats(&script_path, &["--inline-threshold", "1000"])["code_size"],
+        inlined["code_size"]
+    );
+
+    // every call of the app is a local call, so the 7 sites share a single function
+    let outlined = funcs_stats(&script_path, &["--inline-threshold", "0"]);
+    assert_eq!(
+        outlined["injected"]["funcs"].as_u64().unwrap(),
+        inlined["injected"]["funcs"].as_u64().unwrap() + 1
+    );
+    assert_eq!(outlined["injected"]["instrs"], 7);
+    assert!(outlined["code_size"].as_u64() < inlined["code_size"].as_u64());
+}
+
+#[test]
+fn instrument_handwritten_wasm_arg_order() {
+    common::setup_logger();
+    let app_bytes = wat2wasm(fs::read("tests/apps/handwritten/args.wat").unwrap()).unwrap();
+    let out_path = format!("{OUT_BASE_DIR}/arg_order_{OUT_WASM_NAME}");
+    // `arg0` is the i32, saved last since the i64 is on top of the stack
+    let res = instrument_with(
+        r#"
+            i32 sum;
+            wasm:bytecode:call:before {
+                sum = sum + arg0;
+            }
+        "#,
+        |table| WasmRewritingEmitter::new(Module::from_buffer(&app_bytes).unwrap(), table),
+        &out_path,
+    );
+    assert!(matches!(res, Ok(true)));
+    let instrumented = fs::read(&out_path).unwrap();
+
+    // saving the i64 into a local for an i32 would make the module invalid
+    assert!(validate(&instrumented).is_ok());
+}
+
+#[test]
+fn instrument_handwritten_wasm_local_pool() {
+    common::setup_logger();
+    let app_bytes = wat2wasm(fs::read("tests/apps/handwritten/args.wat").unwrap()).unwrap();
+    let out_path = format!("{OUT_BASE_DIR}/local_pool_{OUT_WASM_NAME}");
+    let res = instrument_with(
+        r#"
+            wasm:bytecode:call:before {
+                i32 next;
+                next = arg0 + 1;
+                print("log {}", next);
+            }
+        "#,
+        |table| {
+            let app_wasm = ModuleConfig::new()
+                .preserve_code_transform(true)
+                .parse(&app_bytes)
+                .unwrap();
+            WasmRewritingEmitter::new(app_wasm, table)
+        },
+        &out_path,
+    );
+    assert!(matches!(res, Ok(true)));
+    let instrumented = fs::read(&out_path).unwrap();
+
+    // the 3 sites share the locals for `arg0` (i32), `arg1` (i64) and `next` (i32)
+    let stats = stats(&[], &app_bytes, &instrumented).unwrap();
+    assert_eq!(stats.funcs.len(), 1);
+    assert_eq!(stats.funcs[0].injected_locals, 3);
+
+    // the code of each site goes right before its call
+    let diff = diff(&app_bytes, &instrumented).unwrap();
+    let instrs = &diff.changed_funcs[0].instrs;
+    let calls: Vec<usize> = instrs
+        .iter()
+        .enumerate()
+        .filter(|(_, instr)| instr.change == Change::Same && instr.text.starts_with("Call"))
+        .map(|(idx, _)| idx)
+        .collect();
+    assert_eq!(calls.len(), 3);
+    assert!(calls
+        .iter()
+        .all(|idx| instrs[idx - 1].change == Change::Injected));
+}
+
+#[test]
+fn instrument_handwritten_wasm_coalesced_counters() {
+    common::setup_logger();
+    let app_path = "tests/apps/handwritten/branch.wat";
+    let script_path = format!("{OUT_BASE_DIR}/coalesced.mm");
+    fs::write(
+        &script_path,
+        r#"
+            i32 count;
+            wasm:bytecode:*:before {
+                count = count + 1;
+            }
+        "#,
+    )
+    .unwrap();
+    // an increment (4 instructions) before each of the 6 instructions of the app
+    let per_site = app_stats(app_path, &script_path, &[]);
+    assert_eq!(per_site["injected"]["instrs"], 24);
+
+    // one increment per basic block: the `block`, what runs up to the `br_if`, the rest of the
+    // block and what follows it
+    let coalesced = app_stats(app_path, &script_path, &["--coalesce-counters"]);
+    assert_eq!(coalesced["injected"]["instrs"], 16);
+    assert!(coalesced["code_size"].as_u64() < per_site["code_size"].as_u64());
+
+    // a predicate reads the count, its increments stay at each site
+    fs::write(
+        &script_path,
+        r#"
+            i32 count;
+            wasm:bytecode:local_get:before {
+                count = count + 1;
+            }
+            wasm:bytecode:br_if:before / count > 5 / {
+                count = count + 2;
+            }
+        "#,
+    )
+    .unwrap();
+    assert_eq!(
+        app_stats(app_path, &script_path, &["--coalesce-counters"])["injected"]["instrs"],
+        app_stats(app_path, &script_path, &[])["injected"]["instrs"]
+    );
+}
+
+#[test]
+fn instrument_handwritten_wasm_constant_globals() {
+    common::setup_logger();
+    let app_path = "tests/apps/handwritten/funcs.wat";
+    let script_path = format!("{OUT_BASE_DIR}/constant_globals.mm");
+    // `DEBUG` is never assigned after its initialization, the probe can't fire
+    fs::write(
+        &script_path,
+        r#"
+            i32 DEBUG = 0;
+            wasm:bytecode:call:before / DEBUG == 1 / {
+                print("calling {}", target_fn_type);
+            }
+        "#,
+    )
+    .unwrap();
+    let stats = app_stats(app_path, &script_path, &[]);
+    assert_eq!(stats["probes"][0]["sites"], 7);
+    assert_eq!(stats["probes"][0]["eliminated"], 7);
+    assert_eq!(stats["injected"]["instrs"], 0);
+
+    // `calls` starts out as 0 and is then assigned 1, it isn't a constant
+    fs::write(
+        &script_path,
+        r#"
+            i32 calls;
+            wasm:bytecode:call:before / calls == 0 / {
+                calls = 1;
+            }
+        "#,
+    )
+    .unwrap();
+    let stats = app_stats(app_path, &script_path, &[]);
+    assert_eq!(stats["probes"][0]["dynamic"], 7);
+}
+
+#[test]
+fn instrument_handwritten_wasm_optimized_tree() {
+    common::setup_logger();
+    let app_path = "tests/apps/handwritten/args.wat";
+    let script_path = format!("{OUT_BASE_DIR}/optimized_tree.mm");
+    // the `after` probe can't fire and the `before` probe doesn't use the arguments of the calls
+    fs::write(
+        &script_path,
+        r#"
+            i32 DEBUG = 0;
+            i32 calls;
+            wasm:bytecode:call:before / calls == 0 / {
+                calls = calls + 2;
+            }
+            wasm:bytecode:call:after / DEBUG == 1 / {
+                calls = 0;
+            }
+        "#,
+    )
+    .unwrap();
+    let stats = app_stats(app_path, &script_path, &["--optimize-tree"]);
+    assert_eq!(stats["probes"].as_array().unwrap().len(), 1);
+    assert_eq!(stats["probes"][0]["mode"], "before");
+    assert_eq!(stats["probes"][0]["dynamic"], 3);
+    assert_eq!(stats["injected"]["locals"], 0);
+
+    // the arguments are saved for the probes using them
+    fs::write(
+        &script_path,
+        r#"
+            wasm:bytecode:call:before {
+                print("log {}", arg0);
+            }
+        "#,
+    )
+    .unwrap();
+    let stats = app_stats(app_path, &script_path, &["--optimize-tree"]);
+    assert_eq!(stats["injected"]["locals"], 2);
+}
+
+#[test]
+fn instrument_handwritten_wasm_serialized_behavior() {
+    common::setup_logger();
+    let app_path = "tests/apps/handwritten/args.wat";
+    let script_path = format!("{OUT_BASE_DIR}/serialized_behavior.mm");
+    fs::write(
+        &script_path,
+        r#"
+            i32 count;
+            wasm:bytecode:call:before {
+                count = count + arg0;
+            }
+            wasm:bytecode:call:after {}
+        "#,
+    )
+    .unwrap();
+    for args in [vec![], vec!["--optimize-tree"]] {
+        let vis_path = format!("{OUT_BASE_DIR}/serialized_behavior.svg");
+        let res = Command::new("target/debug/whamm")
+            .arg("vis-script")
+            .arg("--script")
+            .arg(&script_path)
+            .arg("--output-path")
+            .arg(&vis_path)
+            .arg("--format")
+            .arg("json")
+            .args(&args)
+            .output()
+            .expect("failed to execute process");
+        assert!(res.status.success());
+
+        // emitting the serialized tree is the same as emitting the one built from the script
+        let json_path = format!("{OUT_BASE_DIR}/serialized_behavior.json");
+        let loaded = app_stats(app_path, &script_path, &["--behavior", &json_path]);
+        assert_eq!(loaded, app_stats(app_path, &script_path, &args));
+    }
+}